@@ -0,0 +1,3 @@
+alter table clips add column api_key blob references api_keys (api_key) on delete set null;
+
+create index if not exists clips_api_key on clips (api_key);
@@ -0,0 +1,15 @@
+create table if not exists users
+(
+    user_id       text primary key not null,
+    username      text unique not null,
+    password_hash text not null,
+    created       datetime not null
+);
+
+alter table clips add column user_id text references users (user_id) on delete set null;
+
+alter table api_keys add column user_id text references users (user_id) on delete cascade;
+
+create index if not exists clips_user_id on clips (user_id);
+
+create index if not exists api_keys_user_id on api_keys (user_id);
@@ -0,0 +1,7 @@
+create table if not exists user_identities
+(
+    issuer  text not null,
+    subject text not null,
+    user_id text not null references users (user_id) on delete cascade,
+    primary key (issuer, subject)
+);
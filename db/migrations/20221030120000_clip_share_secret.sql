@@ -0,0 +1,2 @@
+-- key share links of a clip are signed with, generated when the first link is handed out
+alter table clips add column share_secret blob;
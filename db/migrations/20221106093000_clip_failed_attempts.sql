@@ -0,0 +1,3 @@
+-- wrong passwords entered for a clip, which is deleted once burn_after is reached
+alter table clips add column failed_attempts integer not null default 0;
+alter table clips add column burn_after integer;
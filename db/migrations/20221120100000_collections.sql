@@ -0,0 +1,19 @@
+-- clips grouped under a shortcode of their own
+create table if not exists collections
+(
+    collection_id text primary key not null,
+    shortcode     text unique not null,
+    title         text,
+    password      text,
+    posted        datetime not null,
+    api_key       blob references api_keys (api_key) on delete set null,
+    user_id       text references users (user_id) on delete set null
+);
+
+create table if not exists collection_clips
+(
+    collection_id text not null references collections (collection_id) on delete cascade,
+    clip_id       text not null references clips (clip_id) on delete cascade,
+    added         datetime not null,
+    primary key (collection_id, clip_id)
+);
@@ -0,0 +1,10 @@
+-- views of a clip per hour, referring host and kind of client; days are summed up from hours
+create table if not exists clip_views
+(
+    clip_id  text not null references clips (clip_id) on delete cascade,
+    bucket   integer not null,
+    referrer text not null default '',
+    agent    text not null,
+    views    integer not null,
+    primary key (clip_id, bucket, referrer, agent)
+);
@@ -0,0 +1,11 @@
+-- distinct visitors of a clip: a visitor is a keyed hash of IP address and user agent,
+-- the key changes every day so only rows of the current day are needed to tell visitors apart
+alter table clips add column visitors integer not null default 0;
+
+create table if not exists clip_visitors
+(
+    clip_id text not null references clips (clip_id) on delete cascade,
+    day     integer not null,
+    visitor text not null,
+    primary key (clip_id, day, visitor)
+);
@@ -0,0 +1,18 @@
+-- a grant names exactly one user or API key, at most once per clip.
+-- sqlite can't add constraints to an existing table: rebuild it, keeping valid grants only
+create table clip_grants_checked
+(
+    clip_id text not null references clips (clip_id) on delete cascade,
+    user_id text references users (user_id) on delete cascade,
+    api_key blob references api_keys (api_key) on delete cascade,
+    check ((user_id is null) <> (api_key is null))
+);
+
+insert into clip_grants_checked (clip_id, user_id, api_key)
+select distinct clip_id, user_id, api_key from clip_grants where (user_id is null) <> (api_key is null);
+
+drop table clip_grants;
+alter table clip_grants_checked rename to clip_grants;
+
+create unique index if not exists clip_grants_user on clip_grants (clip_id, user_id) where user_id is not null;
+create unique index if not exists clip_grants_api_key on clip_grants (clip_id, api_key) where api_key is not null;
@@ -0,0 +1,9 @@
+-- login sessions, the session cookie only carries the id: logging out deletes the row
+create table if not exists sessions
+(
+    session_id text primary key not null,
+    user_id    text not null references users (user_id) on delete cascade,
+    expires    integer not null
+);
+
+create index if not exists sessions_expires on sessions (expires);
@@ -0,0 +1,11 @@
+create table if not exists clips
+(
+    clip_id   text primary key not null,
+    shortcode text unique not null,
+    content   text not null,
+    title     text,
+    posted    timestamp not null,
+    expires   timestamp,
+    password  text,
+    hits      bigint not null
+);
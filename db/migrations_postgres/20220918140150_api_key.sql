@@ -0,0 +1,3 @@
+create table if not exists api_keys (
+    api_key bytea primary key
+);
@@ -0,0 +1,11 @@
+alter table clips add column visibility text not null default 'unlisted';
+
+-- users and API keys a 'shared-with' clip is shared with
+create table if not exists clip_grants
+(
+    clip_id text not null references clips (clip_id) on delete cascade,
+    user_id text references users (user_id) on delete cascade,
+    api_key bytea references api_keys (api_key) on delete cascade
+);
+
+create index if not exists clip_grants_clip_id on clip_grants (clip_id);
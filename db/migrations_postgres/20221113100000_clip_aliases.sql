@@ -0,0 +1,9 @@
+-- alternate shortcodes of a clip, including the ones it had before being renamed
+create table if not exists aliases
+(
+    shortcode text primary key not null,
+    clip_id   text not null references clips (clip_id) on delete cascade,
+    created   timestamp not null
+);
+
+create index if not exists aliases_clip_id on aliases (clip_id);
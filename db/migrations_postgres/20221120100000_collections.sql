@@ -0,0 +1,21 @@
+-- clips grouped under a shortcode of their own
+create table if not exists collections
+(
+    collection_id text primary key not null,
+    shortcode     text unique not null,
+    title         text,
+    password      text,
+    posted        timestamp not null,
+    api_key       bytea references api_keys (api_key) on delete set null,
+    user_id       text references users (user_id) on delete set null
+);
+
+-- seq keeps clips added within the same second in order, sqlite uses its rowid
+create table if not exists collection_clips
+(
+    collection_id text not null references collections (collection_id) on delete cascade,
+    clip_id       text not null references clips (clip_id) on delete cascade,
+    added         timestamp not null,
+    seq           bigserial not null,
+    primary key (collection_id, clip_id)
+);
@@ -0,0 +1,9 @@
+-- normalized labels of a clip, used to filter listings
+create table if not exists clip_tags
+(
+    clip_id text not null references clips (clip_id) on delete cascade,
+    tag     text not null,
+    primary key (clip_id, tag)
+);
+
+create index if not exists clip_tags_tag on clip_tags (tag);
@@ -0,0 +1,13 @@
+-- a grant names exactly one user or API key, at most once per clip
+delete from clip_grants where (user_id is null) = (api_key is null);
+delete from clip_grants duplicate using clip_grants kept
+where duplicate.ctid > kept.ctid
+  and duplicate.clip_id = kept.clip_id
+  and duplicate.user_id is not distinct from kept.user_id
+  and duplicate.api_key is not distinct from kept.api_key;
+
+alter table clip_grants add constraint clip_grants_one_grantee check ((user_id is null) <> (api_key is null));
+
+drop index if exists clip_grants_clip_id;
+create unique index if not exists clip_grants_user on clip_grants (clip_id, user_id) where user_id is not null;
+create unique index if not exists clip_grants_api_key on clip_grants (clip_id, api_key) where api_key is not null;
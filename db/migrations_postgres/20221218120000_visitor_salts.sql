@@ -0,0 +1,10 @@
+-- key visitors of a day are hashed with, shared by all replicas and kept across restarts.
+-- rows of past days are deleted so that their hashes can't be recomputed
+create table if not exists visitor_salts
+(
+    day  bigint  primary key not null,
+    salt bytea   not null
+);
+
+-- hourly view stats are deleted once they are older than what the stats page shows
+create index if not exists clip_views_bucket on clip_views (bucket);
@@ -0,0 +1,33 @@
+#!/bin/sh
+# runs the tests against PostgreSQL in a throwaway container, skipped when docker is not available
+set -e
+cd "$(dirname "$0")/.."
+
+if ! docker info >/dev/null 2>&1; then
+    echo "docker is not available, skipping the postgres tests"
+    exit 0
+fi
+
+container=clipstash-test-postgres
+port=${POSTGRES_PORT:-55432}
+docker run --rm -d --name $container -e POSTGRES_HOST_AUTH_METHOD=trust -p "$port":5432 postgres:15 >/dev/null
+trap 'docker stop $container >/dev/null' EXIT
+# over TCP: the server started during initialization only listens on the socket
+until docker exec $container pg_isready -q -h 127.0.0.1 -U postgres; do sleep 1; done
+
+# the schema queries are checked against when compiling, every test migrates a database of its own
+{
+    cat <<'SQL'
+create table _sqlx_migrations (
+    version bigint primary key,
+    description text not null,
+    installed_on timestamptz not null default now(),
+    success boolean not null,
+    checksum bytea not null,
+    execution_time bigint not null
+);
+SQL
+    cat db/migrations_postgres/*.sql
+} | docker exec -i $container psql -q -v ON_ERROR_STOP=1 -U postgres
+
+DATABASE_URL="postgres://postgres@localhost:$port/postgres" cargo test --no-default-features --features postgres "$@"
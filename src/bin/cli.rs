@@ -1,6 +1,6 @@
-use clipstash::domain::clip::field::{Content, Expires, Password, ShortCode, Title};
+use clipstash::domain::clip::field::{BurnAfter, Content, Expires, Password, SharedWith, ShortCode, Tags, Title, Visibility};
 use clipstash::service::ask::{GetClip, NewClip, UpdateClip};
-use clipstash::web::api::{ApiKey, API_KEY_HEADER};
+use clipstash::web::api::{ApiKey, API_KEY_HEADER, CLIP_PASSWORD_HEADER};
 use clipstash::Clip;
 use std::error::Error;
 use structopt::StructOpt;
@@ -25,6 +25,24 @@ enum Command {
 
         #[structopt(short, long, help = "title")]
         title: Option<Title>,
+
+        #[structopt(short, long, help = "public, unlisted, private or shared-with")]
+        visibility: Option<Visibility>,
+
+        #[structopt(short, long, help = "comma separated usernames or API keys")]
+        shared_with: Option<SharedWith>,
+
+        #[structopt(short, long, help = "delete the clip after this many wrong passwords")]
+        burn_after: Option<BurnAfter>,
+
+        #[structopt(short, long, help = "generate a passphrase instead of using --password")]
+        generate_password: bool,
+
+        #[structopt(long, parse(try_from_str = ShortCode::custom), help = "custom shortcode, e.g. deploy-notes")]
+        shortcode: Option<ShortCode>,
+
+        #[structopt(long, help = "comma separated tags")]
+        tags: Option<Tags>,
     },
     Update {
         shortcode: ShortCode,
@@ -38,6 +56,22 @@ enum Command {
 
         #[structopt(short, long, help = "title")]
         title: Option<Title>,
+
+        #[structopt(short, long, help = "public, unlisted, private or shared-with")]
+        visibility: Option<Visibility>,
+
+        #[structopt(short, long, help = "comma separated usernames or API keys")]
+        shared_with: Option<SharedWith>,
+
+        #[structopt(short, long, help = "delete the clip after this many wrong passwords")]
+        burn_after: Option<BurnAfter>,
+
+        #[structopt(long, help = "comma separated tags, replacing the current ones")]
+        tags: Option<Tags>,
+    },
+    List {
+        #[structopt(long, help = "only list clips with this tag")]
+        tag: Option<String>,
     },
 }
 
@@ -59,12 +93,9 @@ fn get_clip(base_url: &str, ask_svc: GetClip, api_key: ApiKey) -> Result<Clip, B
     let url = format!("{}/api/clip/{}", base_url, ask_svc.shortcode.into_inner());
 
     let mut request = client.get(url);
-    request = match ask_svc.password.into_inner() {
-        Some(pwd) => request.header(
-            reqwest::header::COOKIE,
-            format!("password={}", pwd)),
-        None => request
-    };
+    if let Some(password) = ask_svc.password.into_inner() {
+        request = request.header(CLIP_PASSWORD_HEADER, password);
+    }
     request = request.header(API_KEY_HEADER, api_key.to_base64());
 
     Ok(request.send()?.json()?)
@@ -90,6 +121,19 @@ fn update_clip(base_url: &str, ask_svc: UpdateClip, api_key: ApiKey) -> Result<C
     Ok(request.json(&ask_svc).send()?.json()?)
 }
 
+fn list_clips(base_url: &str, tag: Option<String>, api_key: ApiKey) -> Result<Vec<Clip>, Box<dyn Error>> {
+    let client = reqwest::blocking::Client::builder().build()?;
+    let url = format!("{}/api/clip", base_url);
+
+    let mut request = client.get(url);
+    if let Some(tag) = tag {
+        request = request.query(&[("tag", tag)]);
+    }
+    request = request.header(API_KEY_HEADER, api_key.to_base64());
+
+    Ok(request.send()?.json()?)
+}
+
 fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
     match opt.command {
         Command::Get { shortcode, password } => {
@@ -98,6 +142,8 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
                 GetClip {
                     password: Password::new(password.unwrap_or_default())?,
                     shortcode,
+                    share_link: None,
+                    unlock_token: None,
                 },
                 opt.api_key
             )?;
@@ -105,7 +151,7 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
 
             Ok(())
         }
-        Command::New { clip, password, expires, title } => {
+        Command::New { clip, password, expires, title, visibility, shared_with, burn_after, generate_password, shortcode, tags } => {
             let clip = new_clip(
                 opt.addr.as_str(),
                 NewClip {
@@ -113,6 +159,12 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
                     title: title.unwrap_or_default(),
                     expires: expires.unwrap_or_default(),
                     password: password.unwrap_or_default(),
+                    visibility: visibility.unwrap_or_default(),
+                    shared_with: shared_with.unwrap_or_default(),
+                    burn_after: burn_after.unwrap_or_default(),
+                    generate_password,
+                    shortcode,
+                    tags: tags.unwrap_or_default(),
                 },
                 opt.api_key
             )?;
@@ -121,7 +173,7 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
 
             Ok(())
         }
-        Command::Update { clip, password, expires, title, shortcode } => {
+        Command::Update { clip, password, expires, title, shortcode, visibility, shared_with, burn_after, tags } => {
             let password = password.unwrap_or_default();
 
             let original_clip = get_clip(
@@ -129,6 +181,8 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
                 GetClip {
                     password: password.clone(),
                     shortcode: shortcode.clone(),
+                    share_link: None,
+                    unlock_token: None,
                 },
                 opt.api_key.clone()
             )?;
@@ -137,6 +191,10 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
                 content: Content::new(clip.as_str())?,
                 expires: expires.unwrap_or(original_clip.expires),
                 title: title.unwrap_or(original_clip.title),
+                visibility: visibility.unwrap_or(original_clip.visibility),
+                shared_with,
+                burn_after: burn_after.unwrap_or(original_clip.burn_after),
+                tags,
                 password,
                 shortcode,
             };
@@ -144,6 +202,12 @@ fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
             let clip = update_clip(opt.addr.as_str(), ask_svc, opt.api_key)?;
             println!("{:#?}", clip);
 
+            Ok(())
+        }
+        Command::List { tag } => {
+            let clips = list_clips(opt.addr.as_str(), tag, opt.api_key)?;
+            println!("{:#?}", clips);
+
             Ok(())
         }
     }
@@ -2,33 +2,143 @@ use std::path::PathBuf;
 use clipstash::data::Db;
 use clipstash::web::{renderer::Renderer};
 use dotenv::dotenv;
-use rocket::{Ignite, Rocket};
 use structopt::StructOpt;
+use clipstash::domain::lockout::Lockout;
 use clipstash::domain::maintenance::Maintenance;
+use clipstash::domain::clip::field::{PasswordPolicy, ShortCodeGenerator, ShortCodeStyle};
+use clipstash::domain::quota::Quota;
+use clipstash::web::oidc::{self, OidcConfig};
 use clipstash::web::hit_counter::HitCounter;
+use clipstash::web::{Metrics, SecureCookies};
+use clipstash::web::logging::{self, LogFormat};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "httpd")]
 struct Opt {
-    #[structopt(default_value = "sqlite:db/data.db")]
+    #[structopt(default_value = "sqlite:db/data.db", help = "sqlite:<path>, or postgres://... with the `postgres` feature")]
     db_uri: String,
     #[structopt(short, long, parse(from_os_str), default_value = "templates/")]
     template_dir: PathBuf,
+    #[structopt(long, env = "MAX_CLIPS_PER_KEY", help = "live clips allowed per API key")]
+    max_clips_per_key: Option<u64>,
+    #[structopt(long, env = "MAX_BYTES_PER_KEY", help = "bytes of content allowed per API key")]
+    max_bytes_per_key: Option<u64>,
+    #[structopt(long, env = "PASSWORD_MIN_LENGTH", default_value = "8", help = "shortest clip password accepted")]
+    password_min_length: usize,
+    #[structopt(long, env = "PASSWORD_MIN_ENTROPY", default_value = "30", help = "estimated bits a clip password needs")]
+    password_min_entropy: f64,
+    #[structopt(long, help = "accept well known passwords such as 123456 for clips")]
+    allow_common_passwords: bool,
+    #[structopt(long, env = "SHORTCODE_STYLE", default_value = "base58", help = "base62, base58 or words")]
+    shortcode_style: ShortCodeStyle,
+    #[structopt(long, env = "SHORTCODE_LENGTH", help = "characters, or words, per generated shortcode")]
+    shortcode_length: Option<usize>,
+    #[structopt(long, env = "OIDC_ISSUER", help = "OpenID Connect issuer URL, enables single sign-on")]
+    oidc_issuer: Option<String>,
+    #[structopt(long, env = "OIDC_CLIENT_ID")]
+    oidc_client_id: Option<String>,
+    #[structopt(long, env = "OIDC_CLIENT_SECRET", hide_env_values = true)]
+    oidc_client_secret: Option<String>,
+    #[structopt(long, env = "OIDC_REDIRECT_URL", help = "public URL of /login/oidc/callback")]
+    oidc_redirect_url: Option<String>,
+    #[structopt(long, env = "OIDC_EMAIL_DOMAIN", help = "only allow users with an email in this domain")]
+    oidc_email_domain: Option<String>,
+    #[structopt(long, env = "OIDC_ONLY", help = "disable password registration and login, users log in through the issuer")]
+    oidc_only: bool,
+    #[structopt(long, env = "HTTPS", help = "clients reach the server over https, e.g. through a TLS proxy: cookies are marked secure")]
+    https: bool,
+    #[structopt(long, env = "LOG_LEVEL", default_value = "info,sqlx=warn,rocket::server=warn,_=error", help = "log filter, e.g. info or clipstash=debug,sqlx=info")]
+    log_level: String,
+    #[structopt(long, env = "LOG_FORMAT", default_value = "text", help = "text or json")]
+    log_format: LogFormat,
+    #[structopt(long, env = "SKIP_MIGRATIONS", help = "do not apply pending migrations at startup")]
+    skip_migrations: bool,
 }
 
 fn main() {
     dotenv().ok();
     let opt = Opt::from_args();
+    logging::init(&opt.log_level, opt.log_format);
+    // rocket logs through the subscriber too: escape codes would end up in files and JSON
+    if std::env::var_os("ROCKET_CLI_COLORS").is_none() {
+        std::env::set_var("ROCKET_CLI_COLORS", "false");
+    }
+
+    let shortcodes = ShortCodeGenerator::new(opt.shortcode_style, opt.shortcode_length)
+        .unwrap_or_else(|e| panic!("--shortcode-length: {}", e));
+    if shortcodes.bits() < 48.0 {
+        tracing::warn!(bits = shortcodes.bits(), "generated shortcodes are short, unprotected clips may be guessed");
+    }
 
     let rt = tokio::runtime::Runtime::new().expect("failed to spaw tokio runtime");
 
     let handle = rt.handle().clone();
     let renderer = Renderer::new(opt.template_dir.clone());
-    let db: Db = rt.block_on(async move { Db::new(&opt.db_uri).await });
-    let hit_counter = HitCounter::new(db.get_pool().clone(), handle.clone());
-    let maintenance = Maintenance::spawn(db.get_pool().clone(), handle.clone());
+    let db: Db = rt.block_on(async {
+        let db = Db::new(&opt.db_uri).await;
+        let expected = clipstash::data::expected_schema_version();
+        if opt.skip_migrations {
+            match clipstash::service::action::schema_version(db.get_pool()).await {
+                Ok(version) if version == expected => tracing::info!(?version, "schema is up to date"),
+                Ok(version) => tracing::warn!(?version, ?expected, "schema is not at the expected version, migrations were skipped"),
+                Err(e) => tracing::warn!(error = ?e, "schema version unknown, migrations were skipped"),
+            }
+        } else {
+            match db.migrate().await {
+                Ok(version) => tracing::info!(?version, "schema is up to date"),
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to apply migrations");
+                    panic!("migration error")
+                }
+            }
+        }
+        db
+    });
+    let metrics = Metrics::default();
+    let hit_counter = HitCounter::new(db.get_pool().clone(), handle.clone(), metrics.clone());
+    let lockout = Lockout::default();
+    let maintenance = Maintenance::spawn(db.get_pool().clone(), handle.clone(), metrics.clone(), lockout.clone());
+
+    let quota = Quota { max_clips: opt.max_clips_per_key, max_bytes: opt.max_bytes_per_key };
+    let password_policy = PasswordPolicy {
+        min_length: opt.password_min_length,
+        min_entropy: opt.password_min_entropy,
+        deny_common: !opt.allow_common_passwords,
+    };
+    // the callback is on this server: its public URL tells how clients reach it
+    let https = opt.https || opt.oidc_redirect_url.as_deref().is_some_and(|url| url.starts_with("https://"));
+    let oidc = match (opt.oidc_issuer, opt.oidc_client_id, opt.oidc_redirect_url) {
+        (Some(issuer), Some(client_id), Some(redirect_url)) => {
+            if let Err(e) = oidc::check_secure(&issuer) {
+                panic!("--oidc-issuer: {}", e);
+            }
+            Some(OidcConfig {
+                issuer,
+                client_id,
+                client_secret: opt.oidc_client_secret,
+                redirect_url,
+                email_domain: opt.oidc_email_domain,
+                password_login: !opt.oidc_only,
+            })
+        }
+        (None, _, _) if opt.oidc_only => panic!("--oidc-only requires --oidc-issuer"),
+        (None, _, _) => None,
+        _ => panic!("--oidc-issuer requires --oidc-client-id and --oidc-redirect-url"),
+    };
 
-    let config = clipstash::RocketConfig { renderer, db, hit_counter, maintenance };
+    let config = clipstash::RocketConfig {
+        renderer,
+        db,
+        hit_counter,
+        maintenance,
+        lockout,
+        quota,
+        password_policy,
+        shortcodes,
+        oidc,
+        secure_cookies: SecureCookies(https),
+        metrics,
+    };
 
 
     rt.block_on(async move {
@@ -1,49 +1,117 @@
 pub mod model;
 pub mod query;
+pub mod repository;
 
 use serde::{Deserialize, Serialize};
 use derive_more::{Display, From};
 use uuid::Uuid;
 use std::str::FromStr;
-use sqlx::{Sqlite};
+
+#[cfg(all(feature = "sqlite", feature = "postgres"))]
+compile_error!("the `sqlite` and `postgres` features are exclusive, use `--no-default-features --features postgres`");
+#[cfg(not(any(feature = "sqlite", feature = "postgres")))]
+compile_error!("enable one database backend: the `sqlite` or the `postgres` feature");
 
 #[derive(Debug, thiserror::Error)]
 pub enum DataError {
     #[error("database error: {0}")]
-    Database(#[from] sqlx::Error)
+    Database(#[from] sqlx::Error),
+    #[error("shortcode '{0}' is already taken")]
+    ShortCodeTaken(String),
+    #[error("username '{0}' is already taken")]
+    UsernameTaken(String),
+    #[error("no free shortcode found in {0} attempts")]
+    ShortCodesExhausted(usize),
+    #[error("migration error: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    #[error("{0}")]
+    Quota(#[from] crate::domain::quota::QuotaError),
 }
 
+// the database this build talks to, chosen at compile time since queries are checked against it
+#[cfg(feature = "sqlite")]
+pub type Backend = sqlx::Sqlite;
+#[cfg(feature = "postgres")]
+pub type Backend = sqlx::Postgres;
+
+#[cfg(feature = "sqlite")]
+pub const BACKEND_NAME: &str = "sqlite";
+#[cfg(feature = "postgres")]
+pub const BACKEND_NAME: &str = "postgres";
+
 // encapsulating in custom type
 // so that we have better control
 // and that changes to make are collocated here
 // in case there is a crate update or we decide to change DB type
-pub type Db = Database<Sqlite>;
-pub type DbPool = sqlx::sqlite::SqlitePool;
+pub type Db = Database<Backend>;
+pub type DbPool = sqlx::Pool<Backend>;
 // pool of connections. Reuse already open connections for better perf
-pub type Tx<'t> = sqlx::Transaction<'t, Sqlite>;
+pub type Tx<'t> = sqlx::Transaction<'t, Backend>;
 // to allow rolling back
-pub type DbRow = sqlx::sqlite::SqliteRow;
-pub type QueryResult = sqlx::sqlite::SqliteQueryResult;
+pub type DbRow = <Backend as sqlx::Database>::Row;
+pub type QueryResult = <Backend as sqlx::Database>::QueryResult;
+
+// migrations of the backend, compiled into the binary
+#[cfg(feature = "sqlite")]
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./db/migrations");
+#[cfg(feature = "postgres")]
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./db/migrations_postgres");
+
+// version of the newest migration this build knows of
+pub fn expected_schema_version() -> Option<i64> {
+    MIGRATOR.iter().map(|migration| migration.version).max()
+}
+
+// backend a database URL is meant for, by its scheme
+pub fn url_backend(db_url: &str) -> Option<&'static str> {
+    match db_url.split_once(':').map(|(scheme, _)| scheme) {
+        Some("sqlite") => Some("sqlite"),
+        Some("postgres" | "postgresql") => Some("postgres"),
+        _ => None,
+    }
+}
 
 pub struct Database<D: sqlx::Database>(sqlx::Pool<D>);
 
-impl Database<Sqlite> {
+impl Db {
     pub async fn new(db_url: &str) -> Self {
-        let pool = sqlx::sqlite::SqlitePoolOptions::new().connect(db_url).await;
-        match pool {
+        match url_backend(db_url) {
+            Some(BACKEND_NAME) => (),
+            Some(backend) => panic!("this build only supports {} databases, build with the `{}` feature", BACKEND_NAME, backend),
+            None => panic!("unsupported database URL, expected sqlite:<path> or postgres://..."),
+        }
+        match Self::connect(db_url).await {
             Ok(pool) => Self(pool),
             Err(e) => {
-                eprintln!("{:?}\n", e);
-                eprintln!("If the database has not yet been created, run:\n$sqlx database setup");
+                // not the url, it may hold credentials
+                tracing::error!(error = ?e, "database connection failed");
                 panic!("database connection error")
             }
         }
     }
 
+    // creates the database file if it does not exist yet
+    #[cfg(feature = "sqlite")]
+    async fn connect(db_url: &str) -> Result<DbPool, sqlx::Error> {
+        let options = sqlx::sqlite::SqliteConnectOptions::from_str(db_url)?.create_if_missing(true);
+        sqlx::sqlite::SqlitePoolOptions::new().connect_with(options).await
+    }
+
+    #[cfg(feature = "postgres")]
+    async fn connect(db_url: &str) -> Result<DbPool, sqlx::Error> {
+        sqlx::postgres::PgPoolOptions::new().connect(db_url).await
+    }
+
+    // applies pending migrations, returning the schema version
+    pub async fn migrate(&self) -> Result<Option<i64>, DataError> {
+        MIGRATOR.run(&self.0).await?;
+        query::schema_version(&self.0).await
+    }
+
     pub fn get_pool(&self) -> &DbPool { &self.0 }
 }
 
-#[derive(Clone, Debug, Display, From, Deserialize, Serialize)]
+#[derive(Clone, Debug, Display, From, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct DbId(Uuid);
 
 impl DbId {
@@ -77,21 +145,91 @@ impl From<DbId> for String {
 
 #[cfg(test)]
 pub mod test {
-    use rocket::http::uri::fmt::Kind::Path;
-    use sqlx::migrate::Migrator;
     use crate::data::*;
     use tokio::runtime::Handle;
 
     // create in memory DB
+    #[cfg(feature = "sqlite")]
     pub fn new_db(handle: &Handle) -> Db {
-        use sqlx::migrate::Migrator;
-        use std::path::Path;
         handle.block_on(async move {
-            let db = Db::new(":memory:").await;
-            let migrator = Migrator::new(Path::new("./db/migrations")).await.unwrap();
-            let pool = db.get_pool();
-            migrator.run(pool).await.unwrap();
+            let db = Db::new("sqlite::memory:").await;
+            db.migrate().await.unwrap();
             db
         })
     }
-}
\ No newline at end of file
+
+    // create a database of its own for every test on the server at TEST_DATABASE_URL,
+    // or DATABASE_URL. meant for a throwaway server: test databases are not dropped
+    #[cfg(feature = "postgres")]
+    pub fn new_db(handle: &Handle) -> Db {
+        use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+        use sqlx::Executor;
+
+        let url = std::env::var("TEST_DATABASE_URL")
+            .or_else(|_| std::env::var("DATABASE_URL"))
+            .expect("TEST_DATABASE_URL or DATABASE_URL points to a postgres server");
+        handle.block_on(async move {
+            let name = format!("clipstash_test_{}", Uuid::new_v4().to_simple());
+            let server = PgPoolOptions::new().max_connections(1).connect(&url).await.unwrap();
+            server.execute(format!("CREATE DATABASE {}", name).as_str()).await.unwrap();
+            server.close().await;
+
+            let options = PgConnectOptions::from_str(&url).unwrap().database(&name);
+            let db = Database(PgPoolOptions::new().connect_with(options).await.unwrap());
+            db.migrate().await.unwrap();
+            db
+        })
+    }
+
+    // create an anonymous clip with default settings
+    pub async fn new_clip(pool: &DbPool) -> crate::Clip {
+        new_clip_with(pool, |_| ()).await
+    }
+
+    // create an anonymous clip, changing the default request first
+    pub async fn new_clip_with<F: FnOnce(&mut crate::service::ask::NewClip)>(pool: &DbPool, configure: F) -> crate::Clip {
+        use crate::service::{action, ask};
+        let mut req = ask::NewClip {
+            content: crate::domain::clip::field::Content::new("content").unwrap(),
+            title: Default::default(),
+            expires: Default::default(),
+            password: Default::default(),
+            visibility: Default::default(),
+            shared_with: Default::default(),
+            burn_after: Default::default(),
+            generate_password: false,
+            shortcode: None,
+            tags: Default::default(),
+        };
+        configure(&mut req);
+        action::new_clip(req, ask::Identity::Anonymous, &Default::default(), &Default::default(), &Default::default(), pool)
+            .await
+            .unwrap()
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn creates_and_migrates_missing_database() {
+        let rt = crate::test::async_runtime();
+        let path = std::env::temp_dir().join(format!("clipstash-{}.db", DbId::new()));
+        let url = format!("sqlite:{}", path.display());
+        rt.block_on(async {
+            let db = Db::new(&url).await;
+            assert_eq!(db.migrate().await.unwrap(), expected_schema_version());
+            // nothing left to apply the second time
+            assert_eq!(db.migrate().await.unwrap(), expected_schema_version());
+            db.get_pool().close().await;
+        });
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tells_backends_apart_by_scheme() {
+        assert_eq!(url_backend("sqlite:db/data.db"), Some("sqlite"));
+        assert_eq!(url_backend("sqlite::memory:"), Some("sqlite"));
+        assert_eq!(url_backend("postgres://clipstash@db/clipstash"), Some("postgres"));
+        assert_eq!(url_backend("postgresql://db/clipstash"), Some("postgres"));
+        assert_eq!(url_backend("mysql://db/clipstash"), None);
+        assert_eq!(url_backend("data.db"), None);
+    }
+}
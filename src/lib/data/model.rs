@@ -1,10 +1,17 @@
 use crate::data::DbId;
+use crate::domain::quota::Usage as DomainUsage;
+use crate::domain::share::ShareSecret;
+use crate::domain::stats::{Agent, View};
+use crate::domain::user::field::{UserId, Username};
+use crate::domain::user::UserError;
+use crate::service::ask::Identity;
+use crate::web::api::ApiKey;
 use crate::{ClipError, ShortCode, Time};
 use chrono::{NaiveDateTime, Utc};
 use std::convert::TryFrom;
 
 // DB friendly types
-#[derive(Debug, sqlx::FromRow)]
+#[derive(Clone, Debug, sqlx::FromRow)]
 pub struct Clip {
     pub(in crate::data) clip_id: String,
     pub(in crate::data) shortcode: String,
@@ -14,6 +21,44 @@ pub struct Clip {
     pub(in crate::data) expires: Option<NaiveDateTime>,
     pub(in crate::data) password: Option<String>,
     pub(in crate::data) hits: i64,
+    pub(in crate::data) api_key: Option<Vec<u8>>,
+    pub(in crate::data) user_id: Option<String>,
+    pub(in crate::data) visibility: String,
+    pub(in crate::data) share_secret: Option<Vec<u8>>,
+    pub(in crate::data) failed_attempts: i64,
+    pub(in crate::data) burn_after: Option<i64>,
+    pub(in crate::data) visitors: i64,
+    // comma separated, aggregated from clip_tags
+    pub(in crate::data) tags: Option<String>,
+}
+
+impl Clip {
+    // wrong passwords entered so far
+    pub fn failed_attempts(&self) -> u64 {
+        u64::try_from(self.failed_attempts).unwrap_or_default()
+    }
+
+    // None until the first share link is handed out
+    pub fn share_secret(&self) -> Option<ShareSecret> {
+        self.share_secret.clone().map(ShareSecret::from)
+    }
+
+    // API key or user the clip was created by
+    pub fn owner(&self) -> Identity {
+        owner(&self.api_key, &self.user_id)
+    }
+}
+
+fn owner(api_key: &Option<Vec<u8>>, user_id: &Option<String>) -> Identity {
+    use std::str::FromStr;
+
+    match (api_key, user_id) {
+        (Some(api_key), _) => Identity::ApiKey(ApiKey::from(api_key.clone())),
+        (None, Some(user_id)) => UserId::from_str(user_id.as_str())
+            .map(Identity::User)
+            .unwrap_or(Identity::Anonymous),
+        (None, None) => Identity::Anonymous
+    }
 }
 
 impl TryFrom<Clip> for crate::domain::clip::Clip {
@@ -32,6 +77,11 @@ impl TryFrom<Clip> for crate::domain::clip::Clip {
             expires: Expires::new(clip.expires.map(Time::from_naive_utc)),
             password: Password::new(clip.password.unwrap_or_default())?,
             hits: Hits::new(u64::try_from(clip.hits)?),
+            visitors: Hits::new(u64::try_from(clip.visitors)?),
+            visibility: Visibility::from_str(clip.visibility.as_str())
+                .map_err(|e| ClipError::InvalidVisibility(e.to_string()))?,
+            burn_after: BurnAfter::new(clip.burn_after.map(u32::try_from).transpose()?)?,
+            tags: Tags::from_str(clip.tags.unwrap_or_default().as_str())?,
         })
     }
 }
@@ -67,28 +117,59 @@ impl From<String> for GetClip {
 
 pub struct NewClip {
     pub(in crate::data) clip_id: String,
-    pub(in crate::data) shortcode: String,
+    // generated when inserting unless a specific shortcode is requested
+    pub(in crate::data) shortcode: Option<String>,
     pub(in crate::data) content: String,
     pub(in crate::data) title: Option<String>,
     pub(in crate::data) posted: i64,
     pub(in crate::data) expires: Option<i64>,
     pub(in crate::data) password: Option<String>,
+    pub(in crate::data) api_key: Option<Vec<u8>>,
+    pub(in crate::data) user_id: Option<String>,
+    pub(in crate::data) visibility: String,
+    pub(in crate::data) burn_after: Option<i64>,
+    pub(in crate::data) grants: Vec<Grant>,
+    pub(in crate::data) tags: Vec<String>,
 }
 
 impl From<crate::service::ask::NewClip> for NewClip {
     fn from(req: crate::service::ask::NewClip) -> Self {
         Self {
             clip_id: DbId::new().into(),
-            shortcode: ShortCode::new().into(),
+            shortcode: req.shortcode.map(ShortCode::into_inner),
             content: req.content.into_inner(),
             title: req.title.into_inner(),
             posted: Utc::now().timestamp(),
             expires: req.expires.into_inner().map(|time|time.timestamp()),
-            password: req.password.into_inner()
+            password: req.password.into_inner(),
+            api_key: None,
+            user_id: None,
+            visibility: req.visibility.to_string(),
+            burn_after: req.burn_after.into_inner().map(i64::from),
+            grants: vec![],
+            tags: req.tags.into_inner(),
+        }
+    }
+}
+
+// new clip owned by the API key or user it was created by
+impl From<(crate::service::ask::NewClip, Identity)> for NewClip {
+    fn from((req, owner): (crate::service::ask::NewClip, Identity)) -> Self {
+        let clip = Self::from(req);
+        match owner {
+            Identity::Anonymous => clip,
+            Identity::ApiKey(api_key) => Self { api_key: Some(api_key.into_inner()), ..clip },
+            Identity::User(user_id) => Self { user_id: Some(user_id.into_inner().into()), ..clip },
         }
     }
 }
 
+// new clip shared with the given users and API keys, saved along with the clip
+impl From<(crate::service::ask::NewClip, Identity, Vec<Grant>)> for NewClip {
+    fn from((req, owner, grants): (crate::service::ask::NewClip, Identity, Vec<Grant>)) -> Self {
+        Self { grants, ..Self::from((req, owner)) }
+    }
+}
 
 pub struct UpdateClip {
     pub(in crate::data) shortcode: String,
@@ -96,6 +177,11 @@ pub struct UpdateClip {
     pub(in crate::data) title: Option<String>,
     pub(in crate::data) expires: Option<i64>,
     pub(in crate::data) password: Option<String>,
+    pub(in crate::data) visibility: String,
+    pub(in crate::data) burn_after: Option<i64>,
+    // None keeps the current grants and tags
+    pub(in crate::data) grants: Option<Vec<Grant>>,
+    pub(in crate::data) tags: Option<Vec<String>>,
 }
 
 impl From<crate::service::ask::UpdateClip> for UpdateClip {
@@ -105,7 +191,214 @@ impl From<crate::service::ask::UpdateClip> for UpdateClip {
             content: req.content.into_inner(),
             title: req.title.into_inner(),
             expires: req.expires.into_inner().map(|time|time.timestamp()),
-            password: req.password.into_inner()
+            password: req.password.into_inner(),
+            visibility: req.visibility.to_string(),
+            burn_after: req.burn_after.into_inner().map(i64::from),
+            grants: None,
+            tags: req.tags.map(|tags| tags.into_inner()),
+        }
+    }
+}
+
+impl From<(crate::service::ask::UpdateClip, Option<Vec<Grant>>)> for UpdateClip {
+    fn from((req, grants): (crate::service::ask::UpdateClip, Option<Vec<Grant>>)) -> Self {
+        Self { grants, ..Self::from(req) }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Usage {
+    pub(in crate::data) clips: i64,
+    pub(in crate::data) bytes: i64,
+}
+
+impl From<Usage> for DomainUsage {
+    fn from(usage: Usage) -> Self {
+        Self {
+            clips: u64::try_from(usage.clips).unwrap_or_default(),
+            bytes: u64::try_from(usage.bytes).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct User {
+    pub(in crate::data) user_id: String,
+    pub(in crate::data) username: String,
+    pub(in crate::data) password_hash: String,
+    pub(in crate::data) created: NaiveDateTime,
+}
+
+impl User {
+    pub fn password_hash(&self) -> &str {
+        self.password_hash.as_str()
+    }
+}
+
+impl TryFrom<User> for crate::domain::user::User {
+    type Error = UserError;
+
+    fn try_from(user: User) -> Result<Self, Self::Error> {
+        use crate::domain::user::field::*;
+        use std::str::FromStr;
+
+        Ok(Self {
+            user_id: UserId::from_str(user.user_id.as_str())?,
+            username: Username::new(user.username.as_str())?,
+            created: Time::from_naive_utc(user.created),
+        })
+    }
+}
+
+pub struct NewUser {
+    pub(in crate::data) user_id: String,
+    pub(in crate::data) username: String,
+    pub(in crate::data) password_hash: String,
+    pub(in crate::data) created: i64,
+}
+
+// username and the argon2 hash of the password
+impl From<(Username, String)> for NewUser {
+    fn from((username, password_hash): (Username, String)) -> Self {
+        Self {
+            user_id: DbId::new().into(),
+            username: username.into_inner(),
+            password_hash,
+            created: Utc::now().timestamp(),
         }
     }
-}
\ No newline at end of file
+}
+
+// login session, only handed out while it has not expired
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct Session {
+    pub(in crate::data) user_id: String,
+    pub(in crate::data) expires: i64,
+}
+
+impl Session {
+    pub fn user_id(&self) -> Result<UserId, UserError> {
+        use std::str::FromStr;
+        UserId::from_str(self.user_id.as_str())
+    }
+}
+
+// user or API key a clip is shared with, exactly one of the two is set
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct Grant {
+    pub(in crate::data) user_id: Option<String>,
+    pub(in crate::data) api_key: Option<Vec<u8>>,
+}
+
+impl Grant {
+    pub fn api_key(&self) -> Option<ApiKey> {
+        self.api_key.clone().map(ApiKey::from)
+    }
+
+    pub fn user_id(&self) -> Option<UserId> {
+        use std::str::FromStr;
+        self.user_id.as_deref().and_then(|id| UserId::from_str(id).ok())
+    }
+}
+
+impl From<ApiKey> for Grant {
+    fn from(api_key: ApiKey) -> Self {
+        Self { user_id: None, api_key: Some(api_key.into_inner()) }
+    }
+}
+
+impl From<UserId> for Grant {
+    fn from(user_id: UserId) -> Self {
+        Self { user_id: Some(user_id.into_inner().into()), api_key: None }
+    }
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct Collection {
+    pub(in crate::data) collection_id: String,
+    pub(in crate::data) shortcode: String,
+    pub(in crate::data) title: Option<String>,
+    pub(in crate::data) password: Option<String>,
+    pub(in crate::data) posted: NaiveDateTime,
+    pub(in crate::data) api_key: Option<Vec<u8>>,
+    pub(in crate::data) user_id: Option<String>,
+}
+
+impl Collection {
+    // API key or user the collection was created by
+    pub fn owner(&self) -> Identity {
+        owner(&self.api_key, &self.user_id)
+    }
+}
+
+impl TryFrom<Collection> for crate::domain::Collection {
+    type Error = ClipError;
+
+    fn try_from(collection: Collection) -> Result<Self, Self::Error> {
+        use crate::domain::clip::field::*;
+        use crate::domain::collection::CollectionId;
+        use std::str::FromStr;
+
+        Ok(Self {
+            collection_id: CollectionId::new(DbId::from_str(collection.collection_id.as_str())?),
+            shortcode: ShortCode::from(collection.shortcode.as_str()),
+            title: Title::new(collection.title),
+            posted: Posted::new(Time::from_naive_utc(collection.posted)),
+            password: Password::new(collection.password.unwrap_or_default())?,
+        })
+    }
+}
+
+pub struct NewCollection {
+    pub(in crate::data) collection_id: String,
+    // generated when inserting unless a specific shortcode is requested
+    pub(in crate::data) shortcode: Option<String>,
+    pub(in crate::data) title: Option<String>,
+    pub(in crate::data) password: Option<String>,
+    pub(in crate::data) posted: i64,
+    pub(in crate::data) api_key: Option<Vec<u8>>,
+    pub(in crate::data) user_id: Option<String>,
+}
+
+impl From<(crate::service::ask::NewCollection, Identity)> for NewCollection {
+    fn from((req, owner): (crate::service::ask::NewCollection, Identity)) -> Self {
+        let collection = Self {
+            collection_id: DbId::new().into(),
+            shortcode: req.shortcode.map(ShortCode::into_inner),
+            title: req.title.into_inner(),
+            password: req.password.into_inner(),
+            posted: Utc::now().timestamp(),
+            api_key: None,
+            user_id: None,
+        };
+        match owner {
+            Identity::Anonymous => collection,
+            Identity::ApiKey(api_key) => Self { api_key: Some(api_key.into_inner()), ..collection },
+            Identity::User(user_id) => Self { user_id: Some(user_id.into_inner().into()), ..collection },
+        }
+    }
+}
+
+// views of a clip within one hour, from one referrer and kind of client
+#[derive(Debug, sqlx::FromRow)]
+pub struct ClipView {
+    pub(in crate::data) bucket: i64,
+    // empty for direct visits
+    pub(in crate::data) referrer: String,
+    pub(in crate::data) agent: String,
+    pub(in crate::data) views: i64,
+}
+
+impl TryFrom<ClipView> for (View, u64) {
+    type Error = ClipError;
+
+    fn try_from(view: ClipView) -> Result<Self, Self::Error> {
+        use std::str::FromStr;
+
+        let agent = Agent::from_str(view.agent.as_str()).map_err(|e| ClipError::InvalidView(e.to_string()))?;
+        Ok((
+            View { hour: view.bucket, referrer: Some(view.referrer).filter(|host| !host.is_empty()), agent },
+            u64::try_from(view.views)?,
+        ))
+    }
+}
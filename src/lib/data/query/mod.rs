@@ -0,0 +1,278 @@
+// the queries of the backend this build is for: sqlx checks them against DATABASE_URL
+// at compile time, so each backend keeps its own SQL behind the same functions
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::*;
+
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "postgres")]
+pub use postgres::*;
+
+use crate::data::DataError;
+
+type Result<T> = std::result::Result<T, DataError>;
+
+pub enum RevocationStatus {
+    Revoked,
+    NotFound,
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::data::test::*;
+    use crate::data::*;
+    use crate::test::async_runtime;
+    use std::str::FromStr;
+
+    fn model_get_clip(shortcode: &str) -> model::GetClip {
+        model::GetClip { shortcode: shortcode.into() }
+    }
+
+    fn model_new_clip(shortcode: &str) -> model::NewClip {
+        use chrono::Utc;
+        model::NewClip {
+            clip_id: DbId::new().into(),
+            content: format!("content for clip: {}", shortcode),
+            title: None,
+            shortcode: Some(shortcode.into()),
+            posted: Utc::now().timestamp(),
+            expires: None,
+            password: None,
+            api_key: None,
+            user_id: None,
+            visibility: "unlisted".to_owned(),
+            burn_after: None,
+            grants: vec![],
+            tags: vec![],
+        }
+    }
+
+    #[test]
+    fn clip_new_and_get() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+
+        let clip = rt.block_on(async move {
+            super::new_clip(model_new_clip("1"), &Default::default(), &Default::default(), &pool.clone()).await
+        });
+
+        assert!(clip.is_ok());
+
+        let clip = clip.unwrap();
+        assert_eq!(clip.shortcode, "1");
+        assert_eq!(clip.content, format!("content for clip: 1"));
+
+        let clip = rt.block_on(async move {
+            super::get_clip(model_get_clip("1"), &pool.clone()).await
+        });
+        assert!(clip.is_ok());
+    }
+
+    // the title used to be overwritten with the content
+    #[test]
+    fn update_saves_title_apart_from_content() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+
+        rt.block_on(async { super::new_clip(model_new_clip("1"), &Default::default(), &Default::default(), pool).await })
+            .unwrap();
+        let update = model::UpdateClip {
+            shortcode: "1".to_owned(),
+            content: "new content".to_owned(),
+            title: Some("new title".to_owned()),
+            expires: None,
+            password: None,
+            visibility: "unlisted".to_owned(),
+            burn_after: None,
+            grants: None,
+            tags: None,
+        };
+        let clip = rt.block_on(async { super::update_clip(update, &Default::default(), pool).await }).unwrap();
+        assert_eq!(clip.content, "new content");
+        assert_eq!(clip.title.as_deref(), Some("new title"));
+    }
+
+    #[test]
+    fn clip_is_not_saved_without_its_grants() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        // a grant names exactly one user or API key
+        let grantless = model::Grant { user_id: None, api_key: None };
+        let model = model::NewClip {
+            grants: vec![grantless],
+            tags: vec!["rust".to_owned()],
+            ..model_new_clip("1")
+        };
+
+        let clip = rt.block_on(async { super::new_clip(model, &Default::default(), &Default::default(), pool).await });
+        assert!(clip.is_err());
+        let clip = rt.block_on(async { super::get_clip(model_get_clip("1"), pool).await });
+        assert!(matches!(clip, Err(DataError::Database(sqlx::Error::RowNotFound))));
+    }
+
+    #[test]
+    fn duplicate_grants_are_saved_once() {
+        use crate::web::api::ApiKey;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        let api_key = rt
+            .block_on(async { super::save_api_key(ApiKey::default(), None, pool).await })
+            .unwrap();
+        let model = model::NewClip {
+            grants: vec![api_key.clone().into(), api_key.into()],
+            ..model_new_clip("1")
+        };
+
+        let clip = rt.block_on(async { super::new_clip(model, &Default::default(), &Default::default(), pool).await }).unwrap();
+        let clip_id = DbId::from_str(&clip.clip_id).unwrap().into();
+        let grants = rt.block_on(async { super::get_grants(&clip_id, pool).await }).unwrap();
+        assert_eq!(grants.len(), 1);
+    }
+
+    #[test]
+    fn gives_up_when_no_shortcode_is_free() {
+        use crate::domain::clip::field::ShortCodeGenerator;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        let shortcodes = ShortCodeGenerator::fixed("taken");
+        let generated = || model::NewClip { shortcode: None, ..model_new_clip("") };
+
+        let clip = rt.block_on(async { super::new_clip(generated(), &shortcodes, &Default::default(), pool).await });
+        assert!(clip.is_ok());
+        let clip = rt.block_on(async { super::new_clip(generated(), &shortcodes, &Default::default(), pool).await });
+        assert!(clip.is_err());
+    }
+
+    #[test]
+    fn usage_counts_clips_of_api_key() {
+        use crate::web::api::ApiKey;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        let api_key = rt
+            .block_on(async move { super::save_api_key(ApiKey::default(), None, &pool.clone()).await })
+            .unwrap();
+
+        for shortcode in ["1", "2"] {
+            let model = model::NewClip {
+                api_key: Some(api_key.clone().into_inner()),
+                ..model_new_clip(shortcode)
+            };
+            rt.block_on(async move { super::new_clip(model, &Default::default(), &Default::default(), &pool.clone()).await })
+                .unwrap();
+        }
+        // not owned by the key
+        rt.block_on(async move { super::new_clip(model_new_clip("3"), &Default::default(), &Default::default(), &pool.clone()).await })
+            .unwrap();
+
+        let usage = rt
+            .block_on(async move { super::get_usage(api_key, &pool.clone()).await })
+            .unwrap();
+        assert_eq!(usage.clips, 2);
+        assert_eq!(usage.bytes, 2 * "content for clip: 1".len() as i64);
+    }
+
+    #[test]
+    fn quota_holds_for_concurrent_clips() {
+        use crate::domain::quota::Quota;
+        use crate::web::api::ApiKey;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool().clone();
+        let api_key = rt
+            .block_on(async { super::save_api_key(ApiKey::default(), None, &pool).await })
+            .unwrap();
+        let quota = Quota { max_clips: Some(2), max_bytes: None };
+
+        let created = (0..6)
+            .map(|_| {
+                let (pool, quota) = (pool.clone(), quota.clone());
+                let model = model::NewClip { api_key: Some(api_key.clone().into_inner()), shortcode: None, ..model_new_clip("") };
+                rt.spawn(async move { super::new_clip(model, &Default::default(), &quota, &pool).await })
+            })
+            .collect::<Vec<_>>();
+        let created = rt.block_on(async {
+            let mut results = vec![];
+            for clip in created {
+                results.push(clip.await.unwrap());
+            }
+            results
+        });
+
+        assert_eq!(created.iter().filter(|clip| clip.is_ok()).count(), 2);
+        assert!(created.iter().all(|clip| matches!(clip, Ok(_) | Err(DataError::Quota(_)))));
+        let usage = rt.block_on(async { super::get_usage(api_key, &pool).await }).unwrap();
+        assert_eq!(usage.clips, 2);
+    }
+
+    #[test]
+    fn username_is_unique() {
+        use crate::domain::user::field::Username;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        let new_user = || {
+            let model = model::NewUser::from((Username::new("alice").unwrap(), String::new()));
+            rt.block_on(async { super::new_user(model, pool).await })
+        };
+
+        assert!(new_user().is_ok());
+        assert!(matches!(new_user(), Err(DataError::UsernameTaken(username)) if username == "alice"));
+    }
+
+    #[test]
+    fn visitor_salt_is_shared_until_the_day_is_over() {
+        use crate::domain::stats::View;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        let day = 10 * View::DAY;
+
+        rt.block_on(async {
+            assert_eq!(super::visitor_salt(day, &[1; 32], pool).await.unwrap(), vec![1; 32]);
+            // another replica, or this one after a restart
+            assert_eq!(super::visitor_salt(day, &[2; 32], pool).await.unwrap(), vec![1; 32]);
+
+            super::delete_stale_visitors(day + View::DAY, pool).await.unwrap();
+            assert_eq!(super::visitor_salt(day + View::DAY, &[3; 32], pool).await.unwrap(), vec![3; 32]);
+            assert_eq!(super::visitor_salt(day, &[2; 32], pool).await.unwrap(), vec![2; 32]);
+        });
+    }
+
+    #[test]
+    fn old_views_are_deleted() {
+        use crate::domain::stats::{Agent, View};
+        use crate::domain::clip::field::ClipId;
+        use crate::service::action;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+
+        rt.block_on(async {
+            let clip = super::new_clip(model_new_clip("1"), &Default::default(), &Default::default(), pool).await.unwrap();
+            let clip_id: ClipId = DbId::from_str(&clip.clip_id).unwrap().into();
+            let view = |hour| View { hour, referrer: None, agent: Agent::Cli };
+            let views = vec![(clip_id.clone(), view(View::DAY), 1), (clip_id.clone(), view(3 * View::DAY), 2)];
+            action::record_views(views, vec![], pool).await.unwrap();
+
+            assert_eq!(super::delete_old_views(2 * View::DAY, pool).await.unwrap(), 1);
+            let views = super::get_clip_views(&clip_id, 0, pool).await.unwrap();
+            assert_eq!(views.len(), 1);
+            assert_eq!(views[0].bucket, 3 * View::DAY);
+        });
+    }
+}
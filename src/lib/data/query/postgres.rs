@@ -0,0 +1,751 @@
+use super::{RevocationStatus, Result};
+use crate::data::{model, Backend, DataError, DbPool, Tx};
+use crate::domain::quota::Quota;
+use crate::ShortCode;
+use crate::domain::clip::field::{ClipId, ShortCodeGenerator};
+use crate::domain::collection::CollectionId;
+use crate::domain::share::ShareSecret;
+use crate::domain::stats::{View, Visitor};
+use crate::data::model::GetClip;
+use crate::domain::user::field::{UserId, Username};
+use crate::web::api::ApiKey;
+
+// timestamps are bound as unix seconds, like with sqlite, and stored as UTC timestamps:
+// to_timestamp(...) AT TIME ZONE 'utc' does not depend on the session time zone
+
+// aliases resolve to the clip they belong to
+pub async fn get_clip<M: Into<GetClip>>(model: M, pool: &DbPool) -> Result<model::Clip> {
+    let model = model.into();
+    let shortcode = model.shortcode.as_str();
+    Ok(sqlx::query_as!(
+        model::Clip,
+        r#"SELECT *, (SELECT string_agg(tag, ',') FROM clip_tags WHERE clip_tags.clip_id = clips.clip_id) AS "tags?: String"
+           FROM clips WHERE shortcode = $1 OR clip_id = (SELECT clip_id FROM aliases WHERE shortcode = $1)"#,
+        shortcode
+      )
+        .fetch_one(pool)
+        .await?)
+}
+
+// using a model as param to avoid having to pass a whole bunch of clip properties as params.
+// the clip, its grants and its tags are saved together or not at all
+pub async fn new_clip<M: Into<model::NewClip>>(
+    model: M,
+    shortcodes: &ShortCodeGenerator,
+    quota: &Quota,
+    pool: &DbPool,
+) -> Result<model::Clip> {
+    let model = model.into();
+    let mut tx = pool.begin().await?;
+    let shortcode = insert_clip(&model, shortcodes, &mut tx).await?;
+    if let Some(api_key) = &model.api_key {
+        check_quota(api_key, quota, &mut tx).await?;
+    }
+    replace_grants(&model.clip_id, &model.grants, &mut tx).await?;
+    replace_tags(&model.clip_id, &model.tags, &mut tx).await?;
+    tx.commit().await?;
+    get_clip(shortcode, pool).await
+}
+
+// primary shortcode or alias
+async fn shortcode_taken<'e, E: sqlx::Executor<'e, Database = Backend>>(shortcode: &str, executor: E) -> Result<bool> {
+    Ok(sqlx::query!(
+        r#"SELECT EXISTS(SELECT 1 FROM clips WHERE shortcode = $1)
+            OR EXISTS(SELECT 1 FROM aliases WHERE shortcode = $1) AS "taken!: bool""#,
+        shortcode)
+        .fetch_one(executor)
+        .await?
+        .taken)
+}
+
+// 23505 is unique_violation, the constraint tells which one
+fn is_unique_violation(e: &sqlx::Error, constraint: &str) -> bool {
+    matches!(e, sqlx::Error::Database(e) if e.code().as_deref() == Some("23505") && e.message().contains(constraint))
+}
+
+// generated shortcodes are retried on collision, requested ones are not.
+// a failed statement aborts the transaction, so collisions are skipped instead of raised
+async fn insert_clip(model: &model::NewClip, shortcodes: &ShortCodeGenerator, tx: &mut Tx<'_>) -> Result<String> {
+    for _ in 0..ShortCodeGenerator::MAX_ATTEMPTS {
+        let shortcode = match &model.shortcode {
+            Some(shortcode) => shortcode.clone(),
+            None => shortcodes.generate().into_inner(),
+        };
+        // the unique constraint only covers primary shortcodes, not aliases
+        let inserted = !shortcode_taken(&shortcode, &mut *tx).await? && sqlx::query!(
+            r#"INSERT INTO clips (
+                clip_id,
+                shortcode,
+                content,
+                title,
+                posted,
+                expires,
+                password,
+                hits,
+                api_key,
+                user_id,
+                visibility,
+                burn_after)
+            VALUES($1, $2, $3, $4,
+                   to_timestamp($5::bigint) AT TIME ZONE 'utc',
+                   to_timestamp($6::bigint) AT TIME ZONE 'utc',
+                   $7, $8, $9, $10, $11, $12)
+            ON CONFLICT (shortcode) DO NOTHING"#,
+            model.clip_id,
+            shortcode,
+            model.content,
+            model.title,
+            model.posted,
+            model.expires,
+            model.password,
+            0,
+            model.api_key,
+            model.user_id,
+            model.visibility,
+            model.burn_after)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected() > 0;
+        if inserted {
+            return Ok(shortcode);
+        }
+        if model.shortcode.is_some() {
+            return Err(DataError::ShortCodeTaken(shortcode));
+        }
+    }
+    Err(DataError::ShortCodesExhausted(ShortCodeGenerator::MAX_ATTEMPTS))
+}
+
+// checked after the write with the API key locked, so concurrent clips of the same key
+// are counted one after the other: each statement sees what the others committed.
+// the foreign key of the clip already holds a key share lock, which FOR UPDATE would deadlock on
+async fn check_quota(api_key: &[u8], quota: &Quota, tx: &mut Tx<'_>) -> Result<()> {
+    let usage = locked_usage(api_key, tx).await?;
+    Ok(quota.check(&usage.into())?)
+}
+
+async fn locked_usage(api_key: &[u8], tx: &mut Tx<'_>) -> Result<model::Usage> {
+    sqlx::query!("SELECT api_key FROM api_keys WHERE api_key = $1 FOR NO KEY UPDATE", api_key)
+        .fetch_optional(&mut *tx)
+        .await?;
+    usage(api_key, &mut *tx).await
+}
+
+// grants and tags are only replaced when the model carries them,
+// size changes are accounted to the API key that created the clip
+pub async fn update_clip<M: Into<model::UpdateClip>>(model: M, quota: &Quota, pool: &DbPool) -> Result<model::Clip> {
+    let model = model.into();
+    let mut tx = pool.begin().await?;
+    // usage before the edit, with the API key locked like for new clips
+    let api_key = sqlx::query_scalar!("SELECT api_key FROM clips WHERE shortcode = $1", model.shortcode)
+        .fetch_one(&mut tx)
+        .await?;
+    let before = match &api_key {
+        Some(api_key) => Some(locked_usage(api_key, &mut tx).await?),
+        None => None,
+    };
+    let clip_id = sqlx::query_scalar!(
+        r#"UPDATE clips SET
+                content = $1,
+                expires = to_timestamp($2::bigint) AT TIME ZONE 'utc',
+                password = $3,
+                title = $4,
+                visibility = $5,
+                burn_after = $6
+           WHERE shortcode = $7
+           RETURNING clip_id"#,
+        model.content,
+        model.expires,
+        model.password,
+        model.title,
+        model.visibility,
+        model.burn_after,
+        model.shortcode
+        )
+        .fetch_one(&mut tx)
+        .await?;
+    if let (Some(api_key), Some(before)) = (&api_key, before) {
+        let after = usage(api_key, &mut *tx).await?;
+        quota.check_change(&before.into(), &after.into())?;
+    }
+    if let Some(grants) = &model.grants {
+        replace_grants(&clip_id, grants, &mut tx).await?;
+    }
+    if let Some(tags) = &model.tags {
+        replace_tags(&clip_id, tags, &mut tx).await?;
+    }
+    tx.commit().await?;
+
+    get_clip(model.shortcode, pool).await
+}
+
+// a single statement for any number of clips: the increments are passed as one JSON object
+pub async fn increase_hit_counts(hits: Vec<(ClipId, u32)>, tx: &mut Tx<'_>) -> Result<()> {
+    let hits = hits
+        .into_iter()
+        .map(|(clip_id, hits)| (String::from(clip_id.into_inner()), serde_json::Value::from(hits)))
+        .collect::<serde_json::Map<_, _>>();
+    let hits = serde_json::Value::Object(hits).to_string();
+    sqlx::query!(
+        r#"UPDATE clips SET hits = hits + hit.value::bigint
+           FROM jsonb_each_text($1::text::jsonb) AS hit
+           WHERE clips.clip_id = hit.key"#,
+        hits
+      )
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// adds views to the hourly buckets of their clips, in a single statement like the hit counts
+pub async fn record_views(views: Vec<(ClipId, View, u32)>, tx: &mut Tx<'_>) -> Result<()> {
+    let views = views
+        .into_iter()
+        .map(|(clip_id, view, views)| serde_json::json!({
+            "clip_id": String::from(clip_id.into_inner()),
+            "bucket": view.hour,
+            "referrer": view.referrer.unwrap_or_default(),
+            "agent": view.agent.to_string(),
+            "views": views,
+        }))
+        .collect::<Vec<_>>();
+    let views = serde_json::Value::Array(views).to_string();
+    // summed up first: an upsert may not touch the same row twice
+    sqlx::query!(
+        r#"INSERT INTO clip_views (clip_id, bucket, referrer, agent, views)
+           SELECT clips.clip_id,
+                  (view.value->>'bucket')::bigint AS bucket,
+                  view.value->>'referrer' AS referrer,
+                  view.value->>'agent' AS agent,
+                  sum((view.value->>'views')::bigint)
+           FROM jsonb_array_elements($1::text::jsonb) AS view
+           JOIN clips ON clips.clip_id = view.value->>'clip_id'
+           GROUP BY clips.clip_id, bucket, referrer, agent
+           ON CONFLICT (clip_id, bucket, referrer, agent) DO UPDATE SET views = clip_views.views + excluded.views"#,
+        views
+      )
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// counts visitors not yet seen on their day, then remembers them
+pub async fn record_visitors(visitors: Vec<(ClipId, Visitor)>, tx: &mut Tx<'_>) -> Result<()> {
+    let visitors = visitors
+        .into_iter()
+        .map(|(clip_id, visitor)| serde_json::json!({
+            "clip_id": String::from(clip_id.into_inner()),
+            "day": visitor.day,
+            "visitor": visitor.id,
+        }))
+        .collect::<Vec<_>>();
+    let visitors = serde_json::Value::Array(visitors).to_string();
+    sqlx::query!(
+        r#"UPDATE clips SET visitors = visitors + seen.new_visitors
+           FROM (SELECT new.value->>'clip_id' AS clip_id, count(*) AS new_visitors
+                 FROM jsonb_array_elements($1::text::jsonb) AS new
+                 JOIN clips ON clips.clip_id = new.value->>'clip_id'
+                 WHERE NOT EXISTS (SELECT 1 FROM clip_visitors
+                                   WHERE clip_visitors.clip_id = clips.clip_id
+                                     AND clip_visitors.day = (new.value->>'day')::bigint
+                                     AND clip_visitors.visitor = new.value->>'visitor')
+                 GROUP BY new.value->>'clip_id') AS seen
+           WHERE clips.clip_id = seen.clip_id"#,
+        visitors
+      )
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        r#"INSERT INTO clip_visitors (clip_id, day, visitor)
+           SELECT clips.clip_id, (new.value->>'day')::bigint, new.value->>'visitor'
+           FROM jsonb_array_elements($1::text::jsonb) AS new
+           JOIN clips ON clips.clip_id = new.value->>'clip_id'
+           ON CONFLICT DO NOTHING"#,
+        visitors
+      )
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// the key visitors of `day` are hashed with, `candidate` becomes it unless another replica was first
+pub async fn visitor_salt(day: i64, candidate: &[u8], pool: &DbPool) -> Result<Vec<u8>> {
+    sqlx::query!("INSERT INTO visitor_salts (day, salt) VALUES ($1, $2) ON CONFLICT (day) DO NOTHING", day, candidate)
+        .execute(pool)
+        .await?;
+    Ok(sqlx::query_scalar!("SELECT salt FROM visitor_salts WHERE day = $1", day)
+        .fetch_one(pool)
+        .await?)
+}
+
+// visitors of past days were hashed with a key that is gone, they can't be recognized anymore
+pub async fn delete_stale_visitors(today: i64, pool: &DbPool) -> Result<u64> {
+    sqlx::query!("DELETE FROM visitor_salts WHERE day < $1", today)
+        .execute(pool)
+        .await?;
+    Ok(sqlx::query!("DELETE FROM clip_visitors WHERE day < $1", today)
+        .execute(pool)
+        .await?
+        .rows_affected())
+}
+
+// hourly buckets that start before `since`
+pub async fn delete_old_views(since: i64, pool: &DbPool) -> Result<u64> {
+    Ok(sqlx::query!("DELETE FROM clip_views WHERE bucket < $1", since)
+        .execute(pool)
+        .await?
+        .rows_affected())
+}
+
+// hourly buckets starting at `since` or later
+pub async fn get_clip_views(clip_id: &ClipId, since: i64, pool: &DbPool) -> Result<Vec<model::ClipView>> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    Ok(sqlx::query_as!(
+        model::ClipView,
+        "SELECT bucket, referrer, agent, views FROM clip_views WHERE clip_id = $1 AND bucket >= $2 ORDER BY bucket",
+        clip_id,
+        since
+      )
+        .fetch_all(pool)
+        .await?)
+}
+
+/// count a wrong password entered for a clip
+pub async fn record_failed_attempt(shortcode: &ShortCode, pool: &DbPool) -> Result<model::Clip> {
+    let raw = shortcode.as_str();
+    sqlx::query!("UPDATE clips SET failed_attempts = failed_attempts + 1 WHERE shortcode = $1", raw)
+        .execute(pool)
+        .await?;
+    get_clip(shortcode.clone(), pool).await
+}
+
+pub async fn delete_clip(shortcode: &ShortCode, pool: &DbPool) -> Result<()> {
+    let shortcode = shortcode.as_str();
+    sqlx::query!("DELETE FROM clips WHERE shortcode = $1", shortcode)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// replace the key share links of a clip are signed with, invalidating the previous ones
+pub async fn save_share_secret(shortcode: &ShortCode, secret: ShareSecret, pool: &DbPool) -> Result<()> {
+    let shortcode = shortcode.as_str();
+    let secret = secret.into_inner();
+    sqlx::query!("UPDATE clips SET share_secret = $1 WHERE shortcode = $2", secret, shortcode)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_aliases(clip_id: &ClipId, pool: &DbPool) -> Result<Vec<String>> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    Ok(sqlx::query!("SELECT shortcode FROM aliases WHERE clip_id = $1 ORDER BY created", clip_id)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| row.shortcode)
+        .collect())
+}
+
+pub async fn add_alias(clip_id: &ClipId, alias: &ShortCode, pool: &DbPool) -> Result<()> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    let (alias, now) = (alias.as_str(), chrono::Utc::now().timestamp());
+    // one statement, so a concurrent clip or alias with the same shortcode is a conflict rather than an error
+    let inserted = sqlx::query!(
+        r#"INSERT INTO aliases (shortcode, clip_id, created)
+        SELECT $1::text, $2::text, to_timestamp($3::bigint) AT TIME ZONE 'utc'
+        WHERE NOT EXISTS(SELECT 1 FROM clips WHERE shortcode = $1)
+        ON CONFLICT (shortcode) DO NOTHING"#,
+        alias,
+        clip_id,
+        now)
+        .execute(pool)
+        .await?
+        .rows_affected() > 0;
+    if !inserted {
+        return Err(DataError::ShortCodeTaken(alias.to_owned()));
+    }
+    Ok(())
+}
+
+pub async fn remove_alias(clip_id: &ClipId, alias: &ShortCode, pool: &DbPool) -> Result<()> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    let alias = alias.as_str();
+    match sqlx::query!("DELETE FROM aliases WHERE shortcode = $1 AND clip_id = $2", alias, clip_id)
+        .execute(pool)
+        .await?
+        .rows_affected() {
+        0 => Err(sqlx::Error::RowNotFound.into()),
+        _ => Ok(()),
+    }
+}
+
+/// change the primary shortcode of a clip, keeping the current one as an alias
+pub async fn rename_clip(clip_id: &ClipId, shortcode: &ShortCode, renamed: &ShortCode, pool: &DbPool) -> Result<model::Clip> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    let (shortcode, renamed, now) = (shortcode.as_str(), renamed.as_str(), chrono::Utc::now().timestamp());
+    let mut tx = pool.begin().await?;
+    // going back to a former shortcode of the clip
+    sqlx::query!("DELETE FROM aliases WHERE shortcode = $1 AND clip_id = $2", renamed, clip_id)
+        .execute(&mut tx)
+        .await?;
+    let taken = sqlx::query!(
+        r#"SELECT EXISTS(SELECT 1 FROM clips WHERE shortcode = $1)
+            OR EXISTS(SELECT 1 FROM aliases WHERE shortcode = $1) AS "taken!: bool""#,
+        renamed)
+        .fetch_one(&mut tx)
+        .await?
+        .taken;
+    if taken {
+        return Err(DataError::ShortCodeTaken(renamed.to_owned()));
+    }
+    sqlx::query!("UPDATE clips SET shortcode = $1 WHERE clip_id = $2", renamed, clip_id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO aliases (shortcode, clip_id, created) VALUES ($1, $2, to_timestamp($3::bigint) AT TIME ZONE 'utc')",
+        shortcode,
+        clip_id,
+        now)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    get_clip(renamed.to_owned(), pool).await
+}
+
+pub async fn get_collection(shortcode: &ShortCode, pool: &DbPool) -> Result<model::Collection> {
+    let shortcode = shortcode.as_str();
+    Ok(sqlx::query_as!(model::Collection, "SELECT * FROM collections WHERE shortcode = $1", shortcode)
+        .fetch_one(pool)
+        .await?)
+}
+
+// generated shortcodes are retried on collision, requested ones are not
+pub async fn new_collection<M: Into<model::NewCollection>>(model: M, shortcodes: &ShortCodeGenerator, pool: &DbPool) -> Result<model::Collection> {
+    let model = model.into();
+    for _ in 0..ShortCodeGenerator::MAX_ATTEMPTS {
+        let shortcode = match &model.shortcode {
+            Some(shortcode) => shortcode.clone(),
+            None => shortcodes.generate().into_inner(),
+        };
+        let inserted = sqlx::query!(
+            r#"INSERT INTO collections (collection_id, shortcode, title, password, posted, api_key, user_id)
+            VALUES ($1, $2, $3, $4, to_timestamp($5::bigint) AT TIME ZONE 'utc', $6, $7)"#,
+            model.collection_id,
+            shortcode,
+            model.title,
+            model.password,
+            model.posted,
+            model.api_key,
+            model.user_id)
+            .execute(pool)
+            .await;
+        match inserted {
+            Err(e) if is_unique_violation(&e, "collections_shortcode_key") => {
+                if model.shortcode.is_some() {
+                    return Err(DataError::ShortCodeTaken(shortcode));
+                }
+            }
+            Err(e) => return Err(e.into()),
+            Ok(_) => return get_collection(&ShortCode::from(shortcode), pool).await,
+        }
+    }
+    Err(DataError::ShortCodesExhausted(ShortCodeGenerator::MAX_ATTEMPTS))
+}
+
+// in the order they were added
+pub async fn get_collection_clips(collection_id: &CollectionId, pool: &DbPool) -> Result<Vec<model::Clip>> {
+    let collection_id: String = collection_id.clone().into_inner().into();
+    Ok(sqlx::query_as!(
+        model::Clip,
+        r#"SELECT clips.*, (SELECT string_agg(tag, ',') FROM clip_tags WHERE clip_tags.clip_id = clips.clip_id) AS "tags?: String"
+            FROM clips
+            JOIN collection_clips ON collection_clips.clip_id = clips.clip_id
+            WHERE collection_clips.collection_id = $1
+                AND (clips.expires IS NULL OR clips.expires > now() AT TIME ZONE 'utc')
+            ORDER BY collection_clips.added, collection_clips.seq"#,
+        collection_id)
+        .fetch_all(pool)
+        .await?)
+}
+
+// adding a clip twice keeps it in its original place
+pub async fn add_collection_clip(collection_id: &CollectionId, clip_id: &ClipId, pool: &DbPool) -> Result<()> {
+    let collection_id: String = collection_id.clone().into_inner().into();
+    let clip_id: String = clip_id.clone().into_inner().into();
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query!(
+        r#"INSERT INTO collection_clips (collection_id, clip_id, added)
+           VALUES ($1, $2, to_timestamp($3::bigint) AT TIME ZONE 'utc')
+           ON CONFLICT DO NOTHING"#,
+        collection_id,
+        clip_id,
+        now)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn remove_collection_clip(collection_id: &CollectionId, clip_id: &ClipId, pool: &DbPool) -> Result<()> {
+    let collection_id: String = collection_id.clone().into_inner().into();
+    let clip_id: String = clip_id.clone().into_inner().into();
+    match sqlx::query!("DELETE FROM collection_clips WHERE collection_id = $1 AND clip_id = $2", collection_id, clip_id)
+        .execute(pool)
+        .await?
+        .rows_affected() {
+        0 => Err(sqlx::Error::RowNotFound.into()),
+        _ => Ok(()),
+    }
+}
+
+/// save API_KEY to DB, optionally on behalf of a user
+pub async fn save_api_key(api_key: ApiKey, user_id: Option<UserId>, pool: &DbPool) -> Result<ApiKey> {
+    let bytes = api_key.clone().into_inner();
+    let user_id: Option<String> = user_id.map(|id| id.into_inner().into());
+    sqlx::query!("INSERT INTO api_keys (api_key, user_id) VALUES ($1, $2)", bytes, user_id)
+        .execute(pool)
+        .await?;
+    Ok(api_key)
+}
+
+/// remove API_KEY from DB
+pub async fn revoke_api_key(api_key: ApiKey, pool: &DbPool) -> Result<RevocationStatus> {
+    let bytes = api_key.clone().into_inner();
+    Ok(sqlx::query!("DELETE FROM api_keys WHERE api_key = $1", bytes)
+        .execute(pool)
+        .await
+        .map(|res| match res.rows_affected() {
+            0 => RevocationStatus::NotFound,
+            _ => RevocationStatus::Revoked
+        })?
+    )
+}
+
+pub async fn api_key_is_valid(api_key: ApiKey, pool: &DbPool) -> Result<bool> {
+    let bytes = api_key.clone().into_inner();
+    Ok(sqlx::query!(r#"SELECT EXISTS(SELECT 1 FROM api_keys WHERE api_key = $1) AS "valid!: bool""#, bytes)
+        .fetch_one(pool)
+        .await?
+        .valid)
+}
+
+/// number and total size of the live clips created with API_KEY
+pub async fn get_usage(api_key: ApiKey, pool: &DbPool) -> Result<model::Usage> {
+    usage(&api_key.into_inner(), pool).await
+}
+
+async fn usage<'e, E: sqlx::Executor<'e, Database = Backend>>(api_key: &[u8], executor: E) -> Result<model::Usage> {
+    Ok(sqlx::query_as!(
+        model::Usage,
+        r#"SELECT
+                COUNT(*) AS "clips!: i64",
+                COALESCE(SUM(octet_length(content)), 0) AS "bytes!: i64"
+           FROM clips
+           WHERE api_key = $1 AND (expires IS NULL OR expires > now() AT TIME ZONE 'utc')"#,
+        api_key
+      )
+        .fetch_one(executor)
+        .await?)
+}
+
+pub async fn new_user<M: Into<model::NewUser>>(model: M, pool: &DbPool) -> Result<model::User> {
+    let model = model.into();
+    let inserted = sqlx::query!(
+        "INSERT INTO users (user_id, username, password_hash, created) VALUES ($1, $2, $3, to_timestamp($4::bigint) AT TIME ZONE 'utc')",
+        model.user_id,
+        model.username,
+        model.password_hash,
+        model.created)
+        .execute(pool)
+        .await;
+    match inserted {
+        Err(e) if is_unique_violation(&e, "users_username_key") => return Err(DataError::UsernameTaken(model.username)),
+        inserted => inserted?,
+    };
+
+    Ok(sqlx::query_as!(model::User, "SELECT * FROM users WHERE user_id = $1", model.user_id)
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn get_user_by_name(username: &Username, pool: &DbPool) -> Result<model::User> {
+    let username = username.as_str();
+    Ok(sqlx::query_as!(model::User, "SELECT * FROM users WHERE username = $1", username)
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn get_user(user_id: UserId, pool: &DbPool) -> Result<model::User> {
+    let user_id: String = user_id.into_inner().into();
+    Ok(sqlx::query_as!(model::User, "SELECT * FROM users WHERE user_id = $1", user_id)
+        .fetch_one(pool)
+        .await?)
+}
+
+/// replace the users and API keys a clip is shared with
+async fn replace_grants(clip_id: &str, grants: &[model::Grant], tx: &mut Tx<'_>) -> Result<()> {
+    sqlx::query!("DELETE FROM clip_grants WHERE clip_id = $1", clip_id)
+        .execute(&mut *tx)
+        .await?;
+    // sharing twice with the same user or API key is a single grant
+    for grant in grants {
+        sqlx::query!(
+            "INSERT INTO clip_grants (clip_id, user_id, api_key) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+            clip_id,
+            grant.user_id,
+            grant.api_key)
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
+// replaces all tags of the clip
+async fn replace_tags(clip_id: &str, tags: &[String], tx: &mut Tx<'_>) -> Result<()> {
+    sqlx::query!("DELETE FROM clip_tags WHERE clip_id = $1", clip_id)
+        .execute(&mut *tx)
+        .await?;
+    for tag in tags {
+        sqlx::query!("INSERT INTO clip_tags (clip_id, tag) VALUES ($1, $2)", clip_id, tag)
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn get_grants(clip_id: &ClipId, pool: &DbPool) -> Result<Vec<model::Grant>> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    Ok(sqlx::query_as!(model::Grant, "SELECT user_id, api_key FROM clip_grants WHERE clip_id = $1", clip_id)
+        .fetch_all(pool)
+        .await?)
+}
+
+/// user the API key was created by, if any
+pub async fn get_api_key_user(api_key: ApiKey, pool: &DbPool) -> Result<Option<UserId>> {
+    use std::str::FromStr;
+
+    let bytes = api_key.into_inner();
+    Ok(sqlx::query!("SELECT user_id FROM api_keys WHERE api_key = $1", bytes)
+        .fetch_optional(pool)
+        .await?
+        .and_then(|row| row.user_id)
+        .and_then(|user_id| UserId::from_str(user_id.as_str()).ok())
+    )
+}
+
+pub async fn get_user_by_identity(issuer: &str, subject: &str, pool: &DbPool) -> Result<model::User> {
+    Ok(sqlx::query_as!(
+        model::User,
+        r#"SELECT users.* FROM users
+           JOIN user_identities ON user_identities.user_id = users.user_id
+           WHERE user_identities.issuer = $1 AND user_identities.subject = $2"#,
+        issuer,
+        subject
+      )
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn link_identity(issuer: &str, subject: &str, user_id: UserId, pool: &DbPool) -> Result<()> {
+    let user_id: String = user_id.into_inner().into();
+    sqlx::query!(
+        "INSERT INTO user_identities (issuer, subject, user_id) VALUES ($1, $2, $3)",
+        issuer,
+        subject,
+        user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn new_session(session_id: &str, user_id: UserId, expires: i64, pool: &DbPool) -> Result<()> {
+    let user_id: String = user_id.into_inner().into();
+    sqlx::query!(
+        "INSERT INTO sessions (session_id, user_id, expires) VALUES ($1, $2, $3)",
+        session_id,
+        user_id,
+        expires)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// expired sessions are as good as gone, whether or not they were deleted yet
+pub async fn get_session(session_id: &str, pool: &DbPool) -> Result<model::Session> {
+    Ok(sqlx::query_as!(
+        model::Session,
+        "SELECT user_id, expires FROM sessions WHERE session_id = $1 AND expires > extract(epoch from now())::bigint",
+        session_id
+      )
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn delete_session(session_id: &str, pool: &DbPool) -> Result<()> {
+    sqlx::query!("DELETE FROM sessions WHERE session_id = $1", session_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_expired_sessions(pool: &DbPool) -> Result<u64> {
+    Ok(sqlx::query!("DELETE FROM sessions WHERE expires <= extract(epoch from now())::bigint")
+        .execute(pool)
+        .await?
+        .rows_affected())
+}
+
+/// clips created by a user, either through the web UI or with one of their API keys,
+/// optionally only those carrying `tag`
+pub async fn get_user_clips(user_id: UserId, tag: Option<&str>, pool: &DbPool) -> Result<Vec<model::Clip>> {
+    let user_id: String = user_id.into_inner().into();
+    Ok(sqlx::query_as!(
+        model::Clip,
+        r#"SELECT *, (SELECT string_agg(tag, ',') FROM clip_tags WHERE clip_tags.clip_id = clips.clip_id) AS "tags?: String"
+           FROM clips
+           WHERE (user_id = $1 OR api_key IN (SELECT api_key FROM api_keys WHERE user_id = $1))
+             AND ($2::text IS NULL OR clip_id IN (SELECT clip_id FROM clip_tags WHERE tag = $2))
+           ORDER BY posted DESC"#,
+        user_id,
+        tag
+      )
+        .fetch_all(pool)
+        .await?)
+}
+
+/// clips created with an API key, optionally only those carrying `tag`
+pub async fn get_api_key_clips(api_key: ApiKey, tag: Option<&str>, pool: &DbPool) -> Result<Vec<model::Clip>> {
+    let api_key = api_key.into_inner();
+    Ok(sqlx::query_as!(
+        model::Clip,
+        r#"SELECT *, (SELECT string_agg(tag, ',') FROM clip_tags WHERE clip_tags.clip_id = clips.clip_id) AS "tags?: String"
+           FROM clips
+           WHERE api_key = $1
+             AND ($2::text IS NULL OR clip_id IN (SELECT clip_id FROM clip_tags WHERE tag = $2))
+           ORDER BY posted DESC"#,
+        api_key,
+        tag
+      )
+        .fetch_all(pool)
+        .await?)
+}
+
+// newest migration applied, None on an empty database
+pub async fn schema_version(pool: &DbPool) -> Result<Option<i64>> {
+    Ok(sqlx::query_scalar!(r#"SELECT max(version) AS "version?: i64" FROM _sqlx_migrations WHERE success"#)
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn ping(pool: &DbPool) -> Result<()> {
+    sqlx::query!("SELECT 1 AS one").fetch_one(pool).await?;
+    Ok(())
+}
+
+pub async fn delete_expired(pool:&DbPool) -> Result<u64> {
+    Ok(sqlx::query!("DELETE FROM clips WHERE expires < now() AT TIME ZONE 'utc'")
+        .execute(pool)
+        .await?
+        .rows_affected()
+    )
+}
@@ -0,0 +1,737 @@
+use super::{RevocationStatus, Result};
+use crate::data::{model, Backend, DataError, DbPool, Tx};
+use crate::domain::quota::Quota;
+use crate::ShortCode;
+use crate::domain::clip::field::{ClipId, ShortCodeGenerator};
+use crate::domain::collection::CollectionId;
+use crate::domain::share::ShareSecret;
+use crate::domain::stats::{View, Visitor};
+use sqlx::Row;
+use crate::data::model::GetClip;
+use crate::domain::user::field::{UserId, Username};
+use crate::web::api::ApiKey;
+
+// aliases resolve to the clip they belong to
+pub async fn get_clip<M: Into<GetClip>>(model: M, pool: &DbPool) -> Result<model::Clip> {
+    let model = model.into();
+    let shortcode = model.shortcode.as_str();
+    Ok(sqlx::query_as!(
+        model::Clip,
+        r#"SELECT *, (SELECT group_concat(tag) FROM clip_tags WHERE clip_tags.clip_id = clips.clip_id) AS "tags?: String"
+           FROM clips WHERE shortcode = ? OR clip_id = (SELECT clip_id FROM aliases WHERE shortcode = ?)"#,
+        shortcode,
+        shortcode
+      )
+        .fetch_one(pool)
+        .await?)
+}
+
+// using a model as param to avoid having to pass a whole bunch of clip properties as params.
+// the clip, its grants and its tags are saved together or not at all
+pub async fn new_clip<M: Into<model::NewClip>>(
+    model: M,
+    shortcodes: &ShortCodeGenerator,
+    quota: &Quota,
+    pool: &DbPool,
+) -> Result<model::Clip> {
+    let model = model.into();
+    let mut tx = pool.begin().await?;
+    let shortcode = insert_clip(&model, shortcodes, &mut tx).await?;
+    if let Some(api_key) = &model.api_key {
+        check_quota(api_key, quota, &mut tx).await?;
+    }
+    replace_grants(&model.clip_id, &model.grants, &mut tx).await?;
+    replace_tags(&model.clip_id, &model.tags, &mut tx).await?;
+    tx.commit().await?;
+    get_clip(shortcode, pool).await
+}
+
+// generated shortcodes are retried on collision, requested ones are not.
+// the alias check is part of the insert, a separate read first could not be upgraded to a write under contention
+async fn insert_clip(model: &model::NewClip, shortcodes: &ShortCodeGenerator, tx: &mut Tx<'_>) -> Result<String> {
+    for _ in 0..ShortCodeGenerator::MAX_ATTEMPTS {
+        let shortcode = match &model.shortcode {
+            Some(shortcode) => shortcode.clone(),
+            None => shortcodes.generate().into_inner(),
+        };
+        let inserted = sqlx::query!(
+            r#"INSERT INTO clips (
+                clip_id,
+                shortcode,
+                content,
+                title,
+                posted,
+                expires,
+                password,
+                hits,
+                api_key,
+                user_id,
+                visibility,
+                burn_after)
+            SELECT ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+            WHERE NOT EXISTS(SELECT 1 FROM aliases WHERE shortcode = ?)
+            ON CONFLICT (shortcode) DO NOTHING"#,
+            model.clip_id,
+            shortcode,
+            model.content,
+            model.title,
+            model.posted,
+            model.expires,
+            model.password,
+            0,
+            model.api_key,
+            model.user_id,
+            model.visibility,
+            model.burn_after,
+            shortcode)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected() > 0;
+        if inserted {
+            return Ok(shortcode);
+        }
+        if model.shortcode.is_some() {
+            return Err(DataError::ShortCodeTaken(shortcode));
+        }
+    }
+    Err(DataError::ShortCodesExhausted(ShortCodeGenerator::MAX_ATTEMPTS))
+}
+
+// checked after the write: the transaction holds the write lock by then,
+// so concurrent clips of the same API key are counted one after the other
+async fn check_quota(api_key: &[u8], quota: &Quota, tx: &mut Tx<'_>) -> Result<()> {
+    let usage = usage(api_key, &mut *tx).await?;
+    Ok(quota.check(&usage.into())?)
+}
+
+// grants and tags are only replaced when the model carries them,
+// size changes are accounted to the API key that created the clip
+pub async fn update_clip<M: Into<model::UpdateClip>>(model: M, quota: &Quota, pool: &DbPool) -> Result<model::Clip> {
+    let model = model.into();
+    let mut tx = pool.begin().await?;
+    // sqlite has no SELECT ... FOR UPDATE: a write that changes nothing takes the write lock,
+    // so the usage read before the update can't change under it
+    let locked = sqlx::query!("UPDATE clips SET api_key = api_key WHERE shortcode = ?", model.shortcode)
+        .execute(&mut tx)
+        .await?;
+    if locked.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+    let clip = sqlx::query!("SELECT clip_id, api_key FROM clips WHERE shortcode = ?", model.shortcode)
+        .fetch_one(&mut tx)
+        .await?;
+    let before = match &clip.api_key {
+        Some(api_key) => Some(usage(api_key, &mut tx).await?),
+        None => None,
+    };
+    sqlx::query!(
+        r#"UPDATE clips SET
+                content = ?,
+                expires = ?,
+                password = ?,
+                title = ?,
+                visibility = ?,
+                burn_after = ?
+           WHERE shortcode = ?"#,
+        model.content,
+        model.expires,
+        model.password,
+        model.title,
+        model.visibility,
+        model.burn_after,
+        model.shortcode
+        )
+        .execute(&mut tx)
+        .await?;
+    if let (Some(api_key), Some(before)) = (&clip.api_key, before) {
+        let after = usage(api_key, &mut tx).await?;
+        quota.check_change(&before.into(), &after.into())?;
+    }
+    let clip_id = clip.clip_id;
+    if let Some(grants) = &model.grants {
+        replace_grants(&clip_id, grants, &mut tx).await?;
+    }
+    if let Some(tags) = &model.tags {
+        replace_tags(&clip_id, tags, &mut tx).await?;
+    }
+    tx.commit().await?;
+
+    get_clip(model.shortcode, pool).await
+}
+
+// a single statement for any number of clips: the increments are passed as one JSON object
+pub async fn increase_hit_counts(hits: Vec<(ClipId, u32)>, tx: &mut Tx<'_>) -> Result<()> {
+    let hits = hits
+        .into_iter()
+        .map(|(clip_id, hits)| (String::from(clip_id.into_inner()), serde_json::Value::from(hits)))
+        .collect::<serde_json::Map<_, _>>();
+    let hits = serde_json::Value::Object(hits).to_string();
+    sqlx::query!(
+        r#"UPDATE clips SET hits = hits + (SELECT value FROM json_each(?) WHERE key = clips.clip_id)
+           WHERE clip_id IN (SELECT key FROM json_each(?))"#,
+        hits,
+        hits
+      )
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// adds views to the hourly buckets of their clips, in a single statement like the hit counts
+pub async fn record_views(views: Vec<(ClipId, View, u32)>, tx: &mut Tx<'_>) -> Result<()> {
+    let views = views
+        .into_iter()
+        .map(|(clip_id, view, views)| serde_json::json!({
+            "clip_id": String::from(clip_id.into_inner()),
+            "bucket": view.hour,
+            "referrer": view.referrer.unwrap_or_default(),
+            "agent": view.agent.to_string(),
+            "views": views,
+        }))
+        .collect::<Vec<_>>();
+    let views = serde_json::Value::Array(views).to_string();
+    sqlx::query!(
+        r#"INSERT INTO clip_views (clip_id, bucket, referrer, agent, views)
+           SELECT clips.clip_id,
+                  json_extract(view.value, '$.bucket'),
+                  json_extract(view.value, '$.referrer'),
+                  json_extract(view.value, '$.agent'),
+                  json_extract(view.value, '$.views')
+           FROM json_each(?) AS view
+           JOIN clips ON clips.clip_id = json_extract(view.value, '$.clip_id')
+           WHERE true
+           ON CONFLICT (clip_id, bucket, referrer, agent) DO UPDATE SET views = views + excluded.views"#,
+        views
+      )
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// counts visitors not yet seen on their day, then remembers them
+pub async fn record_visitors(visitors: Vec<(ClipId, Visitor)>, tx: &mut Tx<'_>) -> Result<()> {
+    let visitors = visitors
+        .into_iter()
+        .map(|(clip_id, visitor)| serde_json::json!({
+            "clip_id": String::from(clip_id.into_inner()),
+            "day": visitor.day,
+            "visitor": visitor.id,
+        }))
+        .collect::<Vec<_>>();
+    let visitors = serde_json::Value::Array(visitors).to_string();
+    sqlx::query!(
+        r#"UPDATE clips SET visitors = visitors + (
+               SELECT count(*) FROM json_each(?) AS new
+               WHERE json_extract(new.value, '$.clip_id') = clips.clip_id
+                 AND NOT EXISTS (SELECT 1 FROM clip_visitors
+                                 WHERE clip_visitors.clip_id = clips.clip_id
+                                   AND clip_visitors.day = json_extract(new.value, '$.day')
+                                   AND clip_visitors.visitor = json_extract(new.value, '$.visitor')))
+           WHERE clip_id IN (SELECT json_extract(value, '$.clip_id') FROM json_each(?))"#,
+        visitors,
+        visitors
+      )
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        r#"INSERT OR IGNORE INTO clip_visitors (clip_id, day, visitor)
+           SELECT clips.clip_id, json_extract(new.value, '$.day'), json_extract(new.value, '$.visitor')
+           FROM json_each(?) AS new
+           JOIN clips ON clips.clip_id = json_extract(new.value, '$.clip_id')"#,
+        visitors
+      )
+        .execute(&mut *tx)
+        .await?;
+    Ok(())
+}
+
+// the key visitors of `day` are hashed with, `candidate` becomes it unless another replica was first
+pub async fn visitor_salt(day: i64, candidate: &[u8], pool: &DbPool) -> Result<Vec<u8>> {
+    sqlx::query!("INSERT INTO visitor_salts (day, salt) VALUES (?, ?) ON CONFLICT (day) DO NOTHING", day, candidate)
+        .execute(pool)
+        .await?;
+    Ok(sqlx::query_scalar!("SELECT salt FROM visitor_salts WHERE day = ?", day)
+        .fetch_one(pool)
+        .await?)
+}
+
+// visitors of past days were hashed with a key that is gone, they can't be recognized anymore
+pub async fn delete_stale_visitors(today: i64, pool: &DbPool) -> Result<u64> {
+    sqlx::query!("DELETE FROM visitor_salts WHERE day < ?", today)
+        .execute(pool)
+        .await?;
+    Ok(sqlx::query!("DELETE FROM clip_visitors WHERE day < ?", today)
+        .execute(pool)
+        .await?
+        .rows_affected())
+}
+
+// hourly buckets that start before `since`
+pub async fn delete_old_views(since: i64, pool: &DbPool) -> Result<u64> {
+    Ok(sqlx::query!("DELETE FROM clip_views WHERE bucket < ?", since)
+        .execute(pool)
+        .await?
+        .rows_affected())
+}
+
+// hourly buckets starting at `since` or later
+pub async fn get_clip_views(clip_id: &ClipId, since: i64, pool: &DbPool) -> Result<Vec<model::ClipView>> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    Ok(sqlx::query_as!(
+        model::ClipView,
+        "SELECT bucket, referrer, agent, views FROM clip_views WHERE clip_id = ? AND bucket >= ? ORDER BY bucket",
+        clip_id,
+        since
+      )
+        .fetch_all(pool)
+        .await?)
+}
+
+/// count a wrong password entered for a clip
+pub async fn record_failed_attempt(shortcode: &ShortCode, pool: &DbPool) -> Result<model::Clip> {
+    let raw = shortcode.as_str();
+    sqlx::query!("UPDATE clips SET failed_attempts = failed_attempts + 1 WHERE shortcode = ?", raw)
+        .execute(pool)
+        .await?;
+    get_clip(shortcode.clone(), pool).await
+}
+
+pub async fn delete_clip(shortcode: &ShortCode, pool: &DbPool) -> Result<()> {
+    let shortcode = shortcode.as_str();
+    sqlx::query!("DELETE FROM clips WHERE shortcode = ?", shortcode)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// replace the key share links of a clip are signed with, invalidating the previous ones
+pub async fn save_share_secret(shortcode: &ShortCode, secret: ShareSecret, pool: &DbPool) -> Result<()> {
+    let shortcode = shortcode.as_str();
+    let secret = secret.into_inner();
+    sqlx::query!("UPDATE clips SET share_secret = ? WHERE shortcode = ?", secret, shortcode)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn get_aliases(clip_id: &ClipId, pool: &DbPool) -> Result<Vec<String>> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    Ok(sqlx::query!("SELECT shortcode FROM aliases WHERE clip_id = ? ORDER BY created", clip_id)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| row.shortcode)
+        .collect())
+}
+
+pub async fn add_alias(clip_id: &ClipId, alias: &ShortCode, pool: &DbPool) -> Result<()> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    let (alias, now) = (alias.as_str(), chrono::Utc::now().timestamp());
+    // one statement, so a concurrent clip or alias with the same shortcode is a conflict rather than an error
+    let inserted = sqlx::query!(
+        r#"INSERT INTO aliases (shortcode, clip_id, created)
+        SELECT ?, ?, ?
+        WHERE NOT EXISTS(SELECT 1 FROM clips WHERE shortcode = ?)
+        ON CONFLICT (shortcode) DO NOTHING"#,
+        alias,
+        clip_id,
+        now,
+        alias)
+        .execute(pool)
+        .await?
+        .rows_affected() > 0;
+    if !inserted {
+        return Err(DataError::ShortCodeTaken(alias.to_owned()));
+    }
+    Ok(())
+}
+
+pub async fn remove_alias(clip_id: &ClipId, alias: &ShortCode, pool: &DbPool) -> Result<()> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    let alias = alias.as_str();
+    match sqlx::query!("DELETE FROM aliases WHERE shortcode = ? AND clip_id = ?", alias, clip_id)
+        .execute(pool)
+        .await?
+        .rows_affected() {
+        0 => Err(sqlx::Error::RowNotFound.into()),
+        _ => Ok(()),
+    }
+}
+
+/// change the primary shortcode of a clip, keeping the current one as an alias
+pub async fn rename_clip(clip_id: &ClipId, shortcode: &ShortCode, renamed: &ShortCode, pool: &DbPool) -> Result<model::Clip> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    let (shortcode, renamed, now) = (shortcode.as_str(), renamed.as_str(), chrono::Utc::now().timestamp());
+    let mut tx = pool.begin().await?;
+    // going back to a former shortcode of the clip
+    sqlx::query!("DELETE FROM aliases WHERE shortcode = ? AND clip_id = ?", renamed, clip_id)
+        .execute(&mut tx)
+        .await?;
+    let taken = sqlx::query!(
+        r#"SELECT EXISTS(SELECT 1 FROM clips WHERE shortcode = ?)
+            OR EXISTS(SELECT 1 FROM aliases WHERE shortcode = ?) AS "taken!: bool""#,
+        renamed,
+        renamed)
+        .fetch_one(&mut tx)
+        .await?
+        .taken;
+    if taken {
+        return Err(DataError::ShortCodeTaken(renamed.to_owned()));
+    }
+    sqlx::query!("UPDATE clips SET shortcode = ? WHERE clip_id = ?", renamed, clip_id)
+        .execute(&mut tx)
+        .await?;
+    sqlx::query!("INSERT INTO aliases (shortcode, clip_id, created) VALUES (?, ?, ?)", shortcode, clip_id, now)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+    get_clip(renamed.to_owned(), pool).await
+}
+
+pub async fn get_collection(shortcode: &ShortCode, pool: &DbPool) -> Result<model::Collection> {
+    let shortcode = shortcode.as_str();
+    Ok(sqlx::query_as!(model::Collection, "SELECT * FROM collections WHERE shortcode = ?", shortcode)
+        .fetch_one(pool)
+        .await?)
+}
+
+// generated shortcodes are retried on collision, requested ones are not
+pub async fn new_collection<M: Into<model::NewCollection>>(model: M, shortcodes: &ShortCodeGenerator, pool: &DbPool) -> Result<model::Collection> {
+    let model = model.into();
+    for _ in 0..ShortCodeGenerator::MAX_ATTEMPTS {
+        let shortcode = match &model.shortcode {
+            Some(shortcode) => shortcode.clone(),
+            None => shortcodes.generate().into_inner(),
+        };
+        let inserted = sqlx::query!(
+            r#"INSERT INTO collections (collection_id, shortcode, title, password, posted, api_key, user_id)
+            VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+            model.collection_id,
+            shortcode,
+            model.title,
+            model.password,
+            model.posted,
+            model.api_key,
+            model.user_id)
+            .execute(pool)
+            .await;
+        match inserted {
+            Err(sqlx::Error::Database(e)) if e.message().contains("UNIQUE constraint failed: collections.shortcode") => {
+                if model.shortcode.is_some() {
+                    return Err(DataError::ShortCodeTaken(shortcode));
+                }
+            }
+            Err(e) => return Err(e.into()),
+            Ok(_) => return get_collection(&ShortCode::from(shortcode), pool).await,
+        }
+    }
+    Err(DataError::ShortCodesExhausted(ShortCodeGenerator::MAX_ATTEMPTS))
+}
+
+// in the order they were added
+pub async fn get_collection_clips(collection_id: &CollectionId, pool: &DbPool) -> Result<Vec<model::Clip>> {
+    let collection_id: String = collection_id.clone().into_inner().into();
+    Ok(sqlx::query_as!(
+        model::Clip,
+        r#"SELECT clips.*, (SELECT group_concat(tag) FROM clip_tags WHERE clip_tags.clip_id = clips.clip_id) AS "tags?: String"
+            FROM clips
+            JOIN collection_clips ON collection_clips.clip_id = clips.clip_id
+            WHERE collection_clips.collection_id = ?
+                AND (clips.expires IS NULL OR clips.expires > strftime('%s', 'now'))
+            ORDER BY collection_clips.added, collection_clips.rowid"#,
+        collection_id)
+        .fetch_all(pool)
+        .await?)
+}
+
+// adding a clip twice keeps it in its original place
+pub async fn add_collection_clip(collection_id: &CollectionId, clip_id: &ClipId, pool: &DbPool) -> Result<()> {
+    let collection_id: String = collection_id.clone().into_inner().into();
+    let clip_id: String = clip_id.clone().into_inner().into();
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query!(
+        "INSERT OR IGNORE INTO collection_clips (collection_id, clip_id, added) VALUES (?, ?, ?)",
+        collection_id,
+        clip_id,
+        now)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn remove_collection_clip(collection_id: &CollectionId, clip_id: &ClipId, pool: &DbPool) -> Result<()> {
+    let collection_id: String = collection_id.clone().into_inner().into();
+    let clip_id: String = clip_id.clone().into_inner().into();
+    match sqlx::query!("DELETE FROM collection_clips WHERE collection_id = ? AND clip_id = ?", collection_id, clip_id)
+        .execute(pool)
+        .await?
+        .rows_affected() {
+        0 => Err(sqlx::Error::RowNotFound.into()),
+        _ => Ok(()),
+    }
+}
+
+/// save API_KEY to DB, optionally on behalf of a user
+pub async fn save_api_key(api_key: ApiKey, user_id: Option<UserId>, pool: &DbPool) -> Result<ApiKey> {
+    let bytes = api_key.clone().into_inner();
+    let user_id: Option<String> = user_id.map(|id| id.into_inner().into());
+    sqlx::query!("INSERT INTO api_keys (api_key, user_id) VALUES (?, ?)", bytes, user_id)
+        .execute(pool)
+        .await?;
+    Ok(api_key)
+}
+
+/// remove API_KEY from DB
+pub async fn revoke_api_key(api_key: ApiKey, pool: &DbPool) -> Result<RevocationStatus> {
+    let bytes = api_key.clone().into_inner();
+    Ok(sqlx::query!("DELETE FROM api_keys where api_key == ?", bytes)
+        .execute(pool)
+        .await
+        .map(|res| match res.rows_affected() {
+            0 => RevocationStatus::NotFound,
+            _ => RevocationStatus::Revoked
+        })?
+    )
+}
+
+pub async fn api_key_is_valid(api_key: ApiKey, pool: &DbPool) -> Result<bool> {
+    let bytes = api_key.clone().into_inner();
+    Ok(sqlx::query("SELECT COUNT(api_key) FROM api_keys  where api_key == ?")
+        .bind(bytes)
+        .fetch_one(pool)
+        .await
+        .map(|row| {
+            let count: u32 = row.get(0);
+            count > 0
+        })?
+    )
+}
+
+/// number and total size of the live clips created with API_KEY
+pub async fn get_usage(api_key: ApiKey, pool: &DbPool) -> Result<model::Usage> {
+    usage(&api_key.into_inner(), pool).await
+}
+
+async fn usage<'e, E: sqlx::Executor<'e, Database = Backend>>(api_key: &[u8], executor: E) -> Result<model::Usage> {
+    Ok(sqlx::query_as!(
+        model::Usage,
+        r#"SELECT
+                COUNT(*) AS "clips!: i64",
+                COALESCE(SUM(LENGTH(CAST(content AS BLOB))), 0) AS "bytes!: i64"
+           FROM clips
+           WHERE api_key = ? AND (expires IS NULL OR expires > strftime('%s', 'now'))"#,
+        api_key
+      )
+        .fetch_one(executor)
+        .await?)
+}
+
+pub async fn new_user<M: Into<model::NewUser>>(model: M, pool: &DbPool) -> Result<model::User> {
+    let model = model.into();
+    let inserted = sqlx::query!(
+        "INSERT INTO users (user_id, username, password_hash, created) VALUES (?, ?, ?, ?)",
+        model.user_id,
+        model.username,
+        model.password_hash,
+        model.created)
+        .execute(pool)
+        .await;
+    match inserted {
+        Err(sqlx::Error::Database(e)) if e.message().contains("UNIQUE constraint failed: users.username") => {
+            return Err(DataError::UsernameTaken(model.username));
+        }
+        inserted => inserted?,
+    };
+
+    Ok(sqlx::query_as!(model::User, "SELECT * FROM users WHERE user_id = ?", model.user_id)
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn get_user_by_name(username: &Username, pool: &DbPool) -> Result<model::User> {
+    let username = username.as_str();
+    Ok(sqlx::query_as!(model::User, "SELECT * FROM users WHERE username = ?", username)
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn get_user(user_id: UserId, pool: &DbPool) -> Result<model::User> {
+    let user_id: String = user_id.into_inner().into();
+    Ok(sqlx::query_as!(model::User, "SELECT * FROM users WHERE user_id = ?", user_id)
+        .fetch_one(pool)
+        .await?)
+}
+
+/// replace the users and API keys a clip is shared with
+async fn replace_grants(clip_id: &str, grants: &[model::Grant], tx: &mut Tx<'_>) -> Result<()> {
+    sqlx::query!("DELETE FROM clip_grants WHERE clip_id = ?", clip_id)
+        .execute(&mut *tx)
+        .await?;
+    // sharing twice with the same user or API key is a single grant
+    for grant in grants {
+        sqlx::query!(
+            "INSERT INTO clip_grants (clip_id, user_id, api_key) VALUES (?, ?, ?) ON CONFLICT DO NOTHING",
+            clip_id,
+            grant.user_id,
+            grant.api_key)
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
+// replaces all tags of the clip
+async fn replace_tags(clip_id: &str, tags: &[String], tx: &mut Tx<'_>) -> Result<()> {
+    sqlx::query!("DELETE FROM clip_tags WHERE clip_id = ?", clip_id)
+        .execute(&mut *tx)
+        .await?;
+    for tag in tags {
+        sqlx::query!("INSERT INTO clip_tags (clip_id, tag) VALUES (?, ?)", clip_id, tag)
+            .execute(&mut *tx)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn get_grants(clip_id: &ClipId, pool: &DbPool) -> Result<Vec<model::Grant>> {
+    let clip_id: String = clip_id.clone().into_inner().into();
+    Ok(sqlx::query_as!(model::Grant, "SELECT user_id, api_key FROM clip_grants WHERE clip_id = ?", clip_id)
+        .fetch_all(pool)
+        .await?)
+}
+
+/// user the API key was created by, if any
+pub async fn get_api_key_user(api_key: ApiKey, pool: &DbPool) -> Result<Option<UserId>> {
+    use std::str::FromStr;
+
+    let bytes = api_key.into_inner();
+    Ok(sqlx::query!("SELECT user_id FROM api_keys WHERE api_key = ?", bytes)
+        .fetch_optional(pool)
+        .await?
+        .and_then(|row| row.user_id)
+        .and_then(|user_id| UserId::from_str(user_id.as_str()).ok())
+    )
+}
+
+pub async fn get_user_by_identity(issuer: &str, subject: &str, pool: &DbPool) -> Result<model::User> {
+    Ok(sqlx::query_as!(
+        model::User,
+        r#"SELECT users.* FROM users
+           JOIN user_identities ON user_identities.user_id = users.user_id
+           WHERE user_identities.issuer = ? AND user_identities.subject = ?"#,
+        issuer,
+        subject
+      )
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn link_identity(issuer: &str, subject: &str, user_id: UserId, pool: &DbPool) -> Result<()> {
+    let user_id: String = user_id.into_inner().into();
+    sqlx::query!(
+        "INSERT INTO user_identities (issuer, subject, user_id) VALUES (?, ?, ?)",
+        issuer,
+        subject,
+        user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn new_session(session_id: &str, user_id: UserId, expires: i64, pool: &DbPool) -> Result<()> {
+    let user_id: String = user_id.into_inner().into();
+    sqlx::query!(
+        "INSERT INTO sessions (session_id, user_id, expires) VALUES (?, ?, ?)",
+        session_id,
+        user_id,
+        expires)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// expired sessions are as good as gone, whether or not they were deleted yet
+pub async fn get_session(session_id: &str, pool: &DbPool) -> Result<model::Session> {
+    Ok(sqlx::query_as!(
+        model::Session,
+        "SELECT user_id, expires FROM sessions WHERE session_id = ? AND expires > strftime('%s', 'now')",
+        session_id
+      )
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn delete_session(session_id: &str, pool: &DbPool) -> Result<()> {
+    sqlx::query!("DELETE FROM sessions WHERE session_id = ?", session_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn delete_expired_sessions(pool: &DbPool) -> Result<u64> {
+    Ok(sqlx::query!("DELETE FROM sessions WHERE expires <= strftime('%s', 'now')")
+        .execute(pool)
+        .await?
+        .rows_affected())
+}
+
+/// clips created by a user, either through the web UI or with one of their API keys,
+/// optionally only those carrying `tag`
+pub async fn get_user_clips(user_id: UserId, tag: Option<&str>, pool: &DbPool) -> Result<Vec<model::Clip>> {
+    let user_id: String = user_id.into_inner().into();
+    Ok(sqlx::query_as!(
+        model::Clip,
+        r#"SELECT *, (SELECT group_concat(tag) FROM clip_tags WHERE clip_tags.clip_id = clips.clip_id) AS "tags?: String"
+           FROM clips
+           WHERE (user_id = ? OR api_key IN (SELECT api_key FROM api_keys WHERE user_id = ?))
+             AND (? IS NULL OR clip_id IN (SELECT clip_id FROM clip_tags WHERE tag = ?))
+           ORDER BY posted DESC"#,
+        user_id,
+        user_id,
+        tag,
+        tag
+      )
+        .fetch_all(pool)
+        .await?)
+}
+
+/// clips created with an API key, optionally only those carrying `tag`
+pub async fn get_api_key_clips(api_key: ApiKey, tag: Option<&str>, pool: &DbPool) -> Result<Vec<model::Clip>> {
+    let api_key = api_key.into_inner();
+    Ok(sqlx::query_as!(
+        model::Clip,
+        // the api_key index hides from sqlx that visitors is not null
+        r#"SELECT clip_id, shortcode, content, title, posted, expires, password, hits, api_key, user_id, visibility,
+                  share_secret, failed_attempts, burn_after, visitors AS "visitors!",
+                  (SELECT group_concat(tag) FROM clip_tags WHERE clip_tags.clip_id = clips.clip_id) AS "tags?: String"
+           FROM clips
+           WHERE api_key = ?
+             AND (? IS NULL OR clip_id IN (SELECT clip_id FROM clip_tags WHERE tag = ?))
+           ORDER BY posted DESC"#,
+        api_key,
+        tag,
+        tag
+      )
+        .fetch_all(pool)
+        .await?)
+}
+
+// newest migration applied, None on an empty database
+pub async fn schema_version(pool: &DbPool) -> Result<Option<i64>> {
+    Ok(sqlx::query_scalar!(r#"SELECT max(version) AS "version?: i64" FROM _sqlx_migrations WHERE success = 1"#)
+        .fetch_one(pool)
+        .await?)
+}
+
+pub async fn ping(pool: &DbPool) -> Result<()> {
+    sqlx::query!("SELECT 1 AS one").fetch_one(pool).await?;
+    Ok(())
+}
+
+pub async fn delete_expired(pool:&DbPool) -> Result<u64> {
+    Ok(sqlx::query!(r#"DELETE FROM clips WHERE strftime('%s', 'now') > expires"#)
+        .execute(pool)
+        .await?
+        .rows_affected()
+    )
+}
@@ -0,0 +1,611 @@
+use super::{ApiKeyRepository, ClipRepository, CollectionRepository, HealthRepository, Result, UserRepository};
+use crate::data::model::{self, GetClip};
+use crate::data::query::RevocationStatus;
+use crate::data::DataError;
+use crate::domain::clip::field::{ClipId, ShortCodeGenerator};
+use crate::domain::collection::CollectionId;
+use crate::domain::quota::{Quota, Usage};
+use crate::domain::share::ShareSecret;
+use crate::domain::stats::{View, Visitor};
+use crate::domain::user::field::{UserId, Username};
+use crate::web::api::ApiKey;
+use crate::ShortCode;
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+
+// keeps everything in process memory, following the semantics of the SQL queries:
+// aliases, cascading deletes and RowNotFound for missing rows. nothing survives a restart
+#[derive(Default)]
+pub struct MemoryStore(Mutex<Tables>);
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[derive(Default)]
+struct Tables {
+    // the tags of a clip live in `tags`, like in clip_tags
+    clips: Vec<model::Clip>,
+    // shortcode and clip_id, in the order they were created
+    aliases: Vec<(String, String)>,
+    grants: Vec<(String, model::Grant)>,
+    tags: Vec<(String, String)>,
+    // clip_id, bucket, referrer and agent to views
+    views: BTreeMap<(String, i64, String, String), i64>,
+    // clip_id, day and visitor
+    visitors: HashSet<(String, i64, String)>,
+    // day to the key its visitors are hashed with
+    salts: HashMap<i64, Vec<u8>>,
+    // API key to the user it was created by
+    api_keys: HashMap<Vec<u8>, Option<String>>,
+    users: Vec<model::User>,
+    // issuer and subject to user_id
+    identities: HashMap<(String, String), String>,
+    sessions: HashMap<String, model::Session>,
+    collections: Vec<model::Collection>,
+    // collection_id and clip_id, in the order they were added
+    collection_clips: Vec<(String, String)>,
+}
+
+impl Tables {
+    // aliases resolve to the clip they belong to
+    fn clip_id_of(&self, shortcode: &str) -> Option<String> {
+        self.clips
+            .iter()
+            .find(|clip| clip.shortcode == shortcode)
+            .map(|clip| clip.clip_id.clone())
+            .or_else(|| self.aliases.iter().find(|(alias, _)| alias == shortcode).map(|(_, clip_id)| clip_id.clone()))
+    }
+
+    fn clip(&self, clip_id: &str) -> Result<model::Clip> {
+        let clip = self.clips.iter().find(|clip| clip.clip_id == clip_id).ok_or_else(not_found)?;
+        Ok(self.with_tags(clip))
+    }
+
+    fn clip_mut(&mut self, shortcode: &str) -> Option<&mut model::Clip> {
+        self.clips.iter_mut().find(|clip| clip.shortcode == shortcode)
+    }
+
+    fn clip_by_id_mut(&mut self, clip_id: &str) -> Option<&mut model::Clip> {
+        self.clips.iter_mut().find(|clip| clip.clip_id == clip_id)
+    }
+
+    // comma separated like group_concat, None without tags
+    fn with_tags(&self, clip: &model::Clip) -> model::Clip {
+        let tags = self.tags
+            .iter()
+            .filter(|(clip_id, _)| *clip_id == clip.clip_id)
+            .map(|(_, tag)| tag.as_str())
+            .collect::<Vec<_>>();
+        let tags = Some(tags.join(",")).filter(|tags| !tags.is_empty());
+        model::Clip { tags, ..clip.clone() }
+    }
+
+    // primary shortcode or alias
+    fn shortcode_taken(&self, shortcode: &str) -> bool {
+        self.clip_id_of(shortcode).is_some()
+    }
+
+    // live clips of the API key
+    fn usage(&self, api_key: &[u8]) -> Usage {
+        let now = now();
+        let live = self.clips
+            .iter()
+            .filter(|clip| clip.api_key.as_deref() == Some(api_key) && clip.expires.is_none_or(|expires| expires.timestamp() > now))
+            .collect::<Vec<_>>();
+        Usage {
+            clips: live.len() as u64,
+            bytes: live.iter().map(|clip| clip.content.len() as u64).sum(),
+        }
+    }
+
+    // sharing twice with the same user or API key is a single grant
+    fn replace_grants(&mut self, clip_id: &str, grants: Vec<model::Grant>) {
+        self.grants.retain(|(id, _)| id != clip_id);
+        for grant in grants {
+            let granted = self.grants.iter().any(|(id, granted)| {
+                id == clip_id && granted.user_id == grant.user_id && granted.api_key == grant.api_key
+            });
+            if !granted {
+                self.grants.push((clip_id.to_owned(), grant));
+            }
+        }
+    }
+
+    fn replace_tags(&mut self, clip_id: &str, tags: Vec<String>) {
+        self.tags.retain(|(id, _)| id != clip_id);
+        self.tags.extend(tags.into_iter().map(|tag| (clip_id.to_owned(), tag)));
+    }
+
+    // removes the rows referencing the deleted clips along with them
+    fn delete_clips<P: Fn(&model::Clip) -> bool>(&mut self, delete: P) -> u64 {
+        let deleted = self.clips
+            .iter()
+            .filter(|clip| delete(clip))
+            .map(|clip| clip.clip_id.clone())
+            .collect::<HashSet<_>>();
+        self.clips.retain(|clip| !deleted.contains(&clip.clip_id));
+        self.aliases.retain(|(_, clip_id)| !deleted.contains(clip_id));
+        self.grants.retain(|(clip_id, _)| !deleted.contains(clip_id));
+        self.tags.retain(|(clip_id, _)| !deleted.contains(clip_id));
+        self.views.retain(|(clip_id, ..), _| !deleted.contains(clip_id));
+        self.visitors.retain(|(clip_id, ..)| !deleted.contains(clip_id));
+        self.collection_clips.retain(|(_, clip_id)| !deleted.contains(clip_id));
+        deleted.len() as u64
+    }
+
+    fn user(&self, user_id: &str) -> Result<model::User> {
+        self.users.iter().find(|user| user.user_id == user_id).cloned().ok_or_else(not_found)
+    }
+
+    fn collection(&self, shortcode: &str) -> Result<model::Collection> {
+        self.collections.iter().find(|collection| collection.shortcode == shortcode).cloned().ok_or_else(not_found)
+    }
+}
+
+fn not_found() -> DataError {
+    sqlx::Error::RowNotFound.into()
+}
+
+fn time(timestamp: i64) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp(timestamp, 0)
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn clip_id(clip_id: &ClipId) -> String {
+    clip_id.clone().into_inner().into()
+}
+
+fn collection_id(collection_id: &CollectionId) -> String {
+    collection_id.clone().into_inner().into()
+}
+
+#[async_trait]
+impl ClipRepository for MemoryStore {
+    async fn get_clip<M: Into<GetClip> + Send>(&self, model: M) -> Result<model::Clip> {
+        let tables = self.0.lock();
+        let clip_id = tables.clip_id_of(&model.into().shortcode).ok_or_else(not_found)?;
+        tables.clip(&clip_id)
+    }
+
+    async fn new_clip<M: Into<model::NewClip> + Send>(&self, model: M, shortcodes: &ShortCodeGenerator, quota: &Quota) -> Result<model::Clip> {
+        let model = model.into();
+        let mut tables = self.0.lock();
+        if let Some(api_key) = &model.api_key {
+            quota.check(&tables.usage(api_key).with_clip(model.content.len() as u64))?;
+        }
+        for _ in 0..ShortCodeGenerator::MAX_ATTEMPTS {
+            let shortcode = match &model.shortcode {
+                Some(shortcode) => shortcode.clone(),
+                None => shortcodes.generate().into_inner(),
+            };
+            if !tables.shortcode_taken(&shortcode) {
+                tables.clips.push(model::Clip {
+                    clip_id: model.clip_id.clone(),
+                    shortcode,
+                    content: model.content.clone(),
+                    title: model.title.clone(),
+                    posted: time(model.posted),
+                    expires: model.expires.map(time),
+                    password: model.password.clone(),
+                    hits: 0,
+                    api_key: model.api_key.clone(),
+                    user_id: model.user_id.clone(),
+                    visibility: model.visibility.clone(),
+                    share_secret: None,
+                    failed_attempts: 0,
+                    burn_after: model.burn_after,
+                    visitors: 0,
+                    tags: None,
+                });
+                tables.replace_grants(&model.clip_id, model.grants);
+                tables.replace_tags(&model.clip_id, model.tags);
+                return tables.clip(&model.clip_id);
+            }
+            if model.shortcode.is_some() {
+                return Err(DataError::ShortCodeTaken(shortcode));
+            }
+        }
+        Err(DataError::ShortCodesExhausted(ShortCodeGenerator::MAX_ATTEMPTS))
+    }
+
+    async fn update_clip<M: Into<model::UpdateClip> + Send>(&self, model: M, quota: &Quota) -> Result<model::Clip> {
+        let model = model.into();
+        let mut tables = self.0.lock();
+        let current = tables.clip_mut(&model.shortcode).ok_or_else(not_found)?.clone();
+        let before = current.api_key.as_deref().map(|api_key| tables.usage(api_key));
+        let clip = tables.clip_mut(&model.shortcode).ok_or_else(not_found)?;
+        clip.content = model.content;
+        clip.expires = model.expires.map(time);
+        clip.password = model.password;
+        clip.title = model.title;
+        clip.visibility = model.visibility;
+        clip.burn_after = model.burn_after;
+        let clip_id = clip.clip_id.clone();
+        if let (Some(api_key), Some(before)) = (&current.api_key, before) {
+            let after = tables.usage(api_key);
+            if let Err(e) = quota.check_change(&before, &after) {
+                *tables.clip_mut(&model.shortcode).ok_or_else(not_found)? = current;
+                return Err(e.into());
+            }
+        }
+        if let Some(grants) = model.grants {
+            tables.replace_grants(&clip_id, grants);
+        }
+        if let Some(tags) = model.tags {
+            tables.replace_tags(&clip_id, tags);
+        }
+        tables.clip(&clip_id)
+    }
+
+    async fn delete_clip(&self, shortcode: &ShortCode) -> Result<()> {
+        self.0.lock().delete_clips(|clip| clip.shortcode == shortcode.as_str());
+        Ok(())
+    }
+
+    async fn record_failed_attempt(&self, shortcode: &ShortCode) -> Result<model::Clip> {
+        let mut tables = self.0.lock();
+        let clip = tables.clip_mut(shortcode.as_str()).ok_or_else(not_found)?;
+        clip.failed_attempts += 1;
+        let clip_id = clip.clip_id.clone();
+        tables.clip(&clip_id)
+    }
+
+    async fn save_share_secret(&self, shortcode: &ShortCode, secret: ShareSecret) -> Result<()> {
+        if let Some(clip) = self.0.lock().clip_mut(shortcode.as_str()) {
+            clip.share_secret = Some(secret.into_inner());
+        }
+        Ok(())
+    }
+
+    async fn get_aliases(&self, clip_id: &ClipId) -> Result<Vec<String>> {
+        let clip_id = self::clip_id(clip_id);
+        Ok(self.0.lock()
+            .aliases
+            .iter()
+            .filter(|(_, id)| *id == clip_id)
+            .map(|(alias, _)| alias.clone())
+            .collect())
+    }
+
+    async fn add_alias(&self, clip_id: &ClipId, alias: &ShortCode) -> Result<()> {
+        let mut tables = self.0.lock();
+        if tables.shortcode_taken(alias.as_str()) {
+            return Err(DataError::ShortCodeTaken(alias.as_str().to_owned()));
+        }
+        tables.aliases.push((alias.as_str().to_owned(), self::clip_id(clip_id)));
+        Ok(())
+    }
+
+    async fn remove_alias(&self, clip_id: &ClipId, alias: &ShortCode) -> Result<()> {
+        let clip_id = self::clip_id(clip_id);
+        let mut tables = self.0.lock();
+        let position = tables.aliases
+            .iter()
+            .position(|(shortcode, id)| shortcode == alias.as_str() && *id == clip_id)
+            .ok_or_else(not_found)?;
+        tables.aliases.remove(position);
+        Ok(())
+    }
+
+    async fn rename_clip(&self, clip_id: &ClipId, shortcode: &ShortCode, renamed: &ShortCode) -> Result<model::Clip> {
+        let clip_id = self::clip_id(clip_id);
+        let mut tables = self.0.lock();
+        // going back to a former shortcode of the clip is fine
+        let taken = tables.clips.iter().any(|clip| clip.shortcode == renamed.as_str())
+            || tables.aliases.iter().any(|(alias, id)| alias == renamed.as_str() && *id != clip_id);
+        if taken {
+            return Err(DataError::ShortCodeTaken(renamed.as_str().to_owned()));
+        }
+        let clip = tables.clips.iter_mut().find(|clip| clip.clip_id == clip_id).ok_or_else(not_found)?;
+        clip.shortcode = renamed.as_str().to_owned();
+        tables.aliases.retain(|(alias, _)| alias != renamed.as_str());
+        tables.aliases.push((shortcode.as_str().to_owned(), clip_id.clone()));
+        tables.clip(&clip_id)
+    }
+
+    async fn get_grants(&self, clip_id: &ClipId) -> Result<Vec<model::Grant>> {
+        let clip_id = self::clip_id(clip_id);
+        Ok(self.0.lock()
+            .grants
+            .iter()
+            .filter(|(id, _)| *id == clip_id)
+            .map(|(_, grant)| grant.clone())
+            .collect())
+    }
+
+    async fn get_user_clips(&self, user_id: UserId, tag: Option<&str>) -> Result<Vec<model::Clip>> {
+        let user_id: String = user_id.into_inner().into();
+        let tables = self.0.lock();
+        let owned = |clip: &model::Clip| {
+            clip.user_id.as_ref() == Some(&user_id)
+                || clip.api_key.as_ref().and_then(|api_key| tables.api_keys.get(api_key)) == Some(&Some(user_id.clone()))
+        };
+        Ok(tagged(&tables, owned, tag))
+    }
+
+    async fn get_api_key_clips(&self, api_key: ApiKey, tag: Option<&str>) -> Result<Vec<model::Clip>> {
+        let api_key = Some(api_key.into_inner());
+        let tables = self.0.lock();
+        Ok(tagged(&tables, |clip| clip.api_key == api_key, tag))
+    }
+
+    async fn record_views(
+        &self,
+        hits: Vec<(ClipId, u32)>,
+        views: Vec<(ClipId, View, u32)>,
+        visitors: Vec<(ClipId, Visitor)>,
+    ) -> Result<()> {
+        let mut tables = self.0.lock();
+        for (clip_id, hits) in hits {
+            if let Some(clip) = tables.clip_by_id_mut(&self::clip_id(&clip_id)) {
+                clip.hits += i64::from(hits);
+            }
+        }
+        for (clip_id, view, views) in views {
+            let clip_id = self::clip_id(&clip_id);
+            if tables.clip_by_id_mut(&clip_id).is_some() {
+                let key = (clip_id, view.hour, view.referrer.unwrap_or_default(), view.agent.to_string());
+                *tables.views.entry(key).or_insert(0) += i64::from(views);
+            }
+        }
+        // visitors not yet seen on their day are counted
+        for (clip_id, visitor) in visitors {
+            let clip_id = self::clip_id(&clip_id);
+            if tables.clip_by_id_mut(&clip_id).is_some() && tables.visitors.insert((clip_id.clone(), visitor.day, visitor.id)) {
+                if let Some(clip) = tables.clip_by_id_mut(&clip_id) {
+                    clip.visitors += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_clip_views(&self, clip_id: &ClipId, since: i64) -> Result<Vec<model::ClipView>> {
+        let clip_id = self::clip_id(clip_id);
+        Ok(self.0.lock()
+            .views
+            .iter()
+            .filter(|((id, bucket, ..), _)| *id == clip_id && *bucket >= since)
+            .map(|((_, bucket, referrer, agent), views)| model::ClipView {
+                bucket: *bucket,
+                referrer: referrer.clone(),
+                agent: agent.clone(),
+                views: *views,
+            })
+            .collect())
+    }
+
+    async fn visitor_salt(&self, day: i64, candidate: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.0.lock().salts.entry(day).or_insert_with(|| candidate.to_vec()).clone())
+    }
+
+    async fn delete_old_views(&self, since: i64) -> Result<u64> {
+        let mut tables = self.0.lock();
+        let before = tables.views.len();
+        tables.views.retain(|(_, bucket, ..), _| *bucket >= since);
+        Ok((before - tables.views.len()) as u64)
+    }
+
+    async fn delete_expired(&self) -> Result<u64> {
+        let now = now();
+        Ok(self.0.lock().delete_clips(|clip| clip.expires.is_some_and(|expires| now > expires.timestamp())))
+    }
+
+    async fn delete_stale_visitors(&self, today: i64) -> Result<u64> {
+        let mut tables = self.0.lock();
+        tables.salts.retain(|day, _| *day >= today);
+        let before = tables.visitors.len();
+        tables.visitors.retain(|(_, day, _)| *day >= today);
+        Ok((before - tables.visitors.len()) as u64)
+    }
+}
+
+// newest first, optionally only those carrying `tag`
+fn tagged<F: Fn(&model::Clip) -> bool>(tables: &Tables, include: F, tag: Option<&str>) -> Vec<model::Clip> {
+    let mut clips = tables.clips
+        .iter()
+        .filter(|clip| include(clip))
+        .filter(|clip| tag.is_none_or(|tag| tables.tags.iter().any(|(id, t)| *id == clip.clip_id && t == tag)))
+        .map(|clip| tables.with_tags(clip))
+        .collect::<Vec<_>>();
+    clips.sort_by_key(|clip| std::cmp::Reverse(clip.posted));
+    clips
+}
+
+#[async_trait]
+impl ApiKeyRepository for MemoryStore {
+    async fn save_api_key(&self, api_key: ApiKey, user_id: Option<UserId>) -> Result<ApiKey> {
+        let user_id = user_id.map(|id| id.into_inner().into());
+        self.0.lock().api_keys.insert(api_key.clone().into_inner(), user_id);
+        Ok(api_key)
+    }
+
+    // clips and collections of the key are kept, the clips shared with it are not anymore
+    async fn revoke_api_key(&self, api_key: ApiKey) -> Result<RevocationStatus> {
+        let api_key = api_key.into_inner();
+        let mut tables = self.0.lock();
+        if tables.api_keys.remove(&api_key).is_none() {
+            return Ok(RevocationStatus::NotFound);
+        }
+        let revoked = Some(api_key);
+        for clip in tables.clips.iter_mut().filter(|clip| clip.api_key == revoked) {
+            clip.api_key = None;
+        }
+        for collection in tables.collections.iter_mut().filter(|collection| collection.api_key == revoked) {
+            collection.api_key = None;
+        }
+        tables.grants.retain(|(_, grant)| grant.api_key != revoked);
+        Ok(RevocationStatus::Revoked)
+    }
+
+    async fn api_key_is_valid(&self, api_key: ApiKey) -> Result<bool> {
+        Ok(self.0.lock().api_keys.contains_key(&api_key.into_inner()))
+    }
+
+    async fn get_api_key_user(&self, api_key: ApiKey) -> Result<Option<UserId>> {
+        Ok(self.0.lock()
+            .api_keys
+            .get(&api_key.into_inner())
+            .cloned()
+            .flatten()
+            .and_then(|user_id| UserId::from_str(user_id.as_str()).ok()))
+    }
+
+    async fn get_usage(&self, api_key: ApiKey) -> Result<model::Usage> {
+        let usage = self.0.lock().usage(&api_key.into_inner());
+        Ok(model::Usage { clips: usage.clips as i64, bytes: usage.bytes as i64 })
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryStore {
+    async fn new_user<M: Into<model::NewUser> + Send>(&self, model: M) -> Result<model::User> {
+        let model = model.into();
+        let mut tables = self.0.lock();
+        if tables.users.iter().any(|user| user.username == model.username) {
+            return Err(DataError::UsernameTaken(model.username));
+        }
+        tables.users.push(model::User {
+            user_id: model.user_id.clone(),
+            username: model.username,
+            password_hash: model.password_hash,
+            created: time(model.created),
+        });
+        tables.user(&model.user_id)
+    }
+
+    async fn get_user_by_name(&self, username: &Username) -> Result<model::User> {
+        self.0.lock().users.iter().find(|user| user.username == username.as_str()).cloned().ok_or_else(not_found)
+    }
+
+    async fn get_user(&self, user_id: UserId) -> Result<model::User> {
+        let user_id: String = user_id.into_inner().into();
+        self.0.lock().user(&user_id)
+    }
+
+    async fn get_user_by_identity(&self, issuer: &str, subject: &str) -> Result<model::User> {
+        let tables = self.0.lock();
+        let user_id = tables.identities.get(&(issuer.to_owned(), subject.to_owned())).ok_or_else(not_found)?;
+        tables.user(user_id)
+    }
+
+    async fn link_identity(&self, issuer: &str, subject: &str, user_id: UserId) -> Result<()> {
+        let user_id = user_id.into_inner().into();
+        self.0.lock().identities.insert((issuer.to_owned(), subject.to_owned()), user_id);
+        Ok(())
+    }
+
+    async fn new_session(&self, session_id: &str, user_id: UserId, expires: i64) -> Result<()> {
+        let session = model::Session { user_id: user_id.into_inner().into(), expires };
+        self.0.lock().sessions.insert(session_id.to_owned(), session);
+        Ok(())
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<model::Session> {
+        let now = now();
+        self.0.lock()
+            .sessions
+            .get(session_id)
+            .filter(|session| session.expires > now)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<()> {
+        self.0.lock().sessions.remove(session_id);
+        Ok(())
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<u64> {
+        let now = now();
+        let mut tables = self.0.lock();
+        let before = tables.sessions.len();
+        tables.sessions.retain(|_, session| session.expires > now);
+        Ok((before - tables.sessions.len()) as u64)
+    }
+}
+
+#[async_trait]
+impl CollectionRepository for MemoryStore {
+    async fn new_collection<M: Into<model::NewCollection> + Send>(&self, model: M, shortcodes: &ShortCodeGenerator) -> Result<model::Collection> {
+        let model = model.into();
+        let mut tables = self.0.lock();
+        for _ in 0..ShortCodeGenerator::MAX_ATTEMPTS {
+            let shortcode = match &model.shortcode {
+                Some(shortcode) => shortcode.clone(),
+                None => shortcodes.generate().into_inner(),
+            };
+            if tables.collection(&shortcode).is_err() {
+                tables.collections.push(model::Collection {
+                    collection_id: model.collection_id.clone(),
+                    shortcode: shortcode.clone(),
+                    title: model.title.clone(),
+                    password: model.password.clone(),
+                    posted: time(model.posted),
+                    api_key: model.api_key.clone(),
+                    user_id: model.user_id.clone(),
+                });
+                return tables.collection(&shortcode);
+            }
+            if model.shortcode.is_some() {
+                return Err(DataError::ShortCodeTaken(shortcode));
+            }
+        }
+        Err(DataError::ShortCodesExhausted(ShortCodeGenerator::MAX_ATTEMPTS))
+    }
+
+    async fn get_collection(&self, shortcode: &ShortCode) -> Result<model::Collection> {
+        self.0.lock().collection(shortcode.as_str())
+    }
+
+    async fn get_collection_clips(&self, collection_id: &CollectionId) -> Result<Vec<model::Clip>> {
+        let (collection_id, now) = (self::collection_id(collection_id), now());
+        let tables = self.0.lock();
+        // expired clips stay members until the maintenance task deletes them, but are not shown
+        let clips = tables.collection_clips
+            .iter()
+            .filter(|(id, _)| *id == collection_id)
+            .map(|(_, clip_id)| tables.clip(clip_id))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(clips.into_iter().filter(|clip| clip.expires.is_none_or(|expires| expires.timestamp() > now)).collect())
+    }
+
+    // adding a clip twice keeps it in its original place
+    async fn add_collection_clip(&self, collection_id: &CollectionId, clip_id: &ClipId) -> Result<()> {
+        let member = (self::collection_id(collection_id), self::clip_id(clip_id));
+        let mut tables = self.0.lock();
+        if !tables.collection_clips.contains(&member) {
+            tables.collection_clips.push(member);
+        }
+        Ok(())
+    }
+
+    async fn remove_collection_clip(&self, collection_id: &CollectionId, clip_id: &ClipId) -> Result<()> {
+        let member = (self::collection_id(collection_id), self::clip_id(clip_id));
+        let mut tables = self.0.lock();
+        let position = tables.collection_clips.iter().position(|m| *m == member).ok_or_else(not_found)?;
+        tables.collection_clips.remove(position);
+        Ok(())
+    }
+}
+
+// always reachable, and its tables always match the newest migration
+#[async_trait]
+impl HealthRepository for MemoryStore {
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn schema_version(&self) -> Result<Option<i64>> {
+        Ok(crate::data::expected_schema_version())
+    }
+}
@@ -0,0 +1,365 @@
+// the stores the service layer works against: the database of this build,
+// or an in-memory one for fast tests of the service logic
+mod memory;
+
+pub use memory::MemoryStore;
+
+use crate::data::model::{self, GetClip};
+use crate::data::query::{self, RevocationStatus};
+use crate::data::{DataError, DbPool};
+use crate::domain::clip::field::{ClipId, ShortCodeGenerator};
+use crate::domain::collection::CollectionId;
+use crate::domain::quota::Quota;
+use crate::domain::share::ShareSecret;
+use crate::domain::stats::{View, Visitor};
+use crate::domain::user::field::{UserId, Username};
+use crate::web::api::ApiKey;
+use crate::ShortCode;
+use async_trait::async_trait;
+
+type Result<T> = std::result::Result<T, DataError>;
+
+// missing rows are reported as sqlx::Error::RowNotFound by every store,
+// so the service maps them to ServiceError::NotFound the same way
+#[async_trait]
+pub trait ClipRepository: Send + Sync {
+    // aliases resolve to the clip they belong to
+    async fn get_clip<M: Into<GetClip> + Send>(&self, model: M) -> Result<model::Clip>;
+    // generated shortcodes are retried on collision, requested ones are not.
+    // the quota of the owning API key is checked along with the write, not ahead of it
+    async fn new_clip<M: Into<model::NewClip> + Send>(&self, model: M, shortcodes: &ShortCodeGenerator, quota: &Quota) -> Result<model::Clip>;
+    async fn update_clip<M: Into<model::UpdateClip> + Send>(&self, model: M, quota: &Quota) -> Result<model::Clip>;
+    async fn delete_clip(&self, shortcode: &ShortCode) -> Result<()>;
+    async fn record_failed_attempt(&self, shortcode: &ShortCode) -> Result<model::Clip>;
+    async fn save_share_secret(&self, shortcode: &ShortCode, secret: ShareSecret) -> Result<()>;
+    async fn get_aliases(&self, clip_id: &ClipId) -> Result<Vec<String>>;
+    async fn add_alias(&self, clip_id: &ClipId, alias: &ShortCode) -> Result<()>;
+    async fn remove_alias(&self, clip_id: &ClipId, alias: &ShortCode) -> Result<()>;
+    async fn rename_clip(&self, clip_id: &ClipId, shortcode: &ShortCode, renamed: &ShortCode) -> Result<model::Clip>;
+    async fn get_grants(&self, clip_id: &ClipId) -> Result<Vec<model::Grant>>;
+    async fn get_user_clips(&self, user_id: UserId, tag: Option<&str>) -> Result<Vec<model::Clip>>;
+    async fn get_api_key_clips(&self, api_key: ApiKey, tag: Option<&str>) -> Result<Vec<model::Clip>>;
+    // hit counts, view stats and visitors are saved together or not at all
+    async fn record_views(
+        &self,
+        hits: Vec<(ClipId, u32)>,
+        views: Vec<(ClipId, View, u32)>,
+        visitors: Vec<(ClipId, Visitor)>,
+    ) -> Result<()>;
+    async fn get_clip_views(&self, clip_id: &ClipId, since: i64) -> Result<Vec<model::ClipView>>;
+    async fn visitor_salt(&self, day: i64, candidate: &[u8]) -> Result<Vec<u8>>;
+    async fn delete_old_views(&self, since: i64) -> Result<u64>;
+    async fn delete_expired(&self) -> Result<u64>;
+    async fn delete_stale_visitors(&self, today: i64) -> Result<u64>;
+}
+
+#[async_trait]
+pub trait ApiKeyRepository: Send + Sync {
+    async fn save_api_key(&self, api_key: ApiKey, user_id: Option<UserId>) -> Result<ApiKey>;
+    async fn revoke_api_key(&self, api_key: ApiKey) -> Result<RevocationStatus>;
+    async fn api_key_is_valid(&self, api_key: ApiKey) -> Result<bool>;
+    async fn get_api_key_user(&self, api_key: ApiKey) -> Result<Option<UserId>>;
+    async fn get_usage(&self, api_key: ApiKey) -> Result<model::Usage>;
+}
+
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    async fn new_user<M: Into<model::NewUser> + Send>(&self, model: M) -> Result<model::User>;
+    async fn get_user_by_name(&self, username: &Username) -> Result<model::User>;
+    async fn get_user(&self, user_id: UserId) -> Result<model::User>;
+    async fn get_user_by_identity(&self, issuer: &str, subject: &str) -> Result<model::User>;
+    async fn link_identity(&self, issuer: &str, subject: &str, user_id: UserId) -> Result<()>;
+    async fn new_session(&self, session_id: &str, user_id: UserId, expires: i64) -> Result<()>;
+    // RowNotFound once the session expired or was deleted
+    async fn get_session(&self, session_id: &str) -> Result<model::Session>;
+    async fn delete_session(&self, session_id: &str) -> Result<()>;
+    async fn delete_expired_sessions(&self) -> Result<u64>;
+}
+
+#[async_trait]
+pub trait CollectionRepository: Send + Sync {
+    async fn new_collection<M: Into<model::NewCollection> + Send>(&self, model: M, shortcodes: &ShortCodeGenerator) -> Result<model::Collection>;
+    async fn get_collection(&self, shortcode: &ShortCode) -> Result<model::Collection>;
+    // in the order they were added
+    async fn get_collection_clips(&self, collection_id: &CollectionId) -> Result<Vec<model::Clip>>;
+    async fn add_collection_clip(&self, collection_id: &CollectionId, clip_id: &ClipId) -> Result<()>;
+    async fn remove_collection_clip(&self, collection_id: &CollectionId, clip_id: &ClipId) -> Result<()>;
+}
+
+// whether the store can serve traffic
+#[async_trait]
+pub trait HealthRepository: Send + Sync {
+    async fn ping(&self) -> Result<()>;
+    // newest migration applied, None on an empty database
+    async fn schema_version(&self) -> Result<Option<i64>>;
+}
+
+#[async_trait]
+impl ClipRepository for DbPool {
+    async fn get_clip<M: Into<GetClip> + Send>(&self, model: M) -> Result<model::Clip> {
+        query::get_clip(model, self).await
+    }
+
+    async fn new_clip<M: Into<model::NewClip> + Send>(&self, model: M, shortcodes: &ShortCodeGenerator, quota: &Quota) -> Result<model::Clip> {
+        query::new_clip(model, shortcodes, quota, self).await
+    }
+
+    async fn update_clip<M: Into<model::UpdateClip> + Send>(&self, model: M, quota: &Quota) -> Result<model::Clip> {
+        query::update_clip(model, quota, self).await
+    }
+
+    async fn delete_clip(&self, shortcode: &ShortCode) -> Result<()> {
+        query::delete_clip(shortcode, self).await
+    }
+
+    async fn record_failed_attempt(&self, shortcode: &ShortCode) -> Result<model::Clip> {
+        query::record_failed_attempt(shortcode, self).await
+    }
+
+    async fn save_share_secret(&self, shortcode: &ShortCode, secret: ShareSecret) -> Result<()> {
+        query::save_share_secret(shortcode, secret, self).await
+    }
+
+    async fn get_aliases(&self, clip_id: &ClipId) -> Result<Vec<String>> {
+        query::get_aliases(clip_id, self).await
+    }
+
+    async fn add_alias(&self, clip_id: &ClipId, alias: &ShortCode) -> Result<()> {
+        query::add_alias(clip_id, alias, self).await
+    }
+
+    async fn remove_alias(&self, clip_id: &ClipId, alias: &ShortCode) -> Result<()> {
+        query::remove_alias(clip_id, alias, self).await
+    }
+
+    async fn rename_clip(&self, clip_id: &ClipId, shortcode: &ShortCode, renamed: &ShortCode) -> Result<model::Clip> {
+        query::rename_clip(clip_id, shortcode, renamed, self).await
+    }
+
+    async fn get_grants(&self, clip_id: &ClipId) -> Result<Vec<model::Grant>> {
+        query::get_grants(clip_id, self).await
+    }
+
+    async fn get_user_clips(&self, user_id: UserId, tag: Option<&str>) -> Result<Vec<model::Clip>> {
+        query::get_user_clips(user_id, tag, self).await
+    }
+
+    async fn get_api_key_clips(&self, api_key: ApiKey, tag: Option<&str>) -> Result<Vec<model::Clip>> {
+        query::get_api_key_clips(api_key, tag, self).await
+    }
+
+    async fn record_views(
+        &self,
+        hits: Vec<(ClipId, u32)>,
+        views: Vec<(ClipId, View, u32)>,
+        visitors: Vec<(ClipId, Visitor)>,
+    ) -> Result<()> {
+        let mut tx = self.begin().await?;
+        query::increase_hit_counts(hits, &mut tx).await?;
+        query::record_views(views, &mut tx).await?;
+        query::record_visitors(visitors, &mut tx).await?;
+        Ok(tx.commit().await?)
+    }
+
+    async fn get_clip_views(&self, clip_id: &ClipId, since: i64) -> Result<Vec<model::ClipView>> {
+        query::get_clip_views(clip_id, since, self).await
+    }
+
+    async fn visitor_salt(&self, day: i64, candidate: &[u8]) -> Result<Vec<u8>> {
+        query::visitor_salt(day, candidate, self).await
+    }
+
+    async fn delete_old_views(&self, since: i64) -> Result<u64> {
+        query::delete_old_views(since, self).await
+    }
+
+    async fn delete_expired(&self) -> Result<u64> {
+        query::delete_expired(self).await
+    }
+
+    async fn delete_stale_visitors(&self, today: i64) -> Result<u64> {
+        query::delete_stale_visitors(today, self).await
+    }
+}
+
+#[async_trait]
+impl ApiKeyRepository for DbPool {
+    async fn save_api_key(&self, api_key: ApiKey, user_id: Option<UserId>) -> Result<ApiKey> {
+        query::save_api_key(api_key, user_id, self).await
+    }
+
+    async fn revoke_api_key(&self, api_key: ApiKey) -> Result<RevocationStatus> {
+        query::revoke_api_key(api_key, self).await
+    }
+
+    async fn api_key_is_valid(&self, api_key: ApiKey) -> Result<bool> {
+        query::api_key_is_valid(api_key, self).await
+    }
+
+    async fn get_api_key_user(&self, api_key: ApiKey) -> Result<Option<UserId>> {
+        query::get_api_key_user(api_key, self).await
+    }
+
+    async fn get_usage(&self, api_key: ApiKey) -> Result<model::Usage> {
+        query::get_usage(api_key, self).await
+    }
+}
+
+#[async_trait]
+impl UserRepository for DbPool {
+    async fn new_user<M: Into<model::NewUser> + Send>(&self, model: M) -> Result<model::User> {
+        query::new_user(model, self).await
+    }
+
+    async fn get_user_by_name(&self, username: &Username) -> Result<model::User> {
+        query::get_user_by_name(username, self).await
+    }
+
+    async fn get_user(&self, user_id: UserId) -> Result<model::User> {
+        query::get_user(user_id, self).await
+    }
+
+    async fn get_user_by_identity(&self, issuer: &str, subject: &str) -> Result<model::User> {
+        query::get_user_by_identity(issuer, subject, self).await
+    }
+
+    async fn link_identity(&self, issuer: &str, subject: &str, user_id: UserId) -> Result<()> {
+        query::link_identity(issuer, subject, user_id, self).await
+    }
+
+    async fn new_session(&self, session_id: &str, user_id: UserId, expires: i64) -> Result<()> {
+        query::new_session(session_id, user_id, expires, self).await
+    }
+
+    async fn get_session(&self, session_id: &str) -> Result<model::Session> {
+        query::get_session(session_id, self).await
+    }
+
+    async fn delete_session(&self, session_id: &str) -> Result<()> {
+        query::delete_session(session_id, self).await
+    }
+
+    async fn delete_expired_sessions(&self) -> Result<u64> {
+        query::delete_expired_sessions(self).await
+    }
+}
+
+#[async_trait]
+impl CollectionRepository for DbPool {
+    async fn new_collection<M: Into<model::NewCollection> + Send>(&self, model: M, shortcodes: &ShortCodeGenerator) -> Result<model::Collection> {
+        query::new_collection(model, shortcodes, self).await
+    }
+
+    async fn get_collection(&self, shortcode: &ShortCode) -> Result<model::Collection> {
+        query::get_collection(shortcode, self).await
+    }
+
+    async fn get_collection_clips(&self, collection_id: &CollectionId) -> Result<Vec<model::Clip>> {
+        query::get_collection_clips(collection_id, self).await
+    }
+
+    async fn add_collection_clip(&self, collection_id: &CollectionId, clip_id: &ClipId) -> Result<()> {
+        query::add_collection_clip(collection_id, clip_id, self).await
+    }
+
+    async fn remove_collection_clip(&self, collection_id: &CollectionId, clip_id: &ClipId) -> Result<()> {
+        query::remove_collection_clip(collection_id, clip_id, self).await
+    }
+}
+
+#[async_trait]
+impl HealthRepository for DbPool {
+    async fn ping(&self) -> Result<()> {
+        query::ping(self).await
+    }
+
+    async fn schema_version(&self) -> Result<Option<i64>> {
+        query::schema_version(self).await
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::data::test::new_db;
+    use crate::data::DbId;
+    use crate::test::async_runtime;
+    use std::str::FromStr;
+
+    fn new_clip(shortcode: &str) -> model::NewClip {
+        model::NewClip {
+            clip_id: DbId::new().into(),
+            content: "content".to_owned(),
+            title: None,
+            shortcode: Some(shortcode.to_owned()),
+            posted: chrono::Utc::now().timestamp(),
+            expires: None,
+            password: None,
+            api_key: None,
+            user_id: None,
+            visibility: "unlisted".to_owned(),
+            burn_after: None,
+            grants: vec![],
+            tags: vec![],
+        }
+    }
+
+    async fn aliases_and_renames<R: ClipRepository>(repo: &R) {
+        let (shortcodes, a, b) = (ShortCodeGenerator::default(), ShortCode::from("a"), ShortCode::from("b"));
+        let tagged = model::NewClip { tags: vec!["rust".to_owned()], ..new_clip("a") };
+        let clip = repo.new_clip(tagged, &shortcodes, &Quota::default()).await.unwrap();
+        assert!(matches!(repo.new_clip(new_clip("a"), &shortcodes, &Quota::default()).await, Err(DataError::ShortCodeTaken(_))));
+
+        let clip_id: ClipId = DbId::from_str(&clip.clip_id).unwrap().into();
+        repo.add_alias(&clip_id, &b).await.unwrap();
+        assert_eq!(repo.get_clip(b.clone()).await.unwrap().shortcode, "a");
+        assert!(matches!(repo.add_alias(&clip_id, &a).await, Err(DataError::ShortCodeTaken(_))));
+        assert!(matches!(repo.add_alias(&clip_id, &b).await, Err(DataError::ShortCodeTaken(_))));
+
+        let renamed = repo.rename_clip(&clip_id, &a, &b).await.unwrap();
+        assert_eq!((renamed.shortcode.as_str(), renamed.tags.as_deref()), ("b", Some("rust")));
+        assert_eq!(repo.get_aliases(&clip_id).await.unwrap(), vec!["a".to_owned()]);
+
+        // aliases go along with their clip
+        repo.delete_clip(&b).await.unwrap();
+        assert!(matches!(repo.get_clip(a).await, Err(DataError::Database(sqlx::Error::RowNotFound))));
+    }
+
+    async fn expired_members<R: ClipRepository + CollectionRepository>(repo: &R) {
+        let shortcodes = ShortCodeGenerator::default();
+        let expired = model::NewClip { expires: Some(chrono::Utc::now().timestamp() - 60), ..new_clip("expired") };
+        let collection = model::NewCollection {
+            collection_id: DbId::new().into(),
+            shortcode: None,
+            title: None,
+            password: None,
+            posted: chrono::Utc::now().timestamp(),
+            api_key: None,
+            user_id: None,
+        };
+        let collection_id = CollectionId::new(DbId::from_str(&repo.new_collection(collection, &shortcodes).await.unwrap().collection_id).unwrap());
+        for model in [new_clip("live"), expired] {
+            let clip = repo.new_clip(model, &shortcodes, &Quota::default()).await.unwrap();
+            repo.add_collection_clip(&collection_id, &DbId::from_str(&clip.clip_id).unwrap().into()).await.unwrap();
+        }
+
+        let shortcodes: Vec<_> = repo.get_collection_clips(&collection_id).await.unwrap().into_iter().map(|clip| clip.shortcode).collect();
+        assert_eq!(shortcodes, vec!["live".to_owned()]);
+    }
+
+    #[test]
+    fn stores_hide_expired_members_of_collections() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(expired_members(db.get_pool()));
+        rt.block_on(expired_members(&MemoryStore::new()));
+    }
+
+    #[test]
+    fn stores_agree_on_aliases_and_renames() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(aliases_and_renames(db.get_pool()));
+        rt.block_on(aliases_and_renames(&MemoryStore::new()));
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use rocket::form::{self, FromFormField, ValueField};
+
+use crate::domain::clip::ClipError;
+
+// number of wrong passwords after which the clip deletes itself, never when None
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "Option<u32>")]
+pub struct BurnAfter(Option<u32>);
+
+impl BurnAfter {
+    pub const MAX: u32 = 100;
+
+    pub fn new(failures: Option<u32>) -> Result<Self, ClipError> {
+        match failures {
+            Some(n) if !(1..=Self::MAX).contains(&n) => Err(ClipError::InvalidBurnAfter(
+                format!("must be between 1 and {}", Self::MAX)
+            )),
+            failures => Ok(Self(failures)),
+        }
+    }
+
+    pub fn into_inner(self) -> Option<u32> {
+        self.0
+    }
+}
+
+impl TryFrom<Option<u32>> for BurnAfter {
+    type Error = ClipError;
+    fn try_from(failures: Option<u32>) -> Result<Self, Self::Error> {
+        Self::new(failures)
+    }
+}
+
+impl FromStr for BurnAfter {
+    type Err = ClipError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            Ok(Self(None))
+        } else {
+            let failures = s.trim().parse().map_err(|_| ClipError::InvalidBurnAfter(s.to_owned()))?;
+            Self::new(Some(failures))
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for BurnAfter {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        Ok(Self::from_str(field.value).map_err(|e| form::Error::validation(format!("{}", e)))?)
+    }
+
+    // the field is optional in forms
+    fn default() -> Option<Self> {
+        Some(<Self as Default>::default())
+    }
+}
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use crate::data::DbId;
 use derive_more::Constructor;
 
-#[derive(Clone, Constructor, Debug, Deserialize, Serialize)]
+#[derive(Clone, Constructor, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct ClipId(DbId);
 
 impl ClipId {
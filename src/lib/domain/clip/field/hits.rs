@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use derive_more::Constructor;
 
-#[derive(Clone, Constructor, Debug, Deserialize, Serialize)]
+#[derive(Clone, Constructor, Debug, Default, Deserialize, Serialize)]
 pub struct Hits(u64);
 
 impl Hits {
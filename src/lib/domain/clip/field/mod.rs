@@ -2,7 +2,7 @@ mod clip_id;
 pub use clip_id::ClipId;
 
 mod shortcode;
-pub use shortcode::ShortCode;
+pub use shortcode::{ShortCode, ShortCodeGenerator, ShortCodeStyle};
 
 
 mod content;
@@ -18,7 +18,19 @@ mod expires;
 pub use expires::Expires;
 
 mod password;
-pub use password::Password;
+pub use password::{Password, PasswordPolicy};
 
 mod hits;
-pub use hits::Hits;
\ No newline at end of file
+pub use hits::Hits;
+
+mod visibility;
+pub use visibility::Visibility;
+
+mod shared_with;
+pub use shared_with::{Grantee, SharedWith};
+
+mod burn_after;
+pub use burn_after::BurnAfter;
+
+mod tags;
+pub use tags::Tags;
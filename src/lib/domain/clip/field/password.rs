@@ -1,10 +1,11 @@
 use serde::{Deserialize, Serialize};
 use crate::domain::clip::ClipError;
+use crate::domain::words;
 
 use rocket::form::{self, FromFormField, ValueField};
 use std::str::FromStr;
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, PartialOrd)]
 pub struct Password(Option<String>);
 
 impl Password {
@@ -30,14 +31,79 @@ impl Password {
     pub fn has_password(&self) -> bool {
           self.0.is_some()
     }
+
+    // random words, e.g. otter-lantern-quartz-meadow-ferry-plum
+    pub fn generate() -> Self {
+        Self(Some(words::random_words(PasswordPolicy::PASSPHRASE_WORDS).join("-")))
+    }
+}
+
+// passwords are only held to the policy when they are set:
+// clips protected before it was tightened can still be unlocked
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    // rough estimate, see entropy()
+    pub min_entropy: f64,
+    // reject well known passwords such as "123456" or "letmein"
+    pub deny_common: bool,
 }
 
-impl Default for Password {
+impl Default for PasswordPolicy {
     fn default() -> Self {
-        Self(None)
+        Self { min_length: 8, min_entropy: 30.0, deny_common: true }
+    }
+}
+
+impl PasswordPolicy {
+    pub const PASSPHRASE_WORDS: usize = 6;
+
+    pub fn check(&self, password: &Password) -> Result<(), ClipError> {
+        let password = match &password.0 {
+            Some(password) => password,
+            None => return Ok(())
+        };
+
+        if password.chars().count() < self.min_length {
+            return Err(ClipError::InvalidPassword(format!("must be at least {} characters long", self.min_length)));
+        }
+        if self.deny_common && COMMON_PASSWORDS.lines().any(|common| common.eq_ignore_ascii_case(password.trim())) {
+            return Err(ClipError::InvalidPassword("is too common".to_owned()));
+        }
+        if entropy(password) < self.min_entropy {
+            return Err(ClipError::InvalidPassword("is too predictable, mix in more distinct characters or words".to_owned()));
+        }
+        Ok(())
     }
 }
 
+// well known passwords, one per line
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+// character predicate and how many characters it covers
+type CharClass = (fn(&char) -> bool, f64);
+
+// bits an attacker guessing from the character classes in use would need:
+// repeated characters add nothing, so "aaaaaaaa" scores as low as "a"
+fn entropy(password: &str) -> f64 {
+    let classes: [CharClass; 4] = [
+        (char::is_ascii_lowercase, 26.0),
+        (char::is_ascii_uppercase, 26.0),
+        (char::is_ascii_digit, 10.0),
+        (|c: &char| !c.is_ascii_alphanumeric(), 33.0),
+    ];
+    let pool: f64 = classes
+        .iter()
+        .filter(|(matches, _)| password.chars().any(|c| matches(&c)))
+        .map(|(_, size)| size)
+        .sum();
+
+    let mut distinct: Vec<char> = password.chars().collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    distinct.len() as f64 * pool.max(1.0).log2()
+}
+
 // will allow to create Passwords from borrowed strings
 impl FromStr for Password {
     type Err = ClipError;
@@ -54,4 +120,25 @@ impl<'r> FromFormField<'r> for Password {
             .map_err(|e| form::Error::validation(format!("{:?}", e)))?
         )
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::domain::clip::field::{Password, PasswordPolicy};
+
+    fn check(password: &str) -> bool {
+        PasswordPolicy::default().check(&Password::new(password.to_owned()).unwrap()).is_ok()
+    }
+
+    #[test]
+    fn enforces_default_policy() {
+        assert!(check(""));
+        assert!(!check("123"));
+        assert!(!check("Password1"));
+        assert!(!check("aaaaaaaaaaaa"));
+        assert!(!check("12344321"));
+        assert!(check("correct horse battery staple"));
+        assert!(check("Tr0ub4dor&3"));
+        assert!(PasswordPolicy::default().check(&Password::generate()).is_ok());
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use rocket::form::{self, FromFormField, ValueField};
+
+use crate::domain::clip::ClipError;
+use crate::domain::user::field::Username;
+use crate::web::api::ApiKey;
+
+// user or API key a clip is shared with
+#[derive(Clone, Debug)]
+pub enum Grantee {
+    User(Username),
+    ApiKey(ApiKey),
+}
+
+// usernames never contain '=' while (16 byte) API keys always end with it
+impl FromStr for Grantee {
+    type Err = ClipError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.contains('=') {
+            ApiKey::from_str(s)
+                .map(Grantee::ApiKey)
+                .map_err(|e| ClipError::InvalidShare(e.to_string()))
+        } else {
+            Username::new(s)
+                .map(Grantee::User)
+                .map_err(|e| ClipError::InvalidShare(e.to_string()))
+        }
+    }
+}
+
+impl From<Grantee> for String {
+    fn from(grantee: Grantee) -> Self {
+        match grantee {
+            Grantee::User(username) => username.into_inner(),
+            Grantee::ApiKey(api_key) => api_key.to_base64(),
+        }
+    }
+}
+
+// allow-list of a clip shared with specific users or API keys
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct SharedWith(Vec<Grantee>);
+
+impl SharedWith {
+    pub fn into_inner(self) -> Vec<Grantee> {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl TryFrom<Vec<String>> for SharedWith {
+    type Error = ClipError;
+    fn try_from(grantees: Vec<String>) -> Result<Self, Self::Error> {
+        grantees
+            .iter()
+            .map(|grantee| Grantee::from_str(grantee))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl From<SharedWith> for Vec<String> {
+    fn from(shared_with: SharedWith) -> Self {
+        shared_with.0.into_iter().map(String::from).collect()
+    }
+}
+
+// comma separated, as typed in the HTML form
+impl FromStr for SharedWith {
+    type Err = ClipError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|grantee| !grantee.trim().is_empty())
+            .map(Grantee::from_str)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for SharedWith {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        Ok(Self::from_str(field.value).map_err(|e| form::Error::validation(format!("{}", e)))?)
+    }
+
+    // the field is optional in forms
+    fn default() -> Option<Self> {
+        Some(<Self as Default>::default())
+    }
+}
@@ -2,26 +2,54 @@
 use rocket::{UriDisplayPath, UriDisplayQuery};
 use rocket::request::FromParam;
 use crate::ClipError;
+use crate::domain::words;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use derive_more::From;
+use strum::{Display, EnumString};
 
+// grammar of every shortcode, generated or chosen:
+//   1 to MAX_LENGTH characters out of A-Z a-z 0-9 '-' '_'
+// chosen (custom) shortcodes must also be at least MIN_CUSTOM_LENGTH long,
+// start and end with a letter or digit and not be one of the RESERVED words.
+// generated ones only use the BASE62 / BASE58 alphabets or words joined by '-',
+// which the tests check against `parse`
 #[derive(Clone, Debug, Deserialize, Serialize, From, UriDisplayQuery, UriDisplayPath, Hash, Eq, PartialEq)]
 pub struct ShortCode(String);
 
 impl ShortCode {
-    pub fn new() -> Self {
-        use rand::prelude::*;
+    pub const MIN_CUSTOM_LENGTH: usize = 3;
+    pub const MAX_LENGTH: usize = 64;
 
-        let allowed_chars = ['a', 'b', 'c', 'd', '1', '2', '3', '4'];
+    pub fn new() -> Self {
+        ShortCodeGenerator::default().generate()
+    }
 
-        let mut rng = thread_rng();
-        let mut shortcode = String::with_capacity(10); // to avoid allocating more memory than needed
-        for _ in 0..10 {
-            shortcode.push(*allowed_chars.choose(&mut rng).expect("sampling array should have values"))
+    // any shortcode coming in from outside: urls, API requests, the CLI
+    pub fn parse(shortcode: &str) -> Result<Self, ClipError> {
+        if shortcode.is_empty() || shortcode.len() > Self::MAX_LENGTH {
+            return Err(ClipError::InvalidShortCode(format!("must be 1 to {} characters long", Self::MAX_LENGTH)));
+        }
+        if !shortcode.chars().all(is_shortcode_char) {
+            return Err(ClipError::InvalidShortCode("may only contain letters, digits, '-' and '_'".to_owned()));
         }
+        Ok(Self(shortcode.to_owned()))
+    }
 
-        Self(shortcode)
+    // shortcodes chosen by users, e.g. deploy-notes
+    pub fn custom(shortcode: &str) -> Result<Self, ClipError> {
+        let shortcode = shortcode.trim();
+        if shortcode.chars().count() < Self::MIN_CUSTOM_LENGTH {
+            return Err(ClipError::InvalidShortCode(format!("must be at least {} characters long", Self::MIN_CUSTOM_LENGTH)));
+        }
+        let shortcode = Self::parse(shortcode)?.0;
+        if !shortcode.starts_with(|c: char| c.is_ascii_alphanumeric()) || !shortcode.ends_with(|c: char| c.is_ascii_alphanumeric()) {
+            return Err(ClipError::InvalidShortCode("must start and end with a letter or digit".to_owned()));
+        }
+        if RESERVED.iter().any(|reserved| reserved.eq_ignore_ascii_case(&shortcode)) {
+            return Err(ClipError::InvalidShortCode(format!("'{}' is reserved", shortcode)));
+        }
+        Ok(Self(shortcode))
     }
 
     pub fn as_str(&self) -> &str {
@@ -51,19 +79,192 @@ impl From<&str> for ShortCode {
     }
 }
 
-// for completeness
 impl FromStr for ShortCode {
     type Err = ClipError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(Self(s.into()))
+        Self::parse(s)
     }
 }
 
 // so that http::get_clip can use Shortcode route param
 impl<'r> FromParam<'r> for ShortCode {
-    type Error = &'r str;
+    type Error = ClipError;
 
+    // malformed shortcodes are turned away before touching the database
     fn from_param(param: &'r str) -> Result<Self, Self::Error> {
-        Ok(ShortCode::from(param))
+        ShortCode::parse(param)
+    }
+}
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+// path segments of our own routes, which would make clips with these shortcodes unreachable or confusing
+const RESERVED: &[&str] = &["raw", "key", "static", "api", "clip", "my", "login", "logout", "register", "share", "unlock", "usage"];
+
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+// base62 without 0, O, I and l, which are easily mixed up when read aloud or copied by hand
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const WORD_SEPARATOR: char = '-';
+
+// shortcodes are the only thing protecting unprotected clips, so they must not be guessable
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Display, EnumString, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum ShortCodeStyle {
+    Base62,
+    #[default]
+    Base58,
+    // random words, e.g. otter-lantern-quartz-meadow-ferry-plum
+    Words,
+}
+
+impl ShortCodeStyle {
+    // length in characters, or in words for `Words`
+    pub fn default_length(&self) -> usize {
+        match self {
+            ShortCodeStyle::Base62 | ShortCodeStyle::Base58 => 12,
+            ShortCodeStyle::Words => 6,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ShortCodeGenerator {
+    style: ShortCodeStyle,
+    length: usize,
+    // always hands out this shortcode, to run out of free ones in tests
+    #[cfg(test)]
+    fixed: Option<ShortCode>,
+}
+
+impl ShortCodeGenerator {
+    // tried before giving up on finding a free shortcode
+    pub const MAX_ATTEMPTS: usize = 8;
+
+    // every shortcode generated must pass `ShortCode::parse`, or its clip could never be reached
+    pub fn new(style: ShortCodeStyle, length: Option<usize>) -> Result<Self, ClipError> {
+        let generator = Self {
+            style,
+            length: length.unwrap_or_else(|| style.default_length()),
+            #[cfg(test)]
+            fixed: None,
+        };
+        if generator.length == 0 {
+            return Err(ClipError::InvalidShortCode("generated shortcodes need a length of at least 1".to_owned()));
+        }
+        if generator.max_length() > ShortCode::MAX_LENGTH {
+            return Err(ClipError::InvalidShortCode(format!(
+                "a length of {} makes generated shortcodes up to {} characters long, at most {} are allowed",
+                generator.length,
+                generator.max_length(),
+                ShortCode::MAX_LENGTH
+            )));
+        }
+        Ok(generator)
+    }
+
+    #[cfg(test)]
+    pub fn fixed(shortcode: &str) -> Self {
+        Self { fixed: Some(ShortCode::parse(shortcode).unwrap()), ..Self::default() }
+    }
+
+    // longest shortcode this generates
+    fn max_length(&self) -> usize {
+        match self.style {
+            ShortCodeStyle::Base62 | ShortCodeStyle::Base58 => self.length,
+            ShortCodeStyle::Words => self.length.saturating_mul(words::max_word_length() + 1).saturating_sub(1),
+        }
+    }
+
+    pub fn generate(&self) -> ShortCode {
+        use rand::seq::SliceRandom;
+
+        #[cfg(test)]
+        if let Some(fixed) = &self.fixed {
+            return fixed.clone();
+        }
+
+        let alphabet = match self.style {
+            ShortCodeStyle::Base62 => BASE62,
+            ShortCodeStyle::Base58 => BASE58,
+            ShortCodeStyle::Words => {
+                return ShortCode(words::random_words(self.length).join(&WORD_SEPARATOR.to_string()));
+            }
+        };
+
+        let mut rng = rand::thread_rng();
+        let shortcode = (0..self.length)
+            .filter_map(|_| alphabet.choose(&mut rng))
+            .map(|&c| char::from(c))
+            .collect();
+        ShortCode(shortcode)
+    }
+
+    // how hard a single shortcode is to guess
+    pub fn bits(&self) -> f64 {
+        let per_symbol = match self.style {
+            ShortCodeStyle::Base62 => (BASE62.len() as f64).log2(),
+            ShortCodeStyle::Base58 => (BASE58.len() as f64).log2(),
+            ShortCodeStyle::Words => words::bits_per_word(),
+        };
+        per_symbol * self.length as f64
+    }
+}
+
+impl Default for ShortCodeGenerator {
+    fn default() -> Self {
+        Self::new(ShortCodeStyle::default(), None).expect("default shortcode length is valid")
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::domain::clip::field::shortcode::{is_shortcode_char, ShortCode, ShortCodeGenerator, ShortCodeStyle, BASE58, BASE62};
+
+    #[test]
+    fn generates_configured_shortcodes() {
+        let shortcode = ShortCodeGenerator::default().generate();
+        assert_eq!(shortcode.as_str().len(), 12);
+        assert!(shortcode.as_str().bytes().all(|c| BASE58.contains(&c)));
+        assert!(ShortCodeGenerator::default().bits() > 70.0);
+
+        let generator = ShortCodeGenerator::new(ShortCodeStyle::Words, Some(3)).unwrap();
+        assert_eq!(generator.generate().as_str().split('-').count(), 3);
+
+        // generated shortcodes always pass the inbound grammar
+        for style in [ShortCodeStyle::Base62, ShortCodeStyle::Base58, ShortCodeStyle::Words] {
+            let shortcode = ShortCodeGenerator::new(style, None).unwrap().generate();
+            assert!(ShortCode::parse(shortcode.as_str()).is_ok(), "{} should parse", shortcode.as_str());
+        }
+        for c in BASE62.iter().chain(BASE58) {
+            assert!(is_shortcode_char(char::from(*c)));
+        }
+    }
+
+    #[test]
+    fn rejects_lengths_that_cannot_be_parsed_back() {
+        for style in [ShortCodeStyle::Base62, ShortCodeStyle::Base58, ShortCodeStyle::Words] {
+            assert!(ShortCodeGenerator::new(style, Some(0)).is_err());
+        }
+        assert!(ShortCodeGenerator::new(ShortCodeStyle::Base58, Some(ShortCode::MAX_LENGTH)).is_ok());
+        assert!(ShortCodeGenerator::new(ShortCodeStyle::Base62, Some(ShortCode::MAX_LENGTH + 1)).is_err());
+        assert!(ShortCodeGenerator::new(ShortCodeStyle::Words, Some(7)).is_ok());
+        assert!(ShortCodeGenerator::new(ShortCodeStyle::Words, Some(8)).is_err());
+    }
+
+    #[test]
+    fn validates_custom_shortcodes() {
+        assert_eq!(ShortCode::custom("deploy-notes").unwrap().as_str(), "deploy-notes");
+        assert!(ShortCode::custom("Release_2022").is_ok());
+
+        assert!(ShortCode::parse("raw").is_ok());
+        assert!(ShortCode::parse("").is_err());
+        assert!(ShortCode::parse("../etc").is_err());
+
+        for invalid in ["ab", "has space", "ümlaut", "-dash", "trailing_", "raw", "KEY", "static", &"x".repeat(65)] {
+            assert!(ShortCode::custom(invalid).is_err(), "{} should be rejected", invalid);
+        }
     }
 }
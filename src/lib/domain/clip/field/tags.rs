@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+use rocket::form::{self, FromFormField, ValueField};
+
+use crate::domain::clip::ClipError;
+
+// labels used to group and filter clips, lowercase and deduplicated
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(try_from = "Vec<String>", into = "Vec<String>")]
+pub struct Tags(Vec<String>);
+
+impl Tags {
+    pub const MAX_TAGS: usize = 10;
+    pub const MAX_LENGTH: usize = 32;
+
+    pub fn new(tags: Vec<String>) -> Result<Self, ClipError> {
+        let mut normalized: Vec<String> = vec![];
+        for tag in tags {
+            let tag = Self::normalize(&tag)?;
+            if !normalized.contains(&tag) {
+                normalized.push(tag);
+            }
+        }
+        if normalized.len() > Self::MAX_TAGS {
+            return Err(ClipError::InvalidTags(format!("at most {} tags are allowed", Self::MAX_TAGS)));
+        }
+        Ok(Self(normalized))
+    }
+
+    // a single tag is trimmed and lowercased, inner whitespace becomes '-'
+    pub fn normalize(tag: &str) -> Result<String, ClipError> {
+        let tag = tag.trim().to_lowercase().split_whitespace().collect::<Vec<_>>().join("-");
+        if tag.is_empty() {
+            return Err(ClipError::InvalidTags("tags can't be empty".to_owned()));
+        }
+        if tag.chars().count() > Self::MAX_LENGTH {
+            return Err(ClipError::InvalidTags(format!("tags are at most {} characters long", Self::MAX_LENGTH)));
+        }
+        if !tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(ClipError::InvalidTags(format!("'{}' may only contain letters, digits, '-' and '_'", tag)));
+        }
+        Ok(tag)
+    }
+
+    pub fn into_inner(self) -> Vec<String> {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl TryFrom<Vec<String>> for Tags {
+    type Error = ClipError;
+    fn try_from(tags: Vec<String>) -> Result<Self, Self::Error> {
+        Self::new(tags)
+    }
+}
+
+impl From<Tags> for Vec<String> {
+    fn from(tags: Tags) -> Self {
+        tags.0
+    }
+}
+
+// comma separated, as typed in the HTML form and stored by the data layer
+impl FromStr for Tags {
+    type Err = ClipError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(
+            s.split(',')
+                .filter(|tag| !tag.trim().is_empty())
+                .map(str::to_owned)
+                .collect(),
+        )
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for Tags {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        Ok(Self::from_str(field.value).map_err(|e| form::Error::validation(format!("{}", e)))?)
+    }
+
+    // the field is optional in forms
+    fn default() -> Option<Self> {
+        Some(<Self as Default>::default())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::Tags;
+    use std::str::FromStr;
+
+    #[test]
+    fn normalizes_and_bounds_tags() {
+        let tags = Tags::from_str(" Rust, rust ,Release Notes,, ").unwrap();
+        assert_eq!(tags.into_inner(), vec!["rust", "release-notes"]);
+
+        assert!(Tags::from_str("c++").is_err());
+        assert!(Tags::from_str(&"x".repeat(Tags::MAX_LENGTH + 1)).is_err());
+        let too_many = (0..=Tags::MAX_TAGS).map(|i| format!("t{}", i)).collect::<Vec<_>>().join(",");
+        assert!(Tags::from_str(&too_many).is_err());
+    }
+}
@@ -13,9 +13,9 @@ impl Title {
         match title {
             Some(title) => {
                 if title.trim().is_empty() {
-                    Self(Some(title))
-                } else {
                     Self(None)
+                } else {
+                    Self(Some(title))
                 }
             }
             None => Self(None)
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use rocket::form::{self, FromFormField, ValueField};
+use strum::{Display, EnumString};
+
+// who may view a clip, on top of its password
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Display, EnumString, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Visibility {
+    // anyone, and may be listed
+    Public,
+    // anyone knowing the shortcode
+    #[default]
+    Unlisted,
+    // only the API key or user who created it
+    Private,
+    // the creator and the users or API keys it is shared with
+    SharedWith,
+}
+
+impl Visibility {
+    // whether only identified requesters may view the clip
+    pub fn is_restricted(&self) -> bool {
+        matches!(self, Visibility::Private | Visibility::SharedWith)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for Visibility {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        if field.value.trim().is_empty() {
+            Ok(<Self as Default>::default())
+        } else {
+            Ok(Self::from_str(field.value).map_err(|e| form::Error::validation(format!("{}", e)))?)
+        }
+    }
+
+    // the field is optional in forms
+    fn default() -> Option<Self> {
+        Some(<Self as Default>::default())
+    }
+}
@@ -25,6 +25,24 @@ pub enum ClipError {
 
     #[error("hits parse error: {0}")]
     Hits(#[from] std::num::TryFromIntError),
+
+    #[error("invalid visibility: {0}")]
+    InvalidVisibility(String),
+
+    #[error("invalid share: {0}")]
+    InvalidShare(String),
+
+    #[error("invalid number of failed attempts: {0}")]
+    InvalidBurnAfter(String),
+
+    #[error("invalid shortcode: {0}")]
+    InvalidShortCode(String),
+
+    #[error("invalid tags: {0}")]
+    InvalidTags(String),
+
+    #[error("invalid view: {0}")]
+    InvalidView(String),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -38,4 +56,12 @@ pub struct Clip {
     pub expires: field::Expires,
     pub password: field::Password,
     pub hits: field::Hits,
+    // distinct visitors per day, summed up
+    #[serde(default)]
+    pub visitors: field::Hits,
+    pub visibility: field::Visibility,
+    #[serde(default)]
+    pub burn_after: field::BurnAfter,
+    #[serde(default)]
+    pub tags: field::Tags,
 }
\ No newline at end of file
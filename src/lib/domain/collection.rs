@@ -0,0 +1,47 @@
+use crate::data::DbId;
+use crate::domain::clip::field::{Password, Posted, ShortCode, Title};
+use crate::Clip;
+use derive_more::Constructor;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Constructor, Debug, Default, Deserialize, Serialize)]
+pub struct CollectionId(DbId);
+
+impl CollectionId {
+    pub fn into_inner(self) -> DbId {
+        self.0
+    }
+}
+
+// clips grouped under a shortcode of their own, e.g. the pastes of one incident.
+// the password guards the index only, every clip keeps its own protection
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Collection {
+    #[serde(skip)]
+    pub collection_id: CollectionId,
+    pub shortcode: ShortCode,
+    pub title: Title,
+    pub posted: Posted,
+    #[serde(skip)]
+    pub password: Password,
+}
+
+impl Collection {
+    // lockout key of the collection: ':' is not part of the shortcode grammar, so it never equals a clip's
+    pub fn lockout_key(&self) -> ShortCode {
+        ShortCode::from(format!("collection:{}", self.shortcode.as_str()))
+    }
+}
+
+// what the index of a collection shows about a clip
+#[derive(Debug, Clone, Serialize)]
+pub struct Member {
+    pub shortcode: ShortCode,
+    pub title: Title,
+}
+
+impl From<Clip> for Member {
+    fn from(clip: Clip) -> Self {
+        Self { shortcode: clip.shortcode, title: clip.title }
+    }
+}
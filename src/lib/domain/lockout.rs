@@ -0,0 +1,185 @@
+use crate::ShortCode;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, thiserror::Error)]
+#[error("too many wrong passwords, try again in {0} seconds")]
+pub struct LockoutError(pub u64);
+
+#[derive(Debug, Clone)]
+pub struct LockoutPolicy {
+    // wrong passwords a client may enter before having to wait
+    pub free_attempts: u32,
+    // failures after which a client is locked out of a clip
+    pub max_client_failures: u32,
+    // failures from all clients after which a clip is locked
+    pub max_clip_failures: u32,
+    pub lockout: Duration,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            max_client_failures: 10,
+            max_clip_failures: 50,
+            lockout: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Clip(ShortCode),
+    Client(ShortCode, Option<IpAddr>),
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    blocked_until: Instant,
+}
+
+// failed password attempts, tracked per clip and per client of a clip:
+// the wait doubles with every failure past the free ones, up to a full lockout.
+// failures are only kept in the memory of this process, so every replica counts its own
+// and a restart forgets them. clones share the same failures
+#[derive(Clone)]
+pub struct Lockout {
+    policy: LockoutPolicy,
+    failures: Arc<Mutex<HashMap<Key, Failures>>>,
+}
+
+impl Lockout {
+    pub fn new(policy: LockoutPolicy) -> Self {
+        Self { policy, failures: Arc::new(Mutex::new(HashMap::new())) }
+    }
+
+    fn keys(shortcode: &ShortCode, client: Option<IpAddr>) -> [Key; 2] {
+        [Key::Clip(shortcode.clone()), Key::Client(shortcode.clone(), client)]
+    }
+
+    // a clip only gets locked outright: backing off on every failure of any client
+    // would let a single client slow down everyone else
+    fn wait(&self, key: &Key, count: u32) -> Duration {
+        match key {
+            Key::Clip(_) if count >= self.policy.max_clip_failures => self.policy.lockout,
+            Key::Client(..) if count >= self.policy.max_client_failures => self.policy.lockout,
+            Key::Client(..) if count > self.policy.free_attempts => {
+                let exponent = (count - self.policy.free_attempts - 1).min(16);
+                Duration::from_secs(1 << exponent).min(self.policy.lockout)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn check(&self, shortcode: &ShortCode, client: Option<IpAddr>) -> Result<(), LockoutError> {
+        let now = Instant::now();
+        let failures = self.failures.lock();
+        let wait = Self::keys(shortcode, client)
+            .iter()
+            .filter_map(|key| failures.get(key))
+            .map(|failures| failures.blocked_until.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default();
+
+        if wait.is_zero() {
+            Ok(())
+        } else {
+            // round up so clients never retry too early
+            Err(LockoutError(wait.as_secs_f64().ceil() as u64))
+        }
+    }
+
+    pub fn failure(&self, shortcode: &ShortCode, client: Option<IpAddr>) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock();
+        for key in Self::keys(shortcode, client) {
+            let wait = |count| self.wait(&key, count);
+            let entry = failures.entry(key.clone()).or_insert(Failures { count: 0, last: now, blocked_until: now });
+            entry.count += 1;
+            entry.last = now;
+            entry.blocked_until = now + wait(entry.count);
+        }
+    }
+
+    pub fn success(&self, shortcode: &ShortCode, client: Option<IpAddr>) {
+        let mut failures = self.failures.lock();
+        for key in Self::keys(shortcode, client) {
+            failures.remove(&key);
+        }
+    }
+
+    // forget clients that stopped guessing a while ago, their wait is over.
+    // runs in the maintenance task rather than on every failure, which would scan all of them
+    pub fn prune(&self) -> usize {
+        let now = Instant::now();
+        let lockout = self.policy.lockout;
+        let mut failures = self.failures.lock();
+        let before = failures.len();
+        failures.retain(|_, failures| now.duration_since(failures.last) < lockout);
+        before - failures.len()
+    }
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self::new(LockoutPolicy::default())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::domain::lockout::{Lockout, LockoutPolicy};
+    use crate::ShortCode;
+    use std::time::Duration;
+
+    #[test]
+    fn backs_off_after_free_attempts() {
+        let lockout = Lockout::new(LockoutPolicy {
+            free_attempts: 2,
+            max_client_failures: 4,
+            max_clip_failures: 6,
+            lockout: Duration::from_secs(60),
+        });
+        let shortcode = ShortCode::from("abc123");
+        let (alice, mallory) = (Some([10, 0, 0, 1].into()), Some([10, 0, 0, 2].into()));
+
+        lockout.failure(&shortcode, mallory);
+        lockout.failure(&shortcode, mallory);
+        assert!(lockout.check(&shortcode, mallory).is_ok());
+
+        lockout.failure(&shortcode, mallory);
+        assert!(lockout.check(&shortcode, mallory).is_err());
+        assert!(lockout.check(&shortcode, alice).is_ok());
+
+        lockout.failure(&shortcode, mallory);
+        assert_eq!(lockout.check(&shortcode, mallory).unwrap_err().0, 60);
+
+        // too many failures across clients lock the clip for everyone
+        lockout.failure(&shortcode, alice);
+        lockout.failure(&shortcode, alice);
+        assert!(lockout.check(&shortcode, alice).is_err());
+
+        lockout.success(&shortcode, alice);
+        assert!(lockout.check(&shortcode, alice).is_ok());
+        assert!(lockout.check(&shortcode, mallory).is_err());
+    }
+
+    #[test]
+    fn prunes_clients_that_stopped_guessing() {
+        let lockout = Lockout::new(LockoutPolicy { lockout: Duration::from_millis(20), ..Default::default() });
+        let shortcode = ShortCode::from("abc123");
+        lockout.failure(&shortcode, Some([10, 0, 0, 1].into()));
+        assert_eq!(lockout.prune(), 0);
+
+        std::thread::sleep(Duration::from_millis(30));
+        // the clip and the client
+        assert_eq!(lockout.prune(), 2);
+        assert!(lockout.failures.lock().is_empty());
+    }
+}
@@ -1,23 +1,58 @@
 use crate::data::DbPool;
+use crate::domain::lockout::Lockout;
 use crate::service;
+use crate::web::Metrics;
+use crate::Time;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::runtime::Handle;
 
-pub struct Maintenance;
+pub struct Maintenance {
+    // unix time of the latest pass, successful or not
+    last_run: Arc<AtomicI64>,
+}
 
 impl Maintenance {
-    pub fn spawn(pool: DbPool, handle: Handle) -> Self {
+    pub const INTERVAL: Duration = Duration::from_secs(10);
+
+    pub fn spawn(pool: DbPool, handle: Handle, metrics: Metrics, lockout: Lockout) -> Self {
+        let last_run = Arc::new(AtomicI64::new(chrono::Utc::now().timestamp()));
+        let task_last_run = last_run.clone();
         // do not block
         handle.spawn(async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(10));
+            let mut interval = tokio::time::interval(Self::INTERVAL);
             loop {
                 interval.tick().await;
 
-                if let Err(e) = service::action::delete_expired(&pool).await {
-                    eprintln!("failed to deleted expired clips: {:?}", e);
+                match service::action::delete_expired(&pool).await {
+                    Ok(deleted) => metrics.clips_expired.inc_by(deleted),
+                    Err(e) => tracing::error!(error = ?e, "failed to delete expired clips"),
+                }
+                if let Err(e) = service::action::delete_stale_visitors(&pool).await {
+                    tracing::error!(error = ?e, "failed to delete visitors of past days");
+                }
+                if let Err(e) = service::action::delete_old_views(&pool).await {
+                    tracing::error!(error = ?e, "failed to delete old view stats");
+                }
+                if let Err(e) = service::action::delete_expired_sessions(&pool).await {
+                    tracing::error!(error = ?e, "failed to delete expired sessions");
                 }
+                lockout.prune();
+                metrics.maintenance_runs.inc();
+                task_last_run.store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
             }
         });
-        Self
+        Self { last_run }
+    }
+
+    pub fn last_run(&self) -> Time {
+        Time::from_timestamp(self.last_run.load(Ordering::Relaxed))
+    }
+
+    // a few missed passes mean the loop is stuck or gone
+    pub fn is_ticking(&self) -> bool {
+        let stale_after = 3 * Self::INTERVAL.as_secs() as i64;
+        chrono::Utc::now().timestamp() - self.last_run().timestamp() <= stale_after
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,16 @@
 pub mod clip;
 pub use clip::Clip;
+pub mod collection;
+pub use collection::Collection;
 
 mod time;
 pub mod maintenance;
+pub mod lockout;
+pub mod quota;
+pub mod share;
+pub mod stats;
+pub mod user;
+pub mod words;
+pub use user::User;
 
 pub use time::Time;
\ No newline at end of file
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, thiserror::Error)]
+pub enum QuotaError {
+    #[error("clip quota exceeded: at most {0} clips per API key")]
+    Clips(u64),
+
+    #[error("storage quota exceeded: at most {0} bytes per API key")]
+    Storage(u64),
+}
+
+// limits applied to the clips created with a given API key
+// None means unlimited
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Quota {
+    pub max_clips: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Quota {
+    // usage is the one the key would have once the operation succeeded
+    pub fn check(&self, usage: &Usage) -> Result<(), QuotaError> {
+        match (self.max_clips, self.max_bytes) {
+            (Some(max), _) if usage.clips > max => Err(QuotaError::Clips(max)),
+            (_, Some(max)) if usage.bytes > max => Err(QuotaError::Storage(max)),
+            _ => Ok(())
+        }
+    }
+
+    // an edit is only rejected when it grows the usage past the quota: a key that is
+    // already over it (e.g. after the limits were lowered) can still shrink its clips
+    pub fn check_change(&self, before: &Usage, after: &Usage) -> Result<(), QuotaError> {
+        match (self.max_clips, self.max_bytes) {
+            (Some(max), _) if after.clips > max && after.clips > before.clips => Err(QuotaError::Clips(max)),
+            (_, Some(max)) if after.bytes > max && after.bytes > before.bytes => Err(QuotaError::Storage(max)),
+            _ => Ok(())
+        }
+    }
+}
+
+// live (not expired) clips stored by an API key
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Usage {
+    pub clips: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    pub fn with_clip(self, bytes: u64) -> Self {
+        Self { clips: self.clips + 1, bytes: self.bytes + bytes }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::domain::clip::field::{ClipId, Password};
+use crate::domain::Time;
+use crate::ShortCode;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ShareError {
+    #[error("this share link has expired")]
+    Expired,
+    #[error("invalid share link")]
+    InvalidSignature,
+    #[error("share links must be valid for 1 to {0} hours")]
+    InvalidDuration(u32),
+}
+
+// per-clip key share links are signed with: rotating it revokes every link handed out so far.
+// links are bound to the clip id rather than its shortcode, so they survive renames
+#[derive(Debug, Clone)]
+pub struct ShareSecret(Vec<u8>);
+
+impl ShareSecret {
+    pub fn into_inner(self) -> Vec<u8> {
+        self.0
+    }
+
+    fn mac(&self, message: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("HMAC accepts keys of any length");
+        mac.update(message.as_bytes());
+        mac
+    }
+
+    fn sign(&self, message: &str) -> String {
+        base64::encode_config(self.mac(message).finalize().into_bytes(), base64::URL_SAFE_NO_PAD)
+    }
+
+    fn verify(&self, message: &str, sig: &str) -> Result<(), ShareError> {
+        let sig = base64::decode_config(sig, base64::URL_SAFE_NO_PAD).map_err(|_| ShareError::InvalidSignature)?;
+        self.mac(message).verify_slice(&sig).map_err(|_| ShareError::InvalidSignature)
+    }
+}
+
+impl Default for ShareSecret {
+    fn default() -> Self {
+        Self((0..32).map(|_| rand::random::<u8>()).collect())
+    }
+}
+
+impl From<Vec<u8>> for ShareSecret {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
+// grants read access to a single clip until `exp`, bypassing its password and visibility
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ShareLink {
+    sig: String,
+    exp: i64,
+}
+
+impl ShareLink {
+    pub const MAX_HOURS: u32 = 24 * 30;
+
+    pub fn new(sig: String, exp: i64) -> Self {
+        Self { sig, exp }
+    }
+
+    pub fn sign(secret: &ShareSecret, clip_id: &ClipId, hours: u32) -> Result<Self, ShareError> {
+        if !(1..=Self::MAX_HOURS).contains(&hours) {
+            return Err(ShareError::InvalidDuration(Self::MAX_HOURS));
+        }
+
+        let exp = expiry(hours.into());
+        Ok(Self::new(secret.sign(&Self::message(clip_id, exp)), exp))
+    }
+
+    fn message(clip_id: &ClipId, exp: i64) -> String {
+        format!("{}:{}", subject(clip_id), exp)
+    }
+
+    pub fn verify(&self, secret: &ShareSecret, clip_id: &ClipId) -> Result<(), ShareError> {
+        secret.verify(&Self::message(clip_id, self.exp), &self.sig)?;
+        check_expiry(self.exp)
+    }
+
+    pub fn expires(&self) -> Time {
+        expires(self.exp)
+    }
+
+    // relative url of the clip page, the signature is url safe base64
+    pub fn url(&self, shortcode: &ShortCode) -> String {
+        format!("/clip/{}?sig={}&exp={}", shortcode.as_str(), self.sig, self.exp)
+    }
+}
+
+// proves that the password of a clip was entered, for that clip only and until `exp`:
+// changing the password or rotating the share secret invalidates it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UnlockToken {
+    sig: String,
+    exp: i64,
+}
+
+impl UnlockToken {
+    pub const TTL_HOURS: i64 = 24;
+
+    pub fn sign(secret: &ShareSecret, clip_id: &ClipId, password: &Password) -> Self {
+        let exp = expiry(Self::TTL_HOURS);
+        Self { sig: secret.sign(&Self::message(clip_id, exp, password)), exp }
+    }
+
+    fn message(clip_id: &ClipId, exp: i64, password: &Password) -> String {
+        format!("unlock:{}:{}:{}", subject(clip_id), exp, password.clone().into_inner().unwrap_or_default())
+    }
+
+    pub fn verify(&self, secret: &ShareSecret, clip_id: &ClipId, password: &Password) -> Result<(), ShareError> {
+        secret.verify(&Self::message(clip_id, self.exp, password), &self.sig)?;
+        check_expiry(self.exp)
+    }
+
+    pub fn expires(&self) -> Time {
+        expires(self.exp)
+    }
+}
+
+// sent as `<exp>.<sig>` in cookies and headers
+impl fmt::Display for UnlockToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.exp, self.sig)
+    }
+}
+
+impl FromStr for UnlockToken {
+    type Err = ShareError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (exp, sig) = s.split_once('.').ok_or(ShareError::InvalidSignature)?;
+        let exp = exp.parse().map_err(|_| ShareError::InvalidSignature)?;
+        Ok(Self { sig: sig.to_owned(), exp })
+    }
+}
+
+fn subject(clip_id: &ClipId) -> String {
+    clip_id.clone().into_inner().to_string()
+}
+
+fn expiry(hours: i64) -> i64 {
+    (chrono::Utc::now() + chrono::Duration::hours(hours)).timestamp()
+}
+
+fn check_expiry(exp: i64) -> Result<(), ShareError> {
+    if exp < chrono::Utc::now().timestamp() {
+        Err(ShareError::Expired)
+    } else {
+        Ok(())
+    }
+}
+
+fn expires(exp: i64) -> Time {
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    Time::from(DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(exp, 0), Utc))
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::data::DbId;
+    use crate::domain::clip::field::{ClipId, Password};
+    use crate::domain::share::{ShareError, ShareLink, ShareSecret, UnlockToken};
+
+    #[test]
+    fn verifies_signed_links() {
+        let secret = ShareSecret::default();
+        let clip_id = ClipId::new(DbId::new());
+        let link = ShareLink::sign(&secret, &clip_id, 4).unwrap();
+        assert!(link.verify(&secret, &clip_id).is_ok());
+
+        // tampered, other clip, rotated secret
+        let extended = ShareLink::new(link.sig.clone(), link.exp + 3600);
+        assert!(matches!(extended.verify(&secret, &clip_id), Err(ShareError::InvalidSignature)));
+        assert!(matches!(link.verify(&secret, &ClipId::new(DbId::new())), Err(ShareError::InvalidSignature)));
+        assert!(matches!(link.verify(&ShareSecret::default(), &clip_id), Err(ShareError::InvalidSignature)));
+
+        assert!(ShareLink::sign(&secret, &clip_id, 0).is_err());
+        assert!(ShareLink::sign(&secret, &clip_id, ShareLink::MAX_HOURS + 1).is_err());
+    }
+
+    #[test]
+    fn rejects_expired_links() {
+        let secret = ShareSecret::default();
+        let clip_id = ClipId::new(DbId::new());
+        let exp = chrono::Utc::now().timestamp() - 1;
+        let link = ShareLink::new(secret.sign(&ShareLink::message(&clip_id, exp)), exp);
+        assert!(matches!(link.verify(&secret, &clip_id), Err(ShareError::Expired)));
+    }
+
+    #[test]
+    fn scopes_unlock_tokens_to_clip_and_password() {
+        let secret = ShareSecret::default();
+        let clip_id = ClipId::new(DbId::new());
+        let password = Password::new("hunter22".to_owned()).unwrap();
+        let token: UnlockToken = UnlockToken::sign(&secret, &clip_id, &password).to_string().parse().unwrap();
+
+        assert!(token.verify(&secret, &clip_id, &password).is_ok());
+        assert!(token.verify(&secret, &ClipId::new(DbId::new()), &password).is_err());
+        assert!(token.verify(&secret, &clip_id, &Password::new("changed".to_owned()).unwrap()).is_err());
+        assert!("garbage".parse::<UnlockToken>().is_err());
+    }
+}
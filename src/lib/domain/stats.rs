@@ -0,0 +1,272 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use parking_lot::Mutex;
+use rocket::http::uri::Absolute;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use strum::{Display, EnumString};
+
+use crate::domain::Time;
+
+// kind of client a clip was viewed with
+#[derive(Clone, Copy, Debug, Hash, Deserialize, Serialize, Display, EnumString, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Agent {
+    Browser,
+    Cli,
+    Api,
+}
+
+impl Agent {
+    const CLI_AGENTS: [&'static str; 5] = ["curl", "wget", "httpie", "powershell", "xh"];
+
+    // command line tools are told apart by their user agent, anything else on the API is a program
+    pub fn classify(user_agent: Option<&str>, api: bool) -> Self {
+        let user_agent = user_agent.unwrap_or_default().to_lowercase();
+        let product = user_agent.split('/').next().unwrap_or_default().trim();
+        if Self::CLI_AGENTS.contains(&product) {
+            Agent::Cli
+        } else if api {
+            Agent::Api
+        } else {
+            Agent::Browser
+        }
+    }
+}
+
+// a single view of a clip, reduced to what its stats keep
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct View {
+    // start of the hour the view falls into, as a unix timestamp
+    pub hour: i64,
+    // host of the referring page, None for direct visits
+    pub referrer: Option<String>,
+    pub agent: Agent,
+}
+
+impl View {
+    pub const HOUR: i64 = 60 * 60;
+    pub const DAY: i64 = 24 * Self::HOUR;
+
+    pub fn new(referrer: Option<&str>, agent: Agent) -> Self {
+        Self {
+            hour: Self::bucket(Utc::now().timestamp(), Self::HOUR),
+            referrer: referrer.and_then(Self::referrer_host),
+            agent,
+        }
+    }
+
+    pub fn bucket(timestamp: i64, size: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(size)
+    }
+
+    // only the host is kept, paths and queries of the referring page may be private
+    fn referrer_host(referrer: &str) -> Option<String> {
+        let referrer = Absolute::parse(referrer).ok()?;
+        Some(referrer.authority()?.host().to_lowercase()).filter(|host| !host.is_empty())
+    }
+}
+
+// a client that viewed clips today, known only by a keyed hash of its IP address and user agent
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Visitor {
+    // start of the day the key was used on
+    pub day: i64,
+    // hex encoded, truncated HMAC
+    pub id: String,
+}
+
+// key visitors of one day are hashed with. it is random, shared by all replicas through the database
+// and deleted once the day is over, so that hashes can neither be reversed nor linked across days
+#[derive(Clone)]
+pub struct VisitorSalt {
+    day: i64,
+    key: Vec<u8>,
+}
+
+impl Default for VisitorSalt {
+    fn default() -> Self {
+        Self::new(Self::today(), rand::random::<[u8; 32]>().to_vec())
+    }
+}
+
+impl VisitorSalt {
+    pub fn new(day: i64, key: Vec<u8>) -> Self {
+        Self { day, key }
+    }
+
+    // start of the current day
+    pub fn today() -> i64 {
+        View::bucket(Utc::now().timestamp(), View::DAY)
+    }
+
+    pub fn day(&self) -> i64 {
+        self.day
+    }
+
+    // None when the client can't be told apart from others
+    pub fn visitor(&self, ip: Option<IpAddr>, user_agent: Option<&str>) -> Option<Visitor> {
+        let ip = ip?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(ip.to_string().as_bytes());
+        mac.update(b"\n");
+        mac.update(user_agent.unwrap_or_default().as_bytes());
+        let id = mac.finalize().into_bytes()[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+        Some(Visitor { day: self.day, id })
+    }
+}
+
+// the salt of the current day, so that it is only read from the database once a day
+#[derive(Default)]
+pub struct SaltCache(Mutex<Option<VisitorSalt>>);
+
+impl SaltCache {
+    pub fn get(&self, day: i64) -> Option<VisitorSalt> {
+        self.0.lock().clone().filter(|salt| salt.day == day)
+    }
+
+    pub fn set(&self, salt: VisitorSalt) {
+        *self.0.lock() = Some(salt);
+    }
+}
+
+// number of views of one hour or day
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Bucket {
+    pub start: Time,
+    pub views: u64,
+}
+
+// number of views from one referrer or kind of client
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Count {
+    pub name: String,
+    pub views: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClipStats {
+    pub hits: u64,
+    pub visitors: u64,
+    // the last `HOURS` hours and `DAYS` days, oldest first
+    pub hourly: Vec<Bucket>,
+    pub daily: Vec<Bucket>,
+    // most views first, direct visits have no referrer
+    pub referrers: Vec<Count>,
+    pub agents: Vec<Count>,
+    // hourly views drawn with block characters
+    pub sparkline: String,
+}
+
+impl ClipStats {
+    pub const HOURS: i64 = 24;
+    pub const DAYS: i64 = 30;
+    const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    // first hour whose views are needed
+    pub fn since(now: i64) -> i64 {
+        View::bucket(now, View::DAY) - (Self::DAYS - 1) * View::DAY
+    }
+
+    pub fn new(hits: u64, visitors: u64, views: Vec<(View, u64)>, now: i64) -> Self {
+        let buckets = |size: i64, count: i64| {
+            let last = View::bucket(now, size);
+            let mut buckets: Vec<(i64, u64)> = (0..count).rev().map(|i| (last - i * size, 0)).collect();
+            for (view, n) in &views {
+                let start = View::bucket(view.hour, size);
+                if let Some(bucket) = buckets.iter_mut().find(|(s, _)| *s == start) {
+                    bucket.1 += n;
+                }
+            }
+            buckets
+        };
+        let counts = |name: &dyn Fn(&View) -> Option<String>| {
+            let mut counts: HashMap<String, u64> = HashMap::new();
+            for (view, n) in &views {
+                if let Some(name) = name(view) {
+                    *counts.entry(name).or_insert(0) += n;
+                }
+            }
+            let mut counts: Vec<Count> = counts.into_iter().map(|(name, views)| Count { name, views }).collect();
+            counts.sort_by(|a, b| b.views.cmp(&a.views).then_with(|| a.name.cmp(&b.name)));
+            counts
+        };
+        let to_buckets = |buckets: Vec<(i64, u64)>| buckets
+            .into_iter()
+            .map(|(start, views)| Bucket { start: Time::from_timestamp(start), views })
+            .collect::<Vec<_>>();
+
+        let hourly = buckets(View::HOUR, Self::HOURS);
+        let max = hourly.iter().map(|(_, views)| *views).max().unwrap_or_default();
+        let sparkline = hourly
+            .iter()
+            .map(|(_, views)| match max {
+                0 => Self::SPARKS[0],
+                max => Self::SPARKS[((views * (Self::SPARKS.len() as u64 - 1)) / max) as usize],
+            })
+            .collect();
+
+        Self {
+            hits,
+            visitors,
+            daily: to_buckets(buckets(View::DAY, Self::DAYS)),
+            hourly: to_buckets(hourly),
+            referrers: counts(&|view| view.referrer.clone()),
+            agents: counts(&|view| Some(view.agent.to_string())),
+            sparkline,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::{Agent, ClipStats, View, VisitorSalt};
+
+    #[test]
+    fn classifies_views() {
+        assert_eq!(Agent::classify(Some("curl/7.85.0"), false), Agent::Cli);
+        assert_eq!(Agent::classify(None, true), Agent::Api);
+        assert_eq!(Agent::classify(Some("Mozilla/5.0 (X11; Linux x86_64)"), false), Agent::Browser);
+
+        let view = View::new(Some("https://Chat.Example.com/room/42?secret=1"), Agent::Browser);
+        assert_eq!(view.referrer.as_deref(), Some("chat.example.com"));
+        assert!(View::new(Some("not a url"), Agent::Browser).referrer.is_none());
+    }
+
+    #[test]
+    fn hashes_visitors() {
+        let salt = VisitorSalt::default();
+        let ip = Some("192.0.2.1".parse().unwrap());
+        let visitor = salt.visitor(ip, Some("curl/7.85.0")).unwrap();
+        assert_eq!(visitor, salt.visitor(ip, Some("curl/7.85.0")).unwrap());
+        assert_eq!(visitor.id.len(), 32);
+        assert_ne!(visitor, salt.visitor(ip, Some("Mozilla/5.0")).unwrap());
+        assert_ne!(visitor, VisitorSalt::default().visitor(ip, Some("curl/7.85.0")).unwrap());
+        let shared = VisitorSalt::new(salt.day(), salt.key.clone());
+        assert_eq!(visitor, shared.visitor(ip, Some("curl/7.85.0")).unwrap());
+        assert!(salt.visitor(None, Some("curl/7.85.0")).is_none());
+    }
+
+    #[test]
+    fn buckets_views_by_hour_and_day() {
+        let now = 10 * View::DAY + 5 * View::HOUR + 17;
+        let view = |hour: i64, referrer: Option<&str>, agent| View { hour, referrer: referrer.map(str::to_owned), agent };
+        let stats = ClipStats::new(7, 2, vec![
+            (view(10 * View::DAY + 5 * View::HOUR, Some("example.com"), Agent::Browser), 4),
+            (view(10 * View::DAY + 4 * View::HOUR, None, Agent::Cli), 2),
+            (view(9 * View::DAY, Some("example.com"), Agent::Api), 1),
+        ], now);
+
+        assert_eq!(stats.hourly.len() as i64, ClipStats::HOURS);
+        assert_eq!(stats.hourly.last().unwrap().views, 4);
+        assert_eq!(stats.daily.len() as i64, ClipStats::DAYS);
+        assert_eq!(stats.daily.iter().rev().take(2).map(|bucket| bucket.views).collect::<Vec<_>>(), vec![6, 1]);
+        assert_eq!(stats.referrers[0].name, "example.com");
+        assert_eq!(stats.referrers[0].views, 5);
+        assert_eq!(stats.agents[0].name, "browser");
+        assert!(stats.sparkline.ends_with("▄█"));
+    }
+}
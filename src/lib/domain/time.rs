@@ -18,6 +18,10 @@ impl Time {
     pub fn from_naive_utc(datetime: NaiveDateTime) -> Self {
         Time(DateTime::from_utc(datetime, Utc))
     }
+
+    pub fn from_timestamp(timestamp: i64) -> Self {
+        Time(DateTime::from_utc(NaiveDateTime::from_timestamp(timestamp, 0), Utc))
+    }
 }
 
 impl FromStr for Time {
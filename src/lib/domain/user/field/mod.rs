@@ -0,0 +1,8 @@
+mod user_id;
+pub use user_id::UserId;
+
+mod username;
+pub use username::Username;
+
+mod password;
+pub use password::Password;
@@ -0,0 +1,44 @@
+use crate::domain::user::UserError;
+
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rocket::form::{self, FromFormField, ValueField};
+
+const MIN_LENGTH: usize = 8;
+
+// account password, only ever stored as an argon2 hash
+#[derive(Clone, Debug)]
+pub struct Password(String);
+
+impl Password {
+    pub fn new(password: &str) -> Result<Self, UserError> {
+        if password.chars().count() < MIN_LENGTH {
+            Err(UserError::InvalidPassword(format!("must be at least {} characters long", MIN_LENGTH)))
+        } else {
+            Ok(Self(password.to_owned()))
+        }
+    }
+
+    pub fn hash(&self) -> Result<String, UserError> {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(self.0.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| UserError::Hash(e.to_string()))
+    }
+
+    // login attempts are checked as typed, without the length requirement of new passwords
+    pub fn verify(password: &str, hash: &str) -> bool {
+        match PasswordHash::new(hash) {
+            Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+            Err(_) => false
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for Password {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        Ok(Self::new(field.value).map_err(|e| form::Error::validation(format!("{}", e)))?)
+    }
+}
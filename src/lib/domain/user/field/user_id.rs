@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+use crate::data::DbId;
+use crate::domain::user::UserError;
+use derive_more::Constructor;
+use std::str::FromStr;
+
+#[derive(Clone, Constructor, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UserId(DbId);
+
+impl UserId {
+    pub fn into_inner(self) -> DbId {
+        self.0
+    }
+}
+
+impl From<DbId> for UserId {
+    fn from(id: DbId) -> Self {
+        Self(id)
+    }
+}
+
+impl Default for UserId {
+    fn default() -> Self {
+        Self(DbId::nil())
+    }
+}
+
+// session cookies store the id as a string
+impl FromStr for UserId {
+    type Err = UserError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(DbId::from_str(s)?))
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use crate::domain::user::UserError;
+
+use rocket::form::{self, FromFormField, ValueField};
+use std::str::FromStr;
+
+const MIN_LENGTH: usize = 3;
+const MAX_LENGTH: usize = 32;
+
+// usernames are compared case insensitively, so they are stored lowercased
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Username(String);
+
+impl Username {
+    pub fn new(username: &str) -> Result<Self, UserError> {
+        let username = username.trim().to_lowercase();
+        if username.len() < MIN_LENGTH || username.len() > MAX_LENGTH {
+            Err(UserError::InvalidUsername(format!(
+                "must be between {} and {} characters long", MIN_LENGTH, MAX_LENGTH
+            )))
+        } else if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            Err(UserError::InvalidUsername(
+                "only letters, digits, '-' and '_' are allowed".to_owned()
+            ))
+        } else {
+            Ok(Self(username))
+        }
+    }
+
+    // closest valid username to a name given by an identity provider,
+    // `suffix` disambiguates between users suggested the same name
+    pub fn suggest(hint: &str, suffix: Option<u32>) -> Self {
+        let mut username: String = hint
+            .trim()
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+            .collect();
+        if username.len() < MIN_LENGTH {
+            username = format!("user-{}", username);
+        }
+
+        let suffix = suffix.map(|n| format!("-{}", n)).unwrap_or_default();
+        username.truncate(MAX_LENGTH - suffix.len());
+        Self(username + suffix.as_str())
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    pub fn into_inner(self) -> String {
+        self.0
+    }
+}
+
+impl FromStr for Username {
+    type Err = UserError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for Username {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        Ok(Self::new(field.value).map_err(|e| form::Error::validation(format!("{}", e)))?)
+    }
+}
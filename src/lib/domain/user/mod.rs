@@ -0,0 +1,27 @@
+pub mod field;
+
+use thiserror::Error;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Error)]
+pub enum UserError {
+    #[error("invalid username: {0}")]
+    InvalidUsername(String),
+
+    #[error("invalid password: {0}")]
+    InvalidPassword(String),
+
+    #[error("password hashing error: {0}")]
+    Hash(String),
+
+    #[error("id parse error: {0}")]
+    Id(#[from] uuid::Error),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct User {
+    #[serde(skip)]
+    pub user_id: field::UserId,
+    pub username: field::Username,
+    pub created: crate::domain::Time,
+}
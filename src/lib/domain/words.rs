@@ -0,0 +1,20 @@
+use rand::seq::SliceRandom;
+
+// short, common and distinct words, one per line
+const WORDS: &str = include_str!("words.txt");
+
+pub fn random_words(count: usize) -> Vec<&'static str> {
+    let words: Vec<&str> = WORDS.lines().collect();
+    let mut rng = rand::thread_rng();
+    (0..count).filter_map(|_| words.choose(&mut rng).copied()).collect()
+}
+
+// bits of entropy a single random word adds
+pub fn bits_per_word() -> f64 {
+    (WORDS.lines().count() as f64).log2()
+}
+
+// characters in the longest word
+pub fn max_word_length() -> usize {
+    WORDS.lines().map(str::len).max().unwrap_or(0)
+}
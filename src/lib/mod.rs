@@ -18,17 +18,31 @@ use crate::data::Db;
 
 pub mod web;
 
+use rocket::fairing::AdHoc;
 use rocket::fs::FileServer;
 use rocket::{Build, Rocket};
 use web::{renderer::Renderer};
+use crate::domain::lockout::Lockout;
 use crate::domain::maintenance::Maintenance;
+use crate::domain::clip::field::{PasswordPolicy, ShortCodeGenerator};
+use crate::domain::quota::Quota;
+use crate::domain::stats::SaltCache;
 use crate::web::hit_counter::HitCounter;
+use crate::web::oidc::OidcConfig;
+use crate::web::{Metrics, SecureCookies};
 
 pub struct RocketConfig {
     pub renderer: Renderer<'static>,
     pub db: Db,
     pub hit_counter: HitCounter,
-    pub maintenance: Maintenance
+    pub maintenance: Maintenance,
+    pub lockout: Lockout,
+    pub quota: Quota,
+    pub password_policy: PasswordPolicy,
+    pub shortcodes: ShortCodeGenerator,
+    pub oidc: Option<OidcConfig>,
+    pub secure_cookies: SecureCookies,
+    pub metrics: Metrics,
 }
 
 pub fn rocket(config: RocketConfig) -> Rocket<Build> {
@@ -37,11 +51,35 @@ pub fn rocket(config: RocketConfig) -> Rocket<Build> {
         .manage::<Renderer>(config.renderer)
         .manage::<HitCounter>(config.hit_counter)
         .manage::<Maintenance>(config.maintenance)
+        .manage::<Lockout>(config.lockout)
+        .manage::<Quota>(config.quota)
+        .manage::<PasswordPolicy>(config.password_policy)
+        .manage::<ShortCodeGenerator>(config.shortcodes)
+        .manage::<Option<OidcConfig>>(config.oidc)
+        .manage::<SecureCookies>(config.secure_cookies)
+        .manage::<SaltCache>(SaltCache::default())
+        .manage::<Metrics>(config.metrics.clone())
         .mount("/", web::http::routes())
+        .mount("/", web::oidc::routes())
         .mount("/api/clip", web::api::routes())
+        .mount("/api/usage", web::api::usage_routes())
+        .mount("/api/collection", web::api::collection_routes())
         .mount("/static", FileServer::from("static"))
+        .mount("/", web::metrics::routes())
+        .mount("/", web::health::routes())
         .register("/", web::http::catcher::catchers())
-        .register("/api/clip", web::api::catcher::catchers())
+        .register("/api", web::api::catcher::catchers())
+        .attach(web::RequestLogger)
+        .attach(AdHoc::on_liftoff("Startup log", |rocket| Box::pin(async move {
+            tracing::info!(address = %rocket.config().address, port = rocket.config().port, "listening");
+        })))
+        .attach(config.metrics)
+        // buffered hits would otherwise be lost on shutdown
+        .attach(AdHoc::on_shutdown("Hit counter commit", |rocket| Box::pin(async move {
+            if let Some(hit_counter) = rocket.state::<HitCounter>() {
+                hit_counter.shutdown(HitCounter::SHUTDOWN_GRACE).await;
+            }
+        })))
 }
 
 #[cfg(test)]
@@ -1,55 +1,884 @@
-use crate::data::{query, DbPool, Tx};
+use crate::data::model;
+use crate::data::repository::{ApiKeyRepository, ClipRepository, CollectionRepository, HealthRepository, UserRepository};
 use crate::service::ask;
-use crate::{Clip, ServiceError, ShortCode};
+use crate::domain::clip::field::{ClipId, Grantee, Password, PasswordPolicy, SharedWith, ShortCodeGenerator, Tags, Visibility};
+use crate::{Clip, ClipError, DataError, ServiceError, ShortCode, Time};
 
-use std::convert::TryInto;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::net::IpAddr;
 use crate::data::query::RevocationStatus;
+use crate::domain::lockout::Lockout;
+use crate::domain::quota::{Quota, Usage};
+use crate::domain::share::{ShareError, ShareLink, ShareSecret, UnlockToken};
+use crate::domain::stats::{ClipStats, View, Visitor, VisitorSalt};
+use crate::domain::user::field::{UserId, Username};
+use crate::domain::collection::Member;
+use crate::domain::{Collection, User};
 use crate::web::api::ApiKey;
 
-pub async fn get_clip(req: ask::GetClip, pool: &DbPool) -> Result<Clip, ServiceError> {
+// API key and user an identity can be attributed to:
+// API keys created from the web UI act on behalf of their user
+async fn principal(
+    identity: ask::Identity,
+    repo: &impl ApiKeyRepository,
+) -> Result<(Option<ApiKey>, Option<UserId>), ServiceError> {
+    Ok(match identity {
+        ask::Identity::Anonymous => (None, None),
+        ask::Identity::User(user_id) => (None, Some(user_id)),
+        ask::Identity::ApiKey(api_key) => {
+            let user_id = repo.get_api_key_user(api_key.clone()).await?;
+            (Some(api_key), user_id)
+        }
+    })
+}
+
+// whether both identities resolve to the same API key or user
+fn same_principal(a: &(Option<ApiKey>, Option<UserId>), b: &(Option<ApiKey>, Option<UserId>)) -> bool {
+    (a.0.is_some() && a.0 == b.0) || (a.1.is_some() && a.1 == b.1)
+}
+
+async fn check_owner(
+    owner: ask::Identity,
+    requester: ask::Identity,
+    repo: &impl ApiKeyRepository,
+) -> Result<(), ServiceError> {
+    if same_principal(&principal(requester, repo).await?, &principal(owner, repo).await?) {
+        Ok(())
+    } else {
+        Err(ServiceError::AccessDenied("Only the creator of this clip can do this".to_owned()))
+    }
+}
+
+// private clips are only visible to their creator, shared ones also to their allow-list
+async fn check_visibility(
+    clip: &Clip,
+    owner: ask::Identity,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<(), ServiceError> {
+    if !clip.visibility.is_restricted() {
+        return Ok(());
+    }
+
+    let requester = principal(requester, repo).await?;
+    if same_principal(&requester, &principal(owner, repo).await?) {
+        return Ok(());
+    }
+
+    if clip.visibility == Visibility::SharedWith {
+        let grants = repo.get_grants(&clip.clip_id).await?;
+        if grants.iter().any(|grant| same_principal(&requester, &(grant.api_key(), grant.user_id()))) {
+            return Ok(());
+        }
+    }
+
+    Err(ServiceError::AccessDenied("This clip is not shared with you".to_owned()))
+}
+
+// users and API keys a clip gets shared with, validating that they exist
+async fn resolve_grants(
+    visibility: Visibility,
+    shared_with: SharedWith,
+    repo: &(impl UserRepository + ApiKeyRepository),
+) -> Result<Vec<model::Grant>, ServiceError> {
+    if visibility != Visibility::SharedWith {
+        return Ok(vec![]);
+    }
+
+    let mut grants = vec![];
+    for grantee in shared_with.into_inner() {
+        match grantee {
+            Grantee::User(username) => match repo.get_user_by_name(&username).await {
+                Ok(user) => grants.push(model::Grant::from(User::try_from(user)?.user_id)),
+                Err(e) => return Err(match ServiceError::from(e) {
+                    ServiceError::NotFound => ClipError::InvalidShare(format!("unknown user {}", username.as_str())).into(),
+                    other => other
+                })
+            },
+            Grantee::ApiKey(api_key) => if repo.api_key_is_valid(api_key.clone()).await? {
+                grants.push(model::Grant::from(api_key))
+            } else {
+                return Err(ClipError::InvalidShare("unknown API key".to_owned()).into());
+            }
+        }
+    }
+    Ok(grants)
+}
+
+// wrong passwords are throttled per clip and client, and burn the clip once its owner's limit is reached
+pub async fn get_clip(
+    req: ask::GetClip,
+    requester: ask::Identity,
+    client: Option<IpAddr>,
+    lockout: &Lockout,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<Clip, ServiceError> {
     let user_password = req.password.clone();
+    let share_link = req.share_link.clone();
+    let unlock_token = req.unlock_token.clone();
     // From impl converts ask GetClip into data GetClip
     // TryFrom impl converts model Clip result into domain Clip
-    let clip: Clip = query::get_clip(req, pool).await?.try_into()?;
+    let clip = repo.get_clip(req).await?;
 
-    if clip.password.has_password() {
-        if clip.password == user_password {
-            Ok(clip)
-        } else {
-            Err(ServiceError::PermissionError("Invalid password".to_owned()))
+    // a valid signed link stands in for both the password and the allow-list
+    if let Some(link) = share_link {
+        let secret = clip.share_secret().ok_or(ShareError::InvalidSignature)?;
+        let clip: Clip = clip.try_into()?;
+        link.verify(&secret, &clip.clip_id)?;
+        return Ok(clip);
+    }
+
+    let owner = clip.owner();
+    let secret = clip.share_secret();
+    let clip: Clip = clip.try_into()?;
+
+    check_visibility(&clip, owner, requester, repo).await?;
+
+    if !clip.password.has_password() {
+        return Ok(clip);
+    }
+    // stale or foreign tokens are ignored, the password is asked for again
+    if let (Some(token), Some(secret)) = (unlock_token, secret) {
+        if token.verify(&secret, &clip.clip_id, &clip.password).is_ok() {
+            return Ok(clip);
+        }
+    }
+    // asking for a protected clip without a password is not a guess
+    if !user_password.has_password() {
+        return Err(ServiceError::PermissionError("Invalid password".to_owned()));
+    }
+
+    lockout.check(&clip.shortcode, client)?;
+    if clip.password == user_password {
+        lockout.success(&clip.shortcode, client);
+        return Ok(clip);
+    }
+
+    lockout.failure(&clip.shortcode, client);
+    let failed_attempts = repo.record_failed_attempt(&clip.shortcode).await?.failed_attempts();
+    match clip.burn_after.into_inner() {
+        Some(limit) if failed_attempts >= u64::from(limit) => {
+            repo.delete_clip(&clip.shortcode).await?;
+            Err(ServiceError::NotFound)
         }
+        _ => Err(ServiceError::PermissionError("Invalid password".to_owned()))
+    }
+}
+
+// get_clip for a password protected clip, along with a token that unlocks it from now on
+pub async fn unlock_clip(
+    req: ask::GetClip,
+    requester: ask::Identity,
+    client: Option<IpAddr>,
+    lockout: &Lockout,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<(Clip, Option<UnlockToken>), ServiceError> {
+    let clip = get_clip(req, requester, client, lockout, repo).await?;
+    if !clip.password.has_password() {
+        return Ok((clip, None));
+    }
+
+    let secret = share_secret(repo.get_clip(clip.shortcode.clone()).await?, repo).await?;
+    let token = UnlockToken::sign(&secret, &clip.clip_id, &clip.password);
+    Ok((clip, Some(token)))
+}
+
+// key signed links and unlock tokens of a clip, created on first use
+async fn share_secret(clip: model::Clip, repo: &impl ClipRepository) -> Result<ShareSecret, ServiceError> {
+    match clip.share_secret() {
+        Some(secret) => Ok(secret),
+        None => {
+            let clip: Clip = clip.try_into()?;
+            let secret = ShareSecret::default();
+            repo.save_share_secret(&clip.shortcode, secret.clone()).await?;
+            Ok(secret)
+        }
+    }
+}
+
+// signs a link with the clip's share secret
+pub async fn share_clip(
+    req: ask::ShareClip,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<ShareLink, ServiceError> {
+    let clip = repo.get_clip(req.shortcode.clone()).await?;
+    check_owner(clip.owner(), requester, repo).await?;
+
+    let clip: Clip = clip.try_into()?;
+    let secret = share_secret(repo.get_clip(clip.shortcode.clone()).await?, repo).await?;
+    Ok(ShareLink::sign(&secret, &clip.clip_id, req.hours)?)
+}
+
+// rotates the share secret so that no link or unlock token handed out so far verifies anymore
+pub async fn revoke_share_links(
+    shortcode: ShortCode,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<(), ServiceError> {
+    let clip = repo.get_clip(shortcode).await?;
+    check_owner(clip.owner(), requester, repo).await?;
+    let clip: Clip = clip.try_into()?;
+    Ok(repo.save_share_secret(&clip.shortcode, ShareSecret::default()).await?)
+}
+
+// the primary shortcode of the clip, when `shortcode` is one of its aliases
+pub async fn resolve_alias(
+    shortcode: &ShortCode,
+    repo: &impl ClipRepository,
+) -> Result<Option<ShortCode>, ServiceError> {
+    let clip: Clip = repo.get_clip(shortcode.clone()).await?.try_into()?;
+    Ok(Some(clip.shortcode).filter(|primary| primary != shortcode))
+}
+
+pub async fn get_aliases(clip: &Clip, repo: &impl ClipRepository) -> Result<Vec<ShortCode>, ServiceError> {
+    Ok(repo.get_aliases(&clip.clip_id).await?.into_iter().map(ShortCode::from).collect())
+}
+
+// aliases follow the rules of custom shortcodes
+pub async fn add_alias(
+    req: ask::Alias,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<Clip, ServiceError> {
+    let clip = repo.get_clip(req.shortcode).await?;
+    check_owner(clip.owner(), requester, repo).await?;
+    let clip: Clip = clip.try_into()?;
+    repo.add_alias(&clip.clip_id, &ShortCode::custom(req.alias.as_str())?).await?;
+    Ok(clip)
+}
+
+pub async fn remove_alias(
+    req: ask::Alias,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<Clip, ServiceError> {
+    let clip = repo.get_clip(req.shortcode).await?;
+    check_owner(clip.owner(), requester, repo).await?;
+    let clip: Clip = clip.try_into()?;
+    repo.remove_alias(&clip.clip_id, &req.alias).await?;
+    Ok(clip)
+}
+
+// links to the current shortcode keep working: it becomes an alias
+pub async fn rename_clip(
+    req: ask::RenameClip,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<Clip, ServiceError> {
+    let clip = repo.get_clip(req.shortcode).await?;
+    check_owner(clip.owner(), requester, repo).await?;
+    let clip: Clip = clip.try_into()?;
+    let renamed = ShortCode::custom(req.renamed.as_str())?;
+    Ok(repo.rename_clip(&clip.clip_id, &clip.shortcode, &renamed).await?.try_into()?)
+}
+
+// only the creator of a clip may collect it, so a collection never exposes clips of others
+async fn collectable_clip(
+    shortcode: ShortCode,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<Clip, ServiceError> {
+    let clip = repo.get_clip(shortcode).await?;
+    check_owner(clip.owner(), requester, repo).await?;
+    Ok(clip.try_into()?)
+}
+
+pub async fn new_collection(
+    req: ask::NewCollection,
+    owner: ask::Identity,
+    policy: &PasswordPolicy,
+    shortcodes: &ShortCodeGenerator,
+    repo: &(impl ClipRepository + ApiKeyRepository + CollectionRepository),
+) -> Result<Collection, ServiceError> {
+    if matches!(owner, ask::Identity::Anonymous) {
+        return Err(ServiceError::AccessDenied("log in or use an API key to create collections".to_owned()));
+    }
+    policy.check(&req.password)?;
+    let shortcode = req.shortcode.as_ref().map(|shortcode| ShortCode::custom(shortcode.as_str())).transpose()?;
+
+    let mut clips = vec![];
+    for shortcode in req.clips.iter().cloned() {
+        clips.push(collectable_clip(shortcode, owner.clone(), repo).await?);
+    }
+
+    let req = ask::NewCollection { shortcode, ..req };
+    let collection: Collection = repo.new_collection((req, owner), shortcodes).await?.try_into()?;
+    for clip in clips {
+        repo.add_collection_clip(&collection.collection_id, &clip.clip_id).await?;
+    }
+    Ok(collection)
+}
+
+// the password of a collection is throttled like the ones of clips
+pub async fn get_collection(
+    req: ask::GetCollection,
+    client: Option<IpAddr>,
+    lockout: &Lockout,
+    repo: &impl CollectionRepository,
+) -> Result<Collection, ServiceError> {
+    let collection: Collection = repo.get_collection(&req.shortcode).await?.try_into()?;
+    if !collection.password.has_password() {
+        return Ok(collection);
+    }
+    if !req.password.has_password() {
+        return Err(ServiceError::PermissionError("Invalid password".to_owned()));
+    }
+
+    let key = collection.lockout_key();
+    lockout.check(&key, client)?;
+    if collection.password == req.password {
+        lockout.success(&key, client);
+        Ok(collection)
     } else {
-        Ok(clip)
+        lockout.failure(&key, client);
+        Err(ServiceError::PermissionError("Invalid password".to_owned()))
     }
 }
 
-pub async fn new_clip(req: ask::NewClip, pool: &DbPool) -> Result<Clip, ServiceError> {
-    Ok(query::new_clip(req, pool).await?.try_into()?)
+pub async fn get_collection_members(
+    collection: &Collection,
+    repo: &impl CollectionRepository,
+) -> Result<Vec<Member>, ServiceError> {
+    repo.get_collection_clips(&collection.collection_id)
+        .await?
+        .into_iter()
+        .map(|clip| Ok(Clip::try_from(clip)?.into()))
+        .collect()
+}
+
+async fn owned_collection(
+    shortcode: &ShortCode,
+    requester: ask::Identity,
+    repo: &(impl CollectionRepository + ApiKeyRepository),
+) -> Result<Collection, ServiceError> {
+    let collection = repo.get_collection(shortcode).await?;
+    check_owner(collection.owner(), requester, repo).await?;
+    Ok(collection.try_into()?)
+}
+
+pub async fn add_to_collection(
+    req: ask::CollectionClip,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository + CollectionRepository),
+) -> Result<Collection, ServiceError> {
+    let collection = owned_collection(&req.collection, requester.clone(), repo).await?;
+    let clip = collectable_clip(req.clip, requester, repo).await?;
+    repo.add_collection_clip(&collection.collection_id, &clip.clip_id).await?;
+    Ok(collection)
 }
 
-pub async fn update_clip(req: ask::UpdateClip, pool: &DbPool) -> Result<Clip, ServiceError> {
-    Ok(query::update_clip(req, pool).await?.try_into()?)
+pub async fn remove_from_collection(
+    req: ask::CollectionClip,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository + CollectionRepository),
+) -> Result<Collection, ServiceError> {
+    let collection = owned_collection(&req.collection, requester, repo).await?;
+    let clip: Clip = repo.get_clip(req.clip).await?.try_into()?;
+    repo.remove_collection_clip(&collection.collection_id, &clip.clip_id).await?;
+    Ok(collection)
 }
 
-pub async fn increase_hit_count(shortcode: &ShortCode, hits: u32, pool: &DbPool) -> Result<(), ServiceError>{ Ok(query::increase_hit_count(shortcode, hits, pool).await?) }
+// only clips created with an API key are subject to quotas
+pub async fn new_clip(
+    req: ask::NewClip,
+    owner: ask::Identity,
+    quota: &Quota,
+    policy: &PasswordPolicy,
+    shortcodes: &ShortCodeGenerator,
+    repo: &(impl ClipRepository + ApiKeyRepository + UserRepository),
+) -> Result<Clip, ServiceError> {
+    let req = match req.generate_password {
+        true if req.password.has_password() => {
+            return Err(ClipError::InvalidPassword("either choose a password or have one generated".to_owned()).into());
+        }
+        true => ask::NewClip { password: Password::generate(), ..req },
+        false => {
+            policy.check(&req.password)?;
+            req
+        }
+    };
+    // requested shortcodes arrive unchecked from JSON
+    let shortcode = req.shortcode.as_ref().map(|shortcode| ShortCode::custom(shortcode.as_str())).transpose()?;
+    let req = ask::NewClip { shortcode, ..req };
+
+    if req.visibility.is_restricted() && matches!(owner, ask::Identity::Anonymous) {
+        return Err(ClipError::InvalidVisibility("log in to create private or shared clips".to_owned()).into());
+    }
+
+    let grants = resolve_grants(req.visibility, req.shared_with.clone(), repo).await?;
+    Ok(repo.new_clip((req, owner, grants), shortcodes, quota).await?.try_into()?)
+}
 
-pub async fn begin_tx(pool:&DbPool) -> Result<Tx<'_>, ServiceError> { Ok(pool.begin().await?) }
+// only the creator of a clip may change it, size changes are accounted to the API key that created it
+pub async fn update_clip(
+    req: ask::UpdateClip,
+    requester: ask::Identity,
+    quota: &Quota,
+    policy: &PasswordPolicy,
+    repo: &(impl ClipRepository + ApiKeyRepository + UserRepository),
+) -> Result<Clip, ServiceError> {
+    let current = repo.get_clip(req.shortcode.clone()).await?;
+    check_owner(current.owner(), requester, repo).await?;
+    let current: Clip = current.try_into()?;
+    // the clip may have been addressed by an alias
+    let req = ask::UpdateClip { shortcode: current.shortcode.clone(), ..req };
 
-pub async fn end_tx(tx: Tx<'_>) -> Result<(), ServiceError> { Ok(tx.commit().await?) }
+    // keeping a password set before the policy was tightened is fine
+    if req.password != current.password {
+        policy.check(&req.password)?;
+    }
+
+    let grants = match req.shared_with.clone() {
+        Some(shared_with) => Some(resolve_grants(req.visibility, shared_with, repo).await?),
+        None if req.visibility != Visibility::SharedWith => Some(vec![]),
+        None => None
+    };
+    Ok(repo.update_clip((req, grants), quota).await?.try_into()?)
+}
+
+pub async fn get_usage(api_key: ApiKey, repo: &impl ApiKeyRepository) -> Result<Usage, ServiceError> {
+    Ok(repo.get_usage(api_key).await?.into())
+}
+
+// every view is a hit, hit counts, view stats and visitors are committed together or not at all
+pub async fn record_views(
+    views: Vec<(ClipId, View, u32)>,
+    visitors: Vec<(ClipId, Visitor)>,
+    repo: &impl ClipRepository,
+) -> Result<(), ServiceError> {
+    let mut hits: HashMap<ClipId, u32> = HashMap::new();
+    for (clip_id, _, count) in &views {
+        *hits.entry(clip_id.clone()).or_insert(0) += count;
+    }
+    Ok(repo.record_views(hits.into_iter().collect(), views, visitors).await?)
+}
+
+// only the creator of a clip may see who viewed it
+pub async fn get_clip_stats(
+    shortcode: ShortCode,
+    requester: ask::Identity,
+    repo: &(impl ClipRepository + ApiKeyRepository),
+) -> Result<ClipStats, ServiceError> {
+    let clip = repo.get_clip(shortcode).await?;
+    check_owner(clip.owner(), requester, repo).await?;
+    let clip: Clip = clip.try_into()?;
+
+    let now = chrono::Utc::now().timestamp();
+    let views = repo.get_clip_views(&clip.clip_id, ClipStats::since(now))
+        .await?
+        .into_iter()
+        .map(TryFrom::try_from)
+        .collect::<Result<_, ClipError>>()?;
+    Ok(ClipStats::new(clip.hits.into_inner(), clip.visitors.into_inner(), views, now))
+}
 
-pub async fn new_api_key(pool:&DbPool) -> Result<ApiKey, ServiceError> {
+pub async fn new_api_key(user_id: Option<UserId>, repo: &impl ApiKeyRepository) -> Result<ApiKey, ServiceError> {
     let api_key = ApiKey::default();
-    Ok(query::save_api_key(api_key, pool).await?)
+    Ok(repo.save_api_key(api_key, user_id).await?)
 }
 
-pub async fn revoke_api_key(api_key:ApiKey, pool:&DbPool) -> Result<RevocationStatus, ServiceError> {
-    Ok(query::revoke_api_key(api_key, pool).await?)
+pub async fn revoke_api_key(api_key:ApiKey, repo: &impl ApiKeyRepository) -> Result<RevocationStatus, ServiceError> {
+    Ok(repo.revoke_api_key(api_key).await?)
 }
 
-pub async fn api_key_is_valid(api_key:ApiKey, pool:&DbPool) -> Result<bool, ServiceError> {
-    Ok(query::api_key_is_valid(api_key, pool).await?)
+pub async fn api_key_is_valid(api_key:ApiKey, repo: &impl ApiKeyRepository) -> Result<bool, ServiceError> {
+    Ok(repo.api_key_is_valid(api_key).await?)
 }
 
-pub async fn delete_expired(pool: &DbPool) -> Result<u64, ServiceError> {
-    Ok(query::delete_expired(pool).await?)
-}
\ No newline at end of file
+// the unique username settles concurrent registrations, the lookup only spares hashing the password
+pub async fn new_user(req: ask::NewUser, repo: &impl UserRepository) -> Result<User, ServiceError> {
+    let taken = || ServiceError::Conflict("username is already taken".to_owned());
+    match repo.get_user_by_name(&req.username).await {
+        Ok(_) => return Err(taken()),
+        Err(e) => match ServiceError::from(e) {
+            ServiceError::NotFound => (),
+            other => return Err(other)
+        }
+    }
+
+    let password_hash = req.password.hash()?;
+    match repo.new_user((req.username, password_hash)).await {
+        Ok(user) => Ok(user.try_into()?),
+        Err(DataError::UsernameTaken(_)) => Err(taken()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// unknown users and wrong passwords are reported the same way
+pub async fn login(req: ask::Login, repo: &impl UserRepository) -> Result<User, ServiceError> {
+    let invalid = || ServiceError::PermissionError("Invalid username or password".to_owned());
+    // no account can have a malformed name
+    let username = Username::new(&req.username).map_err(|_| invalid())?;
+    let user = match repo.get_user_by_name(&username).await {
+        Ok(user) => user,
+        Err(e) => return match ServiceError::from(e) {
+            ServiceError::NotFound => Err(invalid()),
+            other => Err(other)
+        }
+    };
+
+    if crate::domain::user::field::Password::verify(&req.password, user.password_hash()) {
+        Ok(user.try_into()?)
+    } else {
+        Err(invalid())
+    }
+}
+
+// users signing in through OpenID Connect get a local account on their first login
+pub async fn oidc_login(req: ask::OidcLogin, repo: &impl UserRepository) -> Result<User, ServiceError> {
+    match repo.get_user_by_identity(&req.issuer, &req.subject).await {
+        Ok(user) => return Ok(user.try_into()?),
+        Err(e) => match ServiceError::from(e) {
+            ServiceError::NotFound => (),
+            other => return Err(other)
+        }
+    }
+
+    // the name may be taken in between looking it up and creating the account
+    let mut suffix = None;
+    let user: User = loop {
+        let username = Username::suggest(&req.username_hint, suffix);
+        let bump = |suffix: Option<u32>| Some(suffix.unwrap_or(1) + 1);
+        match repo.get_user_by_name(&username).await {
+            Ok(_) => suffix = bump(suffix),
+            Err(e) => match ServiceError::from(e) {
+                // an empty hash never verifies: these accounts can only log in through their provider
+                ServiceError::NotFound => match repo.new_user((username, String::new())).await {
+                    Ok(user) => break user.try_into()?,
+                    Err(DataError::UsernameTaken(_)) => suffix = bump(suffix),
+                    Err(e) => return Err(e.into()),
+                },
+                other => return Err(other)
+            }
+        }
+    };
+    repo.link_identity(&req.issuer, &req.subject, user.user_id.clone()).await?;
+    Ok(user)
+}
+
+// the session cookie only carries the id, so logging out ends the session for good
+pub async fn start_session(user_id: UserId, expires: Time, repo: &impl UserRepository) -> Result<String, ServiceError> {
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+
+    let session_id: String = rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect();
+    repo.new_session(&session_id, user_id, expires.timestamp()).await?;
+    Ok(session_id)
+}
+
+pub async fn session_user(session_id: &str, repo: &impl UserRepository) -> Result<UserId, ServiceError> {
+    Ok(repo.get_session(session_id).await?.user_id()?)
+}
+
+pub async fn end_session(session_id: &str, repo: &impl UserRepository) -> Result<(), ServiceError> {
+    Ok(repo.delete_session(session_id).await?)
+}
+
+pub async fn delete_expired_sessions(repo: &impl UserRepository) -> Result<u64, ServiceError> {
+    Ok(repo.delete_expired_sessions().await?)
+}
+
+pub async fn get_user(user_id: UserId, repo: &impl UserRepository) -> Result<User, ServiceError> {
+    Ok(repo.get_user(user_id).await?.try_into()?)
+}
+
+// `tag` is normalized the same way tags are when saved
+pub async fn get_user_clips(
+    user_id: UserId,
+    tag: Option<&str>,
+    repo: &impl ClipRepository,
+) -> Result<Vec<Clip>, ServiceError> {
+    let tag = tag.map(Tags::normalize).transpose()?;
+    repo.get_user_clips(user_id, tag.as_deref())
+        .await?
+        .into_iter()
+        .map(|clip| Ok(clip.try_into()?))
+        .collect()
+}
+
+pub async fn get_api_key_clips(
+    api_key: ApiKey,
+    tag: Option<&str>,
+    repo: &impl ClipRepository,
+) -> Result<Vec<Clip>, ServiceError> {
+    let tag = tag.map(Tags::normalize).transpose()?;
+    repo.get_api_key_clips(api_key, tag.as_deref())
+        .await?
+        .into_iter()
+        .map(|clip| Ok(clip.try_into()?))
+        .collect()
+}
+
+pub async fn delete_expired(repo: &impl ClipRepository) -> Result<u64, ServiceError> {
+    Ok(repo.delete_expired().await?)
+}
+
+pub async fn ping(repo: &impl HealthRepository) -> Result<(), ServiceError> {
+    Ok(repo.ping().await?)
+}
+
+pub async fn schema_version(repo: &impl HealthRepository) -> Result<Option<i64>, ServiceError> {
+    Ok(repo.schema_version().await?)
+}
+
+pub async fn delete_stale_visitors(repo: &impl ClipRepository) -> Result<u64, ServiceError> {
+    Ok(repo.delete_stale_visitors(VisitorSalt::today()).await?)
+}
+
+// today's key visitors are hashed with, the same on every replica and across restarts
+pub async fn visitor_salt(repo: &impl ClipRepository) -> Result<VisitorSalt, ServiceError> {
+    let today = VisitorSalt::today();
+    let key = repo.visitor_salt(today, &rand::random::<[u8; 32]>()).await?;
+    Ok(VisitorSalt::new(today, key))
+}
+
+// view stats older than what the stats page shows
+pub async fn delete_old_views(repo: &impl ClipRepository) -> Result<u64, ServiceError> {
+    Ok(repo.delete_old_views(ClipStats::since(chrono::Utc::now().timestamp())).await?)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::data::repository::MemoryStore;
+    use crate::data::test::new_db;
+    use crate::domain::clip::field::{BurnAfter, Content, Expires, Title};
+    use crate::domain::stats::Agent;
+    use crate::domain::user::field::Username;
+    use crate::test::async_runtime;
+    use std::str::FromStr;
+
+    // the database of this build and the in-memory store have to behave the same,
+    // so every case below runs against both
+    trait Store: ClipRepository + ApiKeyRepository + UserRepository {}
+
+    impl<R: ClipRepository + ApiKeyRepository + UserRepository> Store for R {}
+
+    fn new_clip_req(password: &str, visibility: Visibility) -> ask::NewClip {
+        ask::NewClip {
+            content: Content::new("content").unwrap(),
+            title: Title::default(),
+            expires: Expires::default(),
+            password: Password::new(password.to_owned()).unwrap(),
+            visibility,
+            shared_with: Default::default(),
+            burn_after: Default::default(),
+            generate_password: false,
+            shortcode: None,
+            tags: Default::default(),
+        }
+    }
+
+    async fn create(req: ask::NewClip, owner: ask::Identity, quota: &Quota, repo: &impl Store) -> Result<Clip, ServiceError> {
+        new_clip(req, owner, quota, &PasswordPolicy::default(), &Default::default(), repo).await
+    }
+
+    async fn create_user(username: &str, repo: &impl Store) -> User {
+        let req = ask::NewUser {
+            username: Username::new(username).unwrap(),
+            password: crate::domain::user::field::Password::new("correct-horse-battery").unwrap(),
+        };
+        new_user(req, repo).await.unwrap()
+    }
+
+    async fn burn_after(repo: &impl Store) {
+        let lockout = Lockout::default();
+        let req = ask::NewClip { burn_after: BurnAfter::new(Some(2)).unwrap(), ..new_clip_req("tulip-orbit-9", Visibility::Unlisted) };
+        let clip = create(req, ask::Identity::Anonymous, &Quota::default(), repo).await.unwrap();
+        let guess = |password: &str| ask::GetClip {
+            shortcode: clip.shortcode.clone(),
+            password: Password::new(password.to_owned()).unwrap(),
+            share_link: None,
+            unlock_token: None,
+        };
+
+        let result = get_clip(guess("wrong"), ask::Identity::Anonymous, None, &lockout, repo).await;
+        assert!(matches!(result, Err(ServiceError::PermissionError(_))));
+        let result = get_clip(guess("wrong again"), ask::Identity::Anonymous, None, &lockout, repo).await;
+        assert!(matches!(result, Err(ServiceError::NotFound)));
+        let result = get_clip(guess("tulip-orbit-9"), ask::Identity::Anonymous, None, &lockout, repo).await;
+        assert!(matches!(result, Err(ServiceError::NotFound)));
+    }
+
+    #[test]
+    fn burns_clip_after_failed_attempts() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(burn_after(db.get_pool()));
+        rt.block_on(burn_after(&MemoryStore::new()));
+    }
+
+    async fn quota_per_api_key(repo: &impl Store) {
+        let quota = Quota { max_clips: Some(1), max_bytes: None };
+        let api_key = new_api_key(None, repo).await.unwrap();
+        let owner = ask::Identity::ApiKey(api_key.clone());
+
+        assert!(create(new_clip_req("", Visibility::Unlisted), owner.clone(), &quota, repo).await.is_ok());
+        let result = create(new_clip_req("", Visibility::Unlisted), owner, &quota, repo).await;
+        assert!(matches!(result, Err(ServiceError::Quota(_))));
+        // clips of anonymous users are not counted
+        assert!(create(new_clip_req("", Visibility::Unlisted), ask::Identity::Anonymous, &quota, repo).await.is_ok());
+        assert_eq!(get_usage(api_key, repo).await.unwrap().clips, 1);
+    }
+
+    #[test]
+    fn enforces_quota_per_api_key() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(quota_per_api_key(db.get_pool()));
+        rt.block_on(quota_per_api_key(&MemoryStore::new()));
+    }
+
+    async fn quota_on_edits(repo: &impl Store) {
+        let api_key = new_api_key(None, repo).await.unwrap();
+        let owner = ask::Identity::ApiKey(api_key);
+        let big = ask::NewClip { content: Content::new("0123456789").unwrap(), ..new_clip_req("", Visibility::Unlisted) };
+        let clip = create(big, owner.clone(), &Quota::default(), repo).await.unwrap();
+        let edit = |content: &str| ask::UpdateClip {
+            shortcode: clip.shortcode.clone(),
+            content: Content::new(content).unwrap(),
+            title: Title::default(),
+            expires: Expires::default(),
+            password: Password::default(),
+            visibility: Visibility::Unlisted,
+            shared_with: None,
+            burn_after: Default::default(),
+            tags: None,
+        };
+
+        // the limit was lowered below what the key already stores
+        let lowered = Quota { max_clips: None, max_bytes: Some(4) };
+        let result = update_clip(edit("0123456789ab"), owner.clone(), &lowered, &PasswordPolicy::default(), repo).await;
+        assert!(matches!(result, Err(ServiceError::Quota(_))));
+        let kept: Clip = repo.get_clip(clip.shortcode.clone()).await.unwrap().try_into().unwrap();
+        assert_eq!(kept.content.into_inner(), "0123456789");
+        assert!(update_clip(edit("01234567"), owner.clone(), &lowered, &PasswordPolicy::default(), repo).await.is_ok());
+        assert!(update_clip(edit("0123"), owner, &lowered, &PasswordPolicy::default(), repo).await.is_ok());
+    }
+
+    #[test]
+    fn edits_may_shrink_clips_of_keys_over_quota() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(quota_on_edits(db.get_pool()));
+        rt.block_on(quota_on_edits(&MemoryStore::new()));
+    }
+
+    async fn private_visibility(repo: &impl Store) {
+        let lockout = Lockout::default();
+        let user = create_user("alice", repo).await;
+        let api_key = new_api_key(Some(user.user_id.clone()), repo).await.unwrap();
+        let owner = ask::Identity::User(user.user_id);
+        let clip = create(new_clip_req("", Visibility::Private), owner.clone(), &Quota::default(), repo).await.unwrap();
+
+        let get = |requester| get_clip(ask::GetClip::from(clip.shortcode.clone()), requester, None, &lockout, repo);
+        assert!(get(owner).await.is_ok());
+        // API keys act on behalf of the user they were created by
+        assert!(get(ask::Identity::ApiKey(api_key)).await.is_ok());
+        assert!(matches!(get(ask::Identity::Anonymous).await, Err(ServiceError::AccessDenied(_))));
+        let stranger = new_api_key(None, repo).await.unwrap();
+        assert!(matches!(get(ask::Identity::ApiKey(stranger)).await, Err(ServiceError::AccessDenied(_))));
+    }
+
+    #[test]
+    fn private_clips_are_visible_to_their_owner_only() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(private_visibility(db.get_pool()));
+        rt.block_on(private_visibility(&MemoryStore::new()));
+    }
+
+    async fn shared_visibility(repo: &impl Store) {
+        let lockout = Lockout::default();
+        let alice = create_user("alice", repo).await;
+        let bob = create_user("bob", repo).await;
+        let carol = create_user("carol", repo).await;
+        let bobs_key = new_api_key(Some(bob.user_id.clone()), repo).await.unwrap();
+        let req = ask::NewClip {
+            shared_with: SharedWith::from_str("bob").unwrap(),
+            ..new_clip_req("", Visibility::SharedWith)
+        };
+        let clip = create(req, ask::Identity::User(alice.user_id), &Quota::default(), repo).await.unwrap();
+
+        let get = |requester| get_clip(ask::GetClip::from(clip.shortcode.clone()), requester, None, &lockout, repo);
+        assert!(get(ask::Identity::User(bob.user_id)).await.is_ok());
+        assert!(get(ask::Identity::ApiKey(bobs_key)).await.is_ok());
+        assert!(matches!(get(ask::Identity::User(carol.user_id)).await, Err(ServiceError::AccessDenied(_))));
+        assert!(matches!(get(ask::Identity::Anonymous).await, Err(ServiceError::AccessDenied(_))));
+    }
+
+    #[test]
+    fn shared_clips_are_visible_to_their_grantees() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(shared_visibility(db.get_pool()));
+        rt.block_on(shared_visibility(&MemoryStore::new()));
+    }
+
+    async fn expiry(repo: &impl Store) {
+        let api_key = new_api_key(None, repo).await.unwrap();
+        let owner = ask::Identity::ApiKey(api_key.clone());
+        let past = Time::from_timestamp(chrono::Utc::now().timestamp() - 60);
+        let req = ask::NewClip { expires: Expires::new(past), ..new_clip_req("", Visibility::Unlisted) };
+        let expired = create(req, owner.clone(), &Quota::default(), repo).await.unwrap();
+        let kept = create(new_clip_req("", Visibility::Unlisted), owner, &Quota::default(), repo).await.unwrap();
+
+        // expired clips no longer count towards the quota, even before they are deleted
+        assert_eq!(get_usage(api_key.clone(), repo).await.unwrap().clips, 1);
+        assert_eq!(delete_expired(repo).await.unwrap(), 1);
+        assert!(matches!(repo.get_clip(expired.shortcode).await.map_err(ServiceError::from), Err(ServiceError::NotFound)));
+        assert!(repo.get_clip(kept.shortcode).await.is_ok());
+        assert_eq!(get_usage(api_key, repo).await.unwrap().clips, 1);
+    }
+
+    #[test]
+    fn expired_clips_are_deleted() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(expiry(db.get_pool()));
+        rt.block_on(expiry(&MemoryStore::new()));
+    }
+
+    async fn hit_counts(repo: &impl Store) {
+        let api_key = new_api_key(None, repo).await.unwrap();
+        let owner = ask::Identity::ApiKey(api_key);
+        let clip = create(new_clip_req("", Visibility::Unlisted), owner.clone(), &Quota::default(), repo).await.unwrap();
+        let clip_id = clip.clip_id;
+        let visitor = |id: &str| (clip_id.clone(), Visitor { day: VisitorSalt::today(), id: id.to_owned() });
+
+        let view = View::new(None, Agent::Cli);
+        record_views(vec![(clip_id.clone(), view.clone(), 2)], vec![visitor("a"), visitor("b")], repo).await.unwrap();
+        // hits are counted for the clip, not for the shortcode it was viewed under
+        let renamed = ShortCode::from("renamed");
+        repo.rename_clip(&clip_id, &clip.shortcode, &renamed).await.unwrap();
+        // a visitor is counted once a day
+        record_views(vec![(clip_id.clone(), view, 1)], vec![visitor("a")], repo).await.unwrap();
+
+        let stats = get_clip_stats(renamed, owner, repo).await.unwrap();
+        assert_eq!((stats.hits, stats.visitors), (3, 2));
+        assert_eq!(stats.hourly.iter().map(|bucket| bucket.views).sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn counts_hits_and_visitors() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        rt.block_on(hit_counts(db.get_pool()));
+        rt.block_on(hit_counts(&MemoryStore::new()));
+    }
+
+    #[test]
+    fn stores_report_health() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let expected = crate::data::expected_schema_version();
+        rt.block_on(async {
+            assert!(ping(db.get_pool()).await.is_ok());
+            assert_eq!(schema_version(db.get_pool()).await.unwrap(), expected);
+            assert!(ping(&MemoryStore::new()).await.is_ok());
+            assert_eq!(schema_version(&MemoryStore::new()).await.unwrap(), expected);
+        });
+    }
+}
@@ -1,6 +1,8 @@
 use crate::domain::clip::field::*;
+use crate::domain::share::{ShareLink, UnlockToken};
+use crate::domain::user::field::{UserId, Username};
+use crate::web::api::ApiKey;
 use crate::ShortCode;
-use derive_more::Constructor;
 use serde::{Deserialize, Serialize};
 
 // all fields can be public as we already implemented validation in domain
@@ -8,13 +10,21 @@ use serde::{Deserialize, Serialize};
 pub struct GetClip {
     pub shortcode: ShortCode,
     pub password: Password,
+    // signed link handed out by the owner, replaces the password
+    #[serde(default)]
+    pub share_link: Option<ShareLink>,
+    // handed out once the password was entered, replaces it
+    #[serde(default)]
+    pub unlock_token: Option<UnlockToken>,
 }
 
 impl GetClip {
     fn from_raw(shortcode:&str) -> Self {
         Self {
             shortcode: ShortCode::from(shortcode),
-            password: Password::default()
+            password: Password::default(),
+            share_link: None,
+            unlock_token: None,
         }
     }
 }
@@ -23,7 +33,9 @@ impl From<ShortCode> for GetClip {
     fn from(shortcode: ShortCode) -> Self {
         Self {
             shortcode,
-            password: Password::default()
+            password: Password::default(),
+            share_link: None,
+            unlock_token: None,
         }
     }
 }
@@ -40,6 +52,21 @@ pub struct NewClip {
     pub title: Title,
     pub expires: Expires,
     pub password: Password,
+    #[serde(default)]
+    pub visibility: Visibility,
+    // only used when visibility is shared-with
+    #[serde(default)]
+    pub shared_with: SharedWith,
+    #[serde(default)]
+    pub burn_after: BurnAfter,
+    // protect the clip with a random passphrase instead of `password`
+    #[serde(default)]
+    pub generate_password: bool,
+    // vanity shortcode to use instead of a generated one
+    #[serde(default)]
+    pub shortcode: Option<ShortCode>,
+    #[serde(default)]
+    pub tags: Tags,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -48,5 +75,93 @@ pub struct UpdateClip {
     pub content: Content,
     pub title: Title,
     pub expires: Expires,
-    pub password: Password
-}
\ No newline at end of file
+    pub password: Password,
+    #[serde(default)]
+    pub visibility: Visibility,
+    // None keeps the current allow-list
+    #[serde(default)]
+    pub shared_with: Option<SharedWith>,
+    #[serde(default)]
+    pub burn_after: BurnAfter,
+    // None keeps the current tags
+    #[serde(default)]
+    pub tags: Option<Tags>,
+}
+
+// who creates or requests a clip
+#[derive(Debug, Clone)]
+pub enum Identity {
+    Anonymous,
+    ApiKey(ApiKey),
+    User(UserId),
+}
+
+#[derive(Debug)]
+pub struct NewUser {
+    pub username: Username,
+    pub password: crate::domain::user::field::Password,
+}
+
+#[derive(Debug)]
+// taken as typed: malformed names and short passwords are just wrong credentials
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+// hand out a signed link to a clip, valid for a number of hours
+#[derive(Debug)]
+pub struct ShareClip {
+    pub shortcode: ShortCode,
+    pub hours: u32,
+}
+
+// an alternate shortcode of a clip, added or removed by its owner
+#[derive(Debug)]
+pub struct Alias {
+    pub shortcode: ShortCode,
+    pub alias: ShortCode,
+}
+
+// give a clip a new primary shortcode, the current one keeps working as an alias
+#[derive(Debug)]
+pub struct RenameClip {
+    pub shortcode: ShortCode,
+    pub renamed: ShortCode,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NewCollection {
+    #[serde(default)]
+    pub title: Title,
+    // guards the index of the collection, not its clips
+    #[serde(default)]
+    pub password: Password,
+    #[serde(default)]
+    pub shortcode: Option<ShortCode>,
+    // clips of the creator to start with
+    #[serde(default)]
+    pub clips: Vec<ShortCode>,
+}
+
+#[derive(Debug)]
+pub struct GetCollection {
+    pub shortcode: ShortCode,
+    pub password: Password,
+}
+
+// a clip added to or removed from a collection
+#[derive(Debug)]
+pub struct CollectionClip {
+    pub collection: ShortCode,
+    pub clip: ShortCode,
+}
+
+// identity asserted by an OpenID Connect provider
+#[derive(Debug)]
+pub struct OidcLogin {
+    pub issuer: String,
+    pub subject: String,
+    // preferred username or email, used to name the local account on first login
+    pub username_hint: String,
+}
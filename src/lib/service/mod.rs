@@ -3,6 +3,10 @@ pub mod action;
 
 use sqlx::Error;
 use crate::{ClipError, DataError};
+use crate::domain::lockout::LockoutError;
+use crate::domain::quota::QuotaError;
+use crate::domain::share::ShareError;
+use crate::domain::user::UserError;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ServiceError {
@@ -14,6 +18,18 @@ pub enum ServiceError {
     NotFound,
     #[error("permissions not met {0}")]
     PermissionError(String),
+    #[error("quota error: {0}")]
+    Quota(#[from] QuotaError),
+    #[error("user error: {0}")]
+    User(#[from] UserError),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("access denied: {0}")]
+    AccessDenied(String),
+    #[error("share link error: {0}")]
+    Share(#[from] ShareError),
+    #[error("locked out: {0}")]
+    Locked(#[from] LockoutError),
 }
 
 impl From<DataError> for ServiceError {
@@ -22,7 +38,10 @@ impl From<DataError> for ServiceError {
             DataError::Database(d) => match d {
                 Error::RowNotFound => Self::NotFound,
                 other => Self::Data(DataError::Database(other))
-            }
+            },
+            e @ (DataError::ShortCodeTaken(_) | DataError::UsernameTaken(_)) => Self::Conflict(e.to_string()),
+            DataError::Quota(e) => Self::Quota(e),
+            e @ (DataError::ShortCodesExhausted(_) | DataError::Migration(_)) => Self::Data(e),
         }
     }
 }
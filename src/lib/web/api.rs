@@ -1,31 +1,44 @@
-use rocket::http::{CookieJar, Status};
+use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::serde::json::Json;
 use rocket::Responder;
 use rocket::State;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
 use crate::data::Db;
-use crate::{service, ShortCode};
+use crate::web::attempts::Attempts;
+use crate::web::viewer::Viewer;
+use crate::domain::quota::{Quota, QuotaError, Usage};
+use crate::domain::clip::field::{Password, PasswordPolicy, ShortCode, ShortCodeGenerator};
+use crate::domain::share::{ShareError, UnlockToken};
+use crate::domain::stats::ClipStats;
+use crate::domain::collection::Member;
+use crate::domain::{Collection, Time};
+use crate::service;
 use crate::service::action;
-use crate::web::{HitCounter, PASSWORD_COOKIE};
+use crate::service::ask::Identity;
+use crate::web::{form, HitCounter, Metrics};
 use crate::ServiceError;
 
 // transfer API_KEY through header
 pub const API_KEY_HEADER: &str = "x-api-key";
+// password of a protected clip
+pub const CLIP_PASSWORD_HEADER: &str = "x-clip-password";
+// `Bearer <token>` as returned by the unlock endpoint
+pub const AUTHORIZATION_HEADER: &str = "authorization";
 
 #[derive(Responder, Debug, thiserror::Error, Serialize)]
 pub enum ApiKeyError {
     #[error("API key not found")]
-    #[response(status = 404, content_type = "json")]
+    #[response(status = 401, content_type = "json")]
     NotFound(String),
     #[error("invalid API key format")]
     #[response(status = 400, content_type = "json")]
     DecodeError(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ApiKey(Vec<u8>);
 
 impl ApiKey {
@@ -33,6 +46,12 @@ impl ApiKey {
     pub fn into_inner(self) -> Vec<u8> { self.0 }
 }
 
+impl From<Vec<u8>> for ApiKey {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+}
+
 impl Default for ApiKey {
     fn default() -> Self {
         let key = (0..16).map(|_| rand::random::<u8>()).collect();
@@ -50,6 +69,9 @@ impl FromStr for ApiKey {
     }
 }
 
+// 400: the request itself is invalid
+// 401: credentials (API key, clip password or unlock token) are missing or wrong
+// 403: the credentials are valid but do not grant access
 #[derive(Debug, thiserror::Error, Responder)]
 pub enum ApiError {
     #[error("not found")]
@@ -60,26 +82,80 @@ pub enum ApiError {
     #[response(status = 500, content_type = "json")]
     Server(Json<String>),
 
+    #[error("bad request")]
+    #[response(status = 400, content_type = "json")]
+    BadRequest(Json<String>),
+
+    #[error("unauthorized")]
     #[response(status = 401, content_type = "json")]
-    #[error("client error")]
-    User(Json<String>),
+    Unauthorized(Json<String>),
 
     #[error("key error")]
     #[response(status = 400, content_type = "json")]
     KeyError(Json<ApiKeyError>),
+
+    #[error("forbidden")]
+    #[response(status = 403, content_type = "json")]
+    Forbidden(Json<String>),
+
+    #[error("insufficient storage")]
+    #[response(status = 507, content_type = "json")]
+    InsufficientStorage(Json<String>),
+
+    #[error("conflict")]
+    #[response(status = 409, content_type = "json")]
+    Conflict(Json<String>),
+
+    #[error("too many requests")]
+    #[response(status = 429, content_type = "json")]
+    TooManyRequests(Json<String>),
 }
 
 impl From<ServiceError> for ApiError {
     fn from(err: ServiceError) -> Self {
         match err {
-            ServiceError::Clip(c) => Self::User(Json(format!("clip parsing error {:?}", c))),
+            ServiceError::Clip(c) => Self::BadRequest(Json(format!("clip parsing error {:?}", c))),
             ServiceError::NotFound => Self::NotFound(Json("entity not found".to_owned())),
             ServiceError::Data(_) => Self::Server(Json("a server error occurred".to_owned())),
-            ServiceError::PermissionError(msg) => Self::User(Json(msg))
+            ServiceError::PermissionError(msg) => Self::Unauthorized(Json(msg)),
+            ServiceError::Quota(e @ QuotaError::Clips(_)) => Self::Forbidden(Json(e.to_string())),
+            ServiceError::Quota(e @ QuotaError::Storage(_)) => Self::InsufficientStorage(Json(e.to_string())),
+            ServiceError::User(e) => Self::BadRequest(Json(format!("user error {:?}", e))),
+            ServiceError::Conflict(msg) => Self::Conflict(Json(msg)),
+            ServiceError::AccessDenied(msg) => Self::Forbidden(Json(msg)),
+            ServiceError::Share(e @ ShareError::InvalidDuration(_)) => Self::BadRequest(Json(e.to_string())),
+            ServiceError::Share(e) => Self::Forbidden(Json(e.to_string())),
+            ServiceError::Locked(e) => Self::TooManyRequests(Json(e.to_string())),
         }
     }
 }
 
+// what an API client proves it may read a password protected clip with:
+// an unlock token as `Authorization: Bearer <token>` or the password in `X-Clip-Password`
+pub struct ClipCredentials {
+    pub password: Password,
+    pub unlock_token: Option<UnlockToken>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClipCredentials {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let password = request
+            .headers()
+            .get_one(CLIP_PASSWORD_HEADER)
+            .and_then(|password| Password::from_str(password).ok())
+            .unwrap_or_default();
+        let unlock_token = request
+            .headers()
+            .get_one(AUTHORIZATION_HEADER)
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| UnlockToken::from_str(token.trim()).ok());
+        Outcome::Success(ClipCredentials { password, unlock_token })
+    }
+}
+
 // to use api key as rocket request guard
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for ApiKey {
@@ -94,13 +170,14 @@ impl<'r> FromRequest<'r> for ApiKey {
         }
 
         fn key_error(e: ApiKeyError) -> Outcome<ApiKey, ApiError> {
-            Outcome::Failure((
-                Status::BadRequest,
-                ApiError::KeyError(Json(e))
-            ))
+            let status = match e {
+                ApiKeyError::NotFound(_) => Status::Unauthorized,
+                ApiKeyError::DecodeError(_) => Status::BadRequest,
+            };
+            Outcome::Failure((status, ApiError::KeyError(Json(e))))
         }
 
-        match request.headers().get_one(API_KEY_HEADER) {
+        let outcome = match request.headers().get_one(API_KEY_HEADER) {
             None => key_error(ApiKeyError::NotFound("Api key not found".to_owned())),
             Some(key) => {
                 let db = match request.guard::<&State<Db>>().await {
@@ -108,93 +185,359 @@ impl<'r> FromRequest<'r> for ApiKey {
                     _ => return server_error()
                 };
 
-                let api_key = match ApiKey::from_str(key) {
-                    Ok(key) => key,
-                    Err(e) => return key_error(e)
-                };
-
-                match action::api_key_is_valid(api_key.clone(), db.get_pool()).await {
-                    Ok(valid) if valid => Outcome::Success(api_key),
-                    Ok(valid) if !valid => {
-                        key_error(ApiKeyError::NotFound("API key is not found".to_owned()))
-                    },
-                    _ => server_error()
+                match ApiKey::from_str(key) {
+                    Err(e) => key_error(e),
+                    Ok(api_key) => match action::api_key_is_valid(api_key.clone(), db.get_pool()).await {
+                        Ok(valid) if valid => Outcome::Success(api_key),
+                        Ok(valid) if !valid => {
+                            key_error(ApiKeyError::NotFound("API key is not found".to_owned()))
+                        },
+                        _ => server_error()
+                    }
                 }
             }
+        };
+
+        if let (Outcome::Failure((status, _)), Some(metrics)) = (&outcome, request.rocket().state::<Metrics>()) {
+            if *status != Status::InternalServerError {
+                metrics.api_key_failures.inc();
+            }
         }
+        outcome
     }
 }
 
 #[rocket::get("/key")]
-pub async fn new_api_key(db:&State<Db>) -> Result<Json<&str>, ApiError> {
-    let api_key = action::new_api_key(db.get_pool()).await?;
-    println!("Api Key: {:?}", api_key.to_base64());
-    Ok(Json("Api key created. See logs for details"))
+pub async fn new_api_key(db:&State<Db>) -> Result<Json<String>, ApiError> {
+    let api_key = action::new_api_key(None, db.get_pool()).await?;
+    // the key only goes to the caller, never to the logs
+    tracing::info!("api key created");
+    Ok(Json(api_key.to_base64()))
 }
 
-#[rocket::get("/<shortcode>")]
+// malformed shortcodes are a bad request rather than a clip that is not found
+fn parse_shortcode(shortcode: &str) -> Result<ShortCode, ApiError> {
+    ShortCode::parse(shortcode).map_err(|e| ApiError::BadRequest(Json(e.to_string())))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::get("/<shortcode>?<share..>")]
 pub async fn get_clip(
     shortcode: &str,
+    share: Option<form::ShareLink>,
+    credentials: ClipCredentials,
     db: &State<Db>,
-    cookies: &CookieJar<'_>,
     hit_counter: &State<HitCounter>,
-    _api_key: ApiKey, // _ as prefix to tell compiler to ignore it if not used
+    attempts: Attempts<'_>,
+    viewer: Viewer,
+    api_key: ApiKey,
 ) -> Result<Json<crate::Clip>, ApiError> {
-    use crate::domain::clip::field::Password;
-
+    let shortcode = parse_shortcode(shortcode)?;
     let req = service::ask::GetClip {
-        shortcode: shortcode.into(),
-        password: cookies
-            .get(PASSWORD_COOKIE)
-            .map(|cookie|cookie.value())
-            .map(|raw_pwd|Password::from_str(raw_pwd).ok())
-            .flatten()
-            .unwrap_or_else(Password::default)
+        shortcode: shortcode.clone(),
+        password: credentials.password,
+        share_link: share.map(Into::into),
+        unlock_token: credentials.unlock_token,
     };
 
-    let clip = action::get_clip(req, db.get_pool()).await?;
-    hit_counter.hit(shortcode.into(), 1);
+    let clip = action::get_clip(req, Identity::ApiKey(api_key), attempts.client, attempts.lockout, db.get_pool()).await?;
+    hit_counter.hit(clip.clip_id.clone(), viewer);
     Ok(Json(clip))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UnlockRequest {
+    password: Password,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnlockedClip {
+    clip: crate::Clip,
+    // send as `Authorization: Bearer <token>` instead of the password from now on
+    token: Option<String>,
+    expires: Option<Time>,
+}
+
+#[rocket::post("/<shortcode>/unlock", data = "<req>")]
+pub async fn unlock_clip(
+    shortcode: &str,
+    req: Json<UnlockRequest>,
+    db: &State<Db>,
+    hit_counter: &State<HitCounter>,
+    attempts: Attempts<'_>,
+    viewer: Viewer,
+    api_key: ApiKey,
+) -> Result<Json<UnlockedClip>, ApiError> {
+    let shortcode = parse_shortcode(shortcode)?;
+    let req = service::ask::GetClip {
+        password: req.into_inner().password,
+        ..shortcode.clone().into()
+    };
+
+    let (clip, token) = action::unlock_clip(req, Identity::ApiKey(api_key), attempts.client, attempts.lockout, db.get_pool()).await?;
+    hit_counter.hit(clip.clip_id.clone(), viewer);
+    Ok(Json(UnlockedClip {
+        clip,
+        expires: token.as_ref().map(|token| token.expires()),
+        token: token.map(|token| token.to_string()),
+    }))
+}
+
 #[rocket::post("/", data = "<req>")]
 pub async fn new_clip(
     req: Json<service::ask::NewClip>,
     db: &State<Db>,
-    _api_key: ApiKey
+    quota: &State<Quota>,
+    policy: &State<PasswordPolicy>,
+    shortcodes: &State<ShortCodeGenerator>,
+    metrics: &State<Metrics>,
+    api_key: ApiKey
 ) -> Result<Json<crate::Clip>, ApiError> {
-    let clip = action::new_clip(req.into_inner(), db.get_pool()).await?;
+    let clip = action::new_clip(req.into_inner(), Identity::ApiKey(api_key), quota, policy, shortcodes, db.get_pool()).await?;
+    metrics.clips_created.inc();
     Ok(Json(clip))
 }
 
+// clips created with the API key, optionally only those carrying `tag`
+#[rocket::get("/?<tag>")]
+pub async fn list_clips(
+    tag: Option<&str>,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<Vec<crate::Clip>>, ApiError> {
+    Ok(Json(action::get_api_key_clips(api_key, tag, db.get_pool()).await?))
+}
+
 #[rocket::put("/", data = "<req>")]
 pub async fn update_clip(
     req: Json<service::ask::UpdateClip>,
     db: &State<Db>,
-    _api_key: ApiKey
+    quota: &State<Quota>,
+    policy: &State<PasswordPolicy>,
+    api_key: ApiKey
 ) -> Result<Json<crate::Clip>, ApiError> {
-    let clip = action::update_clip(req.into_inner(), db.get_pool()).await?;
+    let clip = action::update_clip(req.into_inner(), Identity::ApiKey(api_key), quota, policy, db.get_pool()).await?;
     Ok(Json(clip))
 }
 
+#[derive(Debug, Serialize)]
+pub struct ShareLinkReport {
+    url: String,
+    expires: Time,
+}
+
+#[rocket::post("/<shortcode>/share?<hours>")]
+pub async fn share_clip(
+    shortcode: &str,
+    hours: u32,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<ShareLinkReport>, ApiError> {
+    let shortcode = parse_shortcode(shortcode)?;
+    let req = service::ask::ShareClip { shortcode: shortcode.clone(), hours };
+    let link = action::share_clip(req, Identity::ApiKey(api_key), db.get_pool()).await?;
+    Ok(Json(ShareLinkReport { url: link.url(&shortcode), expires: link.expires() }))
+}
+
+// views per hour and day, referrers and kinds of clients; only for the creator of the clip
+#[rocket::get("/<shortcode>/stats")]
+pub async fn get_clip_stats(
+    shortcode: &str,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<ClipStats>, ApiError> {
+    let stats = action::get_clip_stats(parse_shortcode(shortcode)?, Identity::ApiKey(api_key), db.get_pool()).await?;
+    Ok(Json(stats))
+}
+
+// invalidates every share link of the clip
+#[rocket::delete("/<shortcode>/share")]
+pub async fn revoke_share_links(
+    shortcode: &str,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<&'static str>, ApiError> {
+    action::revoke_share_links(parse_shortcode(shortcode)?, Identity::ApiKey(api_key), db.get_pool()).await?;
+    Ok(Json("share links revoked"))
+}
+
+#[derive(Debug, Serialize)]
+pub struct UsageReport {
+    usage: Usage,
+    quota: Quota,
+}
+
+#[rocket::get("/")]
+pub async fn get_usage(
+    db: &State<Db>,
+    quota: &State<Quota>,
+    api_key: ApiKey
+) -> Result<Json<UsageReport>, ApiError> {
+    let usage = action::get_usage(api_key, db.get_pool()).await?;
+    Ok(Json(UsageReport { usage, quota: quota.inner().clone() }))
+}
+
+#[derive(Debug, Serialize)]
+pub struct AliasReport {
+    shortcode: ShortCode,
+    aliases: Vec<ShortCode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AliasRequest {
+    alias: ShortCode,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RenameRequest {
+    shortcode: ShortCode,
+}
+
+async fn alias_report(clip: crate::Clip, db: &Db) -> Result<Json<AliasReport>, ApiError> {
+    let aliases = action::get_aliases(&clip, db.get_pool()).await?;
+    Ok(Json(AliasReport { shortcode: clip.shortcode, aliases }))
+}
+
+#[rocket::post("/<shortcode>/alias", data = "<req>")]
+pub async fn add_alias(
+    shortcode: &str,
+    req: Json<AliasRequest>,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<AliasReport>, ApiError> {
+    let req = service::ask::Alias { shortcode: parse_shortcode(shortcode)?, alias: req.into_inner().alias };
+    let clip = action::add_alias(req, Identity::ApiKey(api_key), db.get_pool()).await?;
+    alias_report(clip, db).await
+}
+
+#[rocket::delete("/<shortcode>/alias/<alias>")]
+pub async fn remove_alias(
+    shortcode: &str,
+    alias: &str,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<AliasReport>, ApiError> {
+    let req = service::ask::Alias { shortcode: parse_shortcode(shortcode)?, alias: parse_shortcode(alias)? };
+    let clip = action::remove_alias(req, Identity::ApiKey(api_key), db.get_pool()).await?;
+    alias_report(clip, db).await
+}
+
+// the current shortcode is kept as an alias
+#[rocket::post("/<shortcode>/rename", data = "<req>")]
+pub async fn rename_clip(
+    shortcode: &str,
+    req: Json<RenameRequest>,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<AliasReport>, ApiError> {
+    let req = service::ask::RenameClip { shortcode: parse_shortcode(shortcode)?, renamed: req.into_inner().shortcode };
+    let clip = action::rename_clip(req, Identity::ApiKey(api_key), db.get_pool()).await?;
+    alias_report(clip, db).await
+}
+
 pub fn routes() -> Vec<rocket::Route> {
-    rocket::routes!(get_clip, new_clip, update_clip, new_api_key)
+    rocket::routes!(
+        get_clip,
+        get_clip_stats,
+        list_clips,
+        new_clip,
+        update_clip,
+        new_api_key,
+        share_clip,
+        revoke_share_links,
+        unlock_clip,
+        add_alias,
+        remove_alias,
+        rename_clip
+    )
+}
+
+pub fn usage_routes() -> Vec<rocket::Route> {
+    rocket::routes!(get_usage)
+}
+
+#[derive(Debug, Serialize)]
+pub struct CollectionReport {
+    #[serde(flatten)]
+    collection: Collection,
+    clips: Vec<Member>,
+}
+
+async fn collection_report(collection: Collection, db: &Db) -> Result<Json<CollectionReport>, ApiError> {
+    let clips = action::get_collection_members(&collection, db.get_pool()).await?;
+    Ok(Json(CollectionReport { collection, clips }))
+}
+
+#[rocket::post("/", data = "<req>")]
+pub async fn new_collection(
+    req: Json<service::ask::NewCollection>,
+    db: &State<Db>,
+    policy: &State<PasswordPolicy>,
+    shortcodes: &State<ShortCodeGenerator>,
+    api_key: ApiKey
+) -> Result<Json<CollectionReport>, ApiError> {
+    let collection = action::new_collection(req.into_inner(), Identity::ApiKey(api_key), policy, shortcodes, db.get_pool()).await?;
+    collection_report(collection, db).await
+}
+
+// a collection password is sent like a clip password
+#[rocket::get("/<shortcode>")]
+pub async fn get_collection(
+    shortcode: &str,
+    credentials: ClipCredentials,
+    attempts: Attempts<'_>,
+    db: &State<Db>,
+    _api_key: ApiKey
+) -> Result<Json<CollectionReport>, ApiError> {
+    let req = service::ask::GetCollection { shortcode: parse_shortcode(shortcode)?, password: credentials.password };
+    let collection = action::get_collection(req, attempts.client, attempts.lockout, db.get_pool()).await?;
+    collection_report(collection, db).await
+}
+
+#[rocket::put("/<shortcode>/clip/<clip>")]
+pub async fn add_to_collection(
+    shortcode: &str,
+    clip: &str,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<CollectionReport>, ApiError> {
+    let req = service::ask::CollectionClip { collection: parse_shortcode(shortcode)?, clip: parse_shortcode(clip)? };
+    let collection = action::add_to_collection(req, Identity::ApiKey(api_key), db.get_pool()).await?;
+    collection_report(collection, db).await
+}
+
+#[rocket::delete("/<shortcode>/clip/<clip>")]
+pub async fn remove_from_collection(
+    shortcode: &str,
+    clip: &str,
+    db: &State<Db>,
+    api_key: ApiKey
+) -> Result<Json<CollectionReport>, ApiError> {
+    let req = service::ask::CollectionClip { collection: parse_shortcode(shortcode)?, clip: parse_shortcode(clip)? };
+    let collection = action::remove_from_collection(req, Identity::ApiKey(api_key), db.get_pool()).await?;
+    collection_report(collection, db).await
+}
+
+pub fn collection_routes() -> Vec<rocket::Route> {
+    rocket::routes!(new_collection, get_collection, add_to_collection, remove_from_collection)
 }
 
 pub mod catcher {
+    use crate::web::logging::RequestId;
+    use rocket::http::Status;
     use rocket::serde::json::Json;
     use rocket::Request;
     use rocket::{catch, catchers, Catcher};
 
     #[catch(default)]
-    fn default(req: &Request) -> Json<&'static str> {
-        eprintln!("General error: {:?}", req);
+    fn default(status: Status, req: &Request) -> Json<&'static str> {
+        tracing::warn!(request_id = %RequestId::of(req), method = %req.method(), path = %req.uri().path(), status = status.code, "unhandled error");
         Json("Something went wrong")
     }
 
     #[catch(500)]
     fn internal_error(req: &Request) -> Json<&'static str> {
-        eprintln!("Internal error: {:?}", req);
+        tracing::error!(request_id = %RequestId::of(req), method = %req.method(), path = %req.uri().path(), "internal error");
         Json("internal server error")
     }
 
@@ -203,17 +546,361 @@ pub mod catcher {
         Json("404")
     }
 
+    // request guards only fail on the API key
     #[catch(401)]
-    fn request_error() -> Json<&'static str> {
-        Json("request error")
+    fn missing_api_key() -> Json<&'static str> {
+        Json("Api key missing or invalid")
     }
 
     #[catch(400)]
-    fn missing_api_key() -> Json<&'static str> {
-        Json("Api key missing or invalid")
+    fn request_error() -> Json<&'static str> {
+        Json("request error")
     }
 
     pub fn catchers() -> Vec<Catcher> {
         catchers![not_found, default, internal_error, missing_api_key, request_error]
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::data::Db;
+    use crate::domain::quota::Quota;
+    use crate::test::async_runtime;
+    use crate::web::api::{ApiKey, API_KEY_HEADER, CLIP_PASSWORD_HEADER};
+    use crate::web::test::{client, client_with, TestClient};
+    use rocket::http::{ContentType, Header, Status};
+
+    // a client with two API keys: one to create clips with, and one of somebody else
+    fn with_keys(client: TestClient) -> (TestClient, ApiKey, ApiKey) {
+        let db = client.rocket().state::<Db>().unwrap();
+        let (owner, other) = async_runtime().block_on(async {
+            let pool = db.get_pool();
+            (crate::service::action::new_api_key(None, pool).await.unwrap(), crate::service::action::new_api_key(None, pool).await.unwrap())
+        });
+        (client, owner, other)
+    }
+
+    #[test]
+    fn creates_clips_with_custom_shortcodes() {
+        let (client, api_key, _) = with_keys(client());
+        let new_clip = |shortcode: &str| client
+            .post("/api/clip")
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .body(format!(r#"{{"content": "notes", "title": null, "expires": null, "password": null, "shortcode": "{}"}}"#, shortcode))
+            .dispatch()
+            .status();
+
+        assert_eq!(new_clip("deploy-notes"), Status::Ok);
+        assert_eq!(new_clip("deploy-notes"), Status::Conflict);
+        assert_eq!(new_clip("raw"), Status::BadRequest);
+        let get_clip = |shortcode: &str| client
+            .get(format!("/api/clip/{}", shortcode))
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .dispatch()
+            .status();
+        assert_eq!(get_clip("deploy-notes"), Status::Ok);
+        assert_eq!(get_clip("not%20a%20shortcode"), Status::BadRequest);
+    }
+
+    #[test]
+    fn filters_clips_by_tag() {
+        let (client, api_key, _) = with_keys(client());
+        let new_clip = |tags: &str| client
+            .post("/api/clip")
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .body(format!(r#"{{"content": "notes", "title": null, "expires": null, "password": null, "tags": {}}}"#, tags))
+            .dispatch();
+        let list_clips = |url: &str| client
+            .get(url.to_owned())
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .dispatch()
+            .into_json::<Vec<serde_json::Value>>()
+            .unwrap();
+
+        let tagged = new_clip(r#"["Deploy", "notes", "deploy"]"#).into_json::<serde_json::Value>().unwrap();
+        assert_eq!(tagged["tags"], serde_json::json!(["deploy", "notes"]));
+        assert_eq!(new_clip(r#"["notes"]"#).status(), Status::Ok);
+        assert_eq!(new_clip(r#"["not a/tag"]"#).status(), Status::UnprocessableEntity);
+
+        assert_eq!(list_clips("/api/clip").len(), 2);
+        assert_eq!(list_clips("/api/clip?tag=notes").len(), 2);
+        let deploy = list_clips("/api/clip?tag=DEPLOY");
+        assert_eq!(deploy.len(), 1);
+        assert_eq!(deploy[0]["shortcode"], tagged["shortcode"]);
+    }
+
+    #[test]
+    fn reports_views_to_the_creator() {
+        let (client, owner, other) = with_keys(client());
+
+        let created = client
+            .post("/api/clip")
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, owner.to_base64()))
+            .body(r#"{"content": "notes", "title": null, "expires": null, "password": null, "shortcode": "viewed"}"#)
+            .dispatch();
+        assert_eq!(created.status(), Status::Ok);
+        for user_agent in ["curl/7.85.0", "clipstash-bot"] {
+            let response = client
+                .get("/api/clip/viewed")
+                .header(Header::new(API_KEY_HEADER, owner.to_base64()))
+                .header(Header::new("User-Agent", user_agent))
+                .header(Header::new("Referer", "https://chat.example.com/room/1"))
+                .remote("192.0.2.1:4000".parse().unwrap())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+        }
+        let hit_counter = client.rocket().state::<crate::web::HitCounter>().unwrap();
+        async_runtime().block_on(hit_counter.shutdown(std::time::Duration::from_secs(5)));
+
+        let stats = |api_key: &ApiKey| client
+            .get("/api/clip/viewed/stats")
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .dispatch();
+        assert_eq!(stats(&other).status(), Status::Forbidden);
+        let stats = stats(&owner).into_json::<serde_json::Value>().unwrap();
+        assert_eq!(stats["hits"], 2);
+        // same address, but told apart by their user agents
+        assert_eq!(stats["visitors"], 2);
+        assert_eq!(stats["hourly"].as_array().unwrap().last().unwrap()["views"], 2);
+        assert_eq!(stats["referrers"], serde_json::json!([{"name": "chat.example.com", "views": 2}]));
+        assert_eq!(stats["agents"], serde_json::json!([{"name": "api", "views": 1}, {"name": "cli", "views": 1}]));
+    }
+
+    #[test]
+    fn renames_and_aliases_clips() {
+        let (client, owner, other) = with_keys(client());
+        let post = |url: &str, body: &str, api_key: &ApiKey| client
+            .post(url.to_owned())
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .body(body)
+            .dispatch();
+
+        let created = post("/api/clip", r#"{"content": "v1", "title": null, "expires": null, "password": null, "shortcode": "release"}"#, &owner);
+        assert_eq!(created.status(), Status::Ok);
+        let share: serde_json::Value = post("/api/clip/release/share?hours=1", "", &owner).into_json().unwrap();
+
+        assert_eq!(post("/api/clip/release/rename", r#"{"shortcode": "release-v2"}"#, &other).status(), Status::Forbidden);
+        let renamed = post("/api/clip/release/rename", r#"{"shortcode": "release-v2"}"#, &owner);
+        assert_eq!(renamed.status(), Status::Ok);
+        let report: serde_json::Value = renamed.into_json().unwrap();
+        assert_eq!(report["shortcode"], "release-v2");
+        assert_eq!(report["aliases"], serde_json::json!(["release"]));
+
+        // the old shortcode and share link keep working
+        let response = client.get(share["url"].as_str().unwrap()).dispatch();
+        assert_eq!(response.status(), Status::MovedPermanently);
+        let location = response.headers().get_one("Location").unwrap().to_owned();
+        assert!(location.starts_with("/clip/release-v2?"));
+        assert_eq!(client.get(location).dispatch().status(), Status::Ok);
+
+        assert_eq!(post("/api/clip/release-v2/alias", r#"{"alias": "latest"}"#, &owner).status(), Status::Ok);
+        assert_eq!(post("/api/clip/release-v2/alias", r#"{"alias": "release"}"#, &owner).status(), Status::Conflict);
+        let taken = post("/api/clip", r#"{"content": "x", "title": null, "expires": null, "password": null, "shortcode": "latest"}"#, &owner);
+        assert_eq!(taken.status(), Status::Conflict);
+        let clip: serde_json::Value = client
+            .get("/api/clip/latest")
+            .header(Header::new(API_KEY_HEADER, owner.to_base64()))
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(clip["shortcode"], "release-v2");
+
+        let removed = client
+            .delete("/api/clip/release-v2/alias/latest")
+            .header(Header::new(API_KEY_HEADER, owner.to_base64()))
+            .dispatch();
+        assert_eq!(removed.status(), Status::Ok);
+        assert_eq!(client.get("/clip/latest").dispatch().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn groups_clips_in_collections() {
+        use rocket::local::blocking::{LocalRequest, LocalResponse};
+
+        let (client, owner, other) = with_keys(client());
+        fn send<'c>(request: LocalRequest<'c>, api_key: &ApiKey) -> LocalResponse<'c> {
+            request
+                .header(ContentType::JSON)
+                .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+                .dispatch()
+        }
+
+        for shortcode in ["timeline", "postmortem"] {
+            let body = format!(r#"{{"content": "...", "title": "{0} title", "expires": null, "password": null, "shortcode": "{0}"}}"#, shortcode);
+            assert_eq!(send(client.post("/api/clip").body(body), &owner).status(), Status::Ok);
+        }
+
+        // only the creator of the clips may collect them
+        let body = r#"{"title": "Incident 42", "password": "tulip-orbit-9", "shortcode": "incident-42", "clips": ["timeline"]}"#;
+        assert_eq!(send(client.post("/api/collection").body(body), &other).status(), Status::Forbidden);
+        assert_eq!(send(client.post("/api/collection").body(body), &owner).status(), Status::Ok);
+        assert_eq!(send(client.put("/api/collection/incident-42/clip/postmortem"), &other).status(), Status::Forbidden);
+        assert_eq!(send(client.put("/api/collection/incident-42/clip/postmortem"), &owner).status(), Status::Ok);
+
+        assert_eq!(send(client.get("/api/collection/incident-42"), &other).status(), Status::Unauthorized);
+        let collection: serde_json::Value = send(
+            client.get("/api/collection/incident-42").header(Header::new(CLIP_PASSWORD_HEADER, "tulip-orbit-9")),
+            &other
+        ).into_json().unwrap();
+        assert_eq!(collection["title"], "Incident 42");
+        assert_eq!(collection["clips"][1]["shortcode"], "postmortem");
+
+        // the index page asks for the password first
+        assert_eq!(client.get("/collection/incident-42").dispatch().status(), Status::Unauthorized);
+        let page = client
+            .post("/collection/incident-42")
+            .header(ContentType::Form)
+            .body("password=tulip-orbit-9")
+            .dispatch();
+        assert_eq!(page.status(), Status::Ok);
+        assert!(page.into_string().unwrap().contains("postmortem title"));
+
+        let collection: serde_json::Value = send(client.delete("/api/collection/incident-42/clip/timeline"), &owner).into_json().unwrap();
+        assert_eq!(collection["clips"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn enforces_clip_quota_per_api_key() {
+        let (client, api_key, _) = with_keys(client_with(|config| config.quota = Quota { max_clips: Some(1), max_bytes: None }));
+        let new_clip = || client
+            .post("/api/clip")
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .body(r#"{"content": "content", "title": null, "expires": null, "password": null}"#)
+            .dispatch()
+            .status();
+
+        assert_eq!(new_clip(), Status::Ok);
+        assert_eq!(new_clip(), Status::Forbidden);
+
+        let usage = client
+            .get("/api/usage")
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .dispatch();
+        assert_eq!(usage.status(), Status::Ok);
+        let usage: serde_json::Value = usage.into_json().unwrap();
+        assert_eq!(usage["usage"]["clips"], 1);
+    }
+
+    #[test]
+    fn enforces_clip_visibility() {
+        let (client, owner, other) = with_keys(client());
+        let get_clip = |shortcode: &str, api_key: &ApiKey| client
+            .get(format!("/api/clip/{}", shortcode))
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .dispatch()
+            .status();
+
+        let response = client
+            .post("/api/clip")
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, owner.to_base64()))
+            .body(r#"{"content": "secret", "title": null, "expires": null, "password": null, "visibility": "private"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let clip: serde_json::Value = response.into_json().unwrap();
+        let shortcode = clip["shortcode"].as_str().unwrap().to_owned();
+
+        assert_eq!(get_clip(&shortcode, &owner), Status::Ok);
+        assert_eq!(get_clip(&shortcode, &other), Status::Forbidden);
+        assert_eq!(client.get(format!("/clip/raw/{}", shortcode)).dispatch().status(), Status::Forbidden);
+
+        let share = |api_key: &ApiKey| client
+            .put("/api/clip")
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .body(format!(
+                r#"{{"shortcode": "{}", "content": "secret", "title": null, "expires": null, "password": null,
+                    "visibility": "shared-with", "shared_with": ["{}"]}}"#,
+                shortcode,
+                other.to_base64()
+            ))
+            .dispatch()
+            .status();
+        // only the creator may change who sees the clip
+        assert_eq!(share(&other), Status::Forbidden);
+        assert_eq!(get_clip(&shortcode, &other), Status::Forbidden);
+        assert_eq!(share(&owner), Status::Ok);
+        assert_eq!(get_clip(&shortcode, &other), Status::Ok);
+    }
+
+    #[test]
+    fn share_links_grant_access_until_revoked() {
+        let (client, owner, other) = with_keys(client());
+
+        let response = client
+            .post("/api/clip")
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, owner.to_base64()))
+            .body(r#"{"content": "review me", "title": null, "expires": null, "password": "hunter22", "visibility": "private"}"#)
+            .dispatch();
+        let clip: serde_json::Value = response.into_json().unwrap();
+        let shortcode = clip["shortcode"].as_str().unwrap().to_owned();
+
+        let share = |api_key: &ApiKey| client
+            .post(format!("/api/clip/{}/share?hours=4", shortcode))
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .dispatch();
+        assert_eq!(share(&other).status(), Status::Forbidden);
+        let response = share(&owner);
+        assert_eq!(response.status(), Status::Ok);
+        let link: serde_json::Value = response.into_json().unwrap();
+        let query = link["url"].as_str().unwrap().split_once('?').unwrap().1.to_owned();
+
+        let raw = |query: &str| client.get(format!("/clip/raw/{}?{}", shortcode, query)).dispatch();
+        let response = raw(&query);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().unwrap(), "review me");
+        assert_eq!(raw(&query.replace("exp=", "exp=1")).status(), Status::Forbidden);
+
+        let response = client
+            .delete(format!("/api/clip/{}/share", shortcode))
+            .header(Header::new(API_KEY_HEADER, owner.to_base64()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(raw(&query).status(), Status::Forbidden);
+    }
+
+    #[test]
+    fn unlocks_with_password_header_or_token() {
+        let (client, api_key, _) = with_keys(client());
+
+        let response = client
+            .post("/api/clip")
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .body(r#"{"content": "secret", "title": null, "expires": null, "password": "hunter22"}"#)
+            .dispatch();
+        let clip: serde_json::Value = response.into_json().unwrap();
+        let url = format!("/api/clip/{}", clip["shortcode"].as_str().unwrap());
+
+        let get_clip = |header: Option<Header<'static>>| {
+            let mut request = client.get(url.clone()).header(Header::new(API_KEY_HEADER, api_key.to_base64()));
+            if let Some(header) = header {
+                request = request.header(header);
+            }
+            request.dispatch().status()
+        };
+        assert_eq!(client.get(url.clone()).dispatch().status(), Status::Unauthorized);
+        assert_eq!(get_clip(None), Status::Unauthorized);
+        assert_eq!(get_clip(Some(Header::new(CLIP_PASSWORD_HEADER, "wrong"))), Status::Unauthorized);
+        assert_eq!(get_clip(Some(Header::new(CLIP_PASSWORD_HEADER, "hunter22"))), Status::Ok);
+
+        let response = client
+            .post(format!("{}/unlock", url))
+            .header(ContentType::JSON)
+            .header(Header::new(API_KEY_HEADER, api_key.to_base64()))
+            .body(r#"{"password": "hunter22"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let unlocked: serde_json::Value = response.into_json().unwrap();
+        assert_eq!(unlocked["clip"]["content"], "secret");
+        let token = unlocked["token"].as_str().unwrap().to_owned();
+        assert_eq!(get_clip(Some(Header::new("Authorization", format!("Bearer {}", token)))), Status::Ok);
+    }
+}
@@ -0,0 +1,23 @@
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::State;
+use std::net::IpAddr;
+
+use crate::domain::lockout::Lockout;
+
+// client that may be guessing a clip password, and the failures tracked so far
+pub struct Attempts<'r> {
+    pub client: Option<IpAddr>,
+    pub lockout: &'r Lockout,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Attempts<'r> {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        request.guard::<&State<Lockout>>().await.map(|lockout| Attempts {
+            client: request.client_ip(),
+            lockout: lockout.inner(),
+        })
+    }
+}
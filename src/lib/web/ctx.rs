@@ -7,13 +7,9 @@ pub trait PageCtx {
     fn parent(&self) -> &str;
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub struct Home {}
 
-impl Default for Home {
-    fn default() -> Self { Self {} }
-}
-
 impl PageCtx for Home {
     fn title(&self) -> &str { "Stash Your Clipboard!" }
     fn template_path(&self) -> &str { "home" }
@@ -23,6 +19,10 @@ impl PageCtx for Home {
 #[derive(Debug, Serialize, Constructor)]
 pub struct ViewClip {
     pub clip: crate::Clip,
+    // passphrase generated for a clip that was just created
+    pub generated_password: Option<String>,
+    // views of the clip, shown to its creator only
+    pub stats: Option<crate::domain::stats::ClipStats>,
 }
 
 impl PageCtx for ViewClip {
@@ -41,3 +41,58 @@ impl PageCtx for PasswordRequired {
     fn template_path(&self) -> &str { "clip_need_password" }
     fn parent(&self) -> &str { "base" }
 }
+
+#[derive(Debug, Serialize, Constructor)]
+pub struct ViewCollection {
+    shortcode: crate::ShortCode,
+    // None until the password was entered
+    collection: Option<crate::domain::Collection>,
+    clips: Vec<crate::domain::collection::Member>,
+}
+
+impl PageCtx for ViewCollection {
+    fn title(&self) -> &str { "View Collection" }
+    fn template_path(&self) -> &str { "collection" }
+    fn parent(&self) -> &str { "base" }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Register {}
+
+impl PageCtx for Register {
+    fn title(&self) -> &str { "Create an Account" }
+    fn template_path(&self) -> &str { "register" }
+    fn parent(&self) -> &str { "base" }
+}
+
+#[derive(Debug, Serialize, Constructor)]
+pub struct Login {
+    // whether single sign-on is configured
+    sso: bool,
+    // whether the username and password form is offered
+    password_login: bool,
+}
+
+impl PageCtx for Login {
+    fn title(&self) -> &str { "Log In" }
+    fn template_path(&self) -> &str { "login" }
+    fn parent(&self) -> &str { "base" }
+}
+
+#[derive(Debug, Serialize, Constructor)]
+pub struct MyClips {
+    user: crate::domain::User,
+    clips: Vec<crate::Clip>,
+    // only set right after the key was created, it is never shown again
+    api_key: Option<String>,
+    // signed link to one of the clips, shown right after it was requested
+    share_link: Option<String>,
+    // only clips carrying this tag are listed
+    tag: Option<String>,
+}
+
+impl PageCtx for MyClips {
+    fn title(&self) -> &str { "My Clips" }
+    fn template_path(&self) -> &str { "my_clips" }
+    fn parent(&self) -> &str { "base" }
+}
@@ -1,5 +1,7 @@
 use crate::domain::clip::field::*;
+use crate::domain::user::field::Username;
 use rocket::form::FromForm;
+use rocket::UriDisplayQuery;
 use serde::Serialize;
 
 #[derive(Debug, Serialize, FromForm)]
@@ -7,10 +9,50 @@ pub struct NewClip {
     pub content: Content,
     pub title: Title,
     pub expires: Expires,
-    pub password: Password
+    pub password: Password,
+    pub visibility: Visibility,
+    pub shared_with: SharedWith,
+    pub burn_after: BurnAfter,
+    pub generate_password: bool,
+    // left empty for a generated shortcode
+    pub shortcode: Option<String>,
+    pub tags: Tags,
 }
 
 #[derive(Debug, Serialize, FromForm)]
 pub struct GetPasswordProtectedClip {
     pub password: Password
-}
\ No newline at end of file
+}
+
+// new account, the username and password rules apply
+#[derive(Debug, FromForm)]
+pub struct Account {
+    pub username: Username,
+    pub password: crate::domain::user::field::Password
+}
+
+// credentials as typed: a password too short to register with is just a wrong one
+#[derive(Debug, FromForm)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+// query string of a signed share link: /clip/<shortcode>?sig=...&exp=...
+#[derive(Debug, FromForm, UriDisplayQuery)]
+pub struct ShareLink {
+    pub sig: String,
+    pub exp: i64,
+}
+
+impl From<ShareLink> for crate::domain::share::ShareLink {
+    fn from(link: ShareLink) -> Self {
+        Self::new(link.sig, link.exp)
+    }
+}
+
+#[derive(Debug, FromForm)]
+pub struct ShareClip {
+    #[field(validate = range(1..=crate::domain::share::ShareLink::MAX_HOURS as isize))]
+    pub hours: u32,
+}
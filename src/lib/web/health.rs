@@ -0,0 +1,140 @@
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::Serialize;
+
+use crate::data::{self, Db};
+use crate::domain::maintenance::Maintenance;
+use crate::service::action;
+use crate::web::HitCounter;
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn ok() -> Self {
+        Self { ok: true, detail: None }
+    }
+
+    fn failed<D: Into<String>>(detail: D) -> Self {
+        Self { ok: false, detail: Some(detail.into()) }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Liveness {
+    status: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    status: &'static str,
+    schema_version: Option<i64>,
+    database: Check,
+    migrations: Check,
+    hit_counter: Check,
+    maintenance: Check,
+}
+
+impl Readiness {
+    fn is_ready(&self) -> bool {
+        [&self.database, &self.migrations, &self.hit_counter, &self.maintenance]
+            .iter()
+            .all(|check| check.ok)
+    }
+}
+
+// the process is up and answering
+#[rocket::get("/healthz")]
+pub fn healthz() -> Json<Liveness> {
+    Json(Liveness { status: "ok" })
+}
+
+// the instance can serve traffic: 503 with the failed checks otherwise
+#[rocket::get("/readyz")]
+pub async fn readyz(
+    db: &State<Db>,
+    hit_counter: &State<HitCounter>,
+    maintenance: &State<Maintenance>,
+) -> (Status, Json<Readiness>) {
+    let pool = db.get_pool();
+    let database = match action::ping(pool).await {
+        Ok(()) => Check::ok(),
+        Err(e) => {
+            tracing::error!(error = ?e, "readiness: database unreachable");
+            Check::failed("database unreachable")
+        }
+    };
+    let expected = data::expected_schema_version();
+    let schema_version = action::schema_version(pool).await;
+    let migrations = match &schema_version {
+        Ok(version) if *version == expected => Check::ok(),
+        Ok(version) => Check::failed(format!(
+            "schema at version {}, expected {}",
+            version.map_or("none".to_owned(), |v| v.to_string()),
+            expected.map_or("none".to_owned(), |v| v.to_string()),
+        )),
+        Err(e) => {
+            tracing::error!(error = ?e, "readiness: schema version unknown");
+            Check::failed("schema version unknown")
+        }
+    };
+    let hit_counter = if hit_counter.is_alive() {
+        Check::ok()
+    } else {
+        Check::failed("hit counter stopped")
+    };
+    let maintenance = if maintenance.is_ticking() {
+        Check::ok()
+    } else {
+        Check::failed(format!("last run at {}", maintenance.last_run().into_inner().to_rfc3339()))
+    };
+
+    let schema_version = schema_version.ok().flatten();
+    let mut readiness = Readiness { status: "ok", schema_version, database, migrations, hit_counter, maintenance };
+    if readiness.is_ready() {
+        (Status::Ok, Json(readiness))
+    } else {
+        readiness.status = "unavailable";
+        (Status::ServiceUnavailable, Json(readiness))
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes!(healthz, readyz)
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::test::async_runtime;
+    use crate::web::test::client;
+    use rocket::http::Status;
+    use serde_json::Value;
+
+    #[test]
+    fn reports_readiness() {
+        let client = client();
+        assert_eq!(client.get("/healthz").dispatch().status(), Status::Ok);
+
+        let response = client.get("/readyz").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["status"], "ok");
+        assert_eq!(body["migrations"]["ok"], true);
+        assert_eq!(body["schema_version"], crate::data::expected_schema_version().unwrap());
+
+        // a stopped hit counter takes the instance out of rotation
+        let hit_counter = client.rocket().state::<crate::web::HitCounter>().unwrap();
+        async_runtime().block_on(hit_counter.shutdown(crate::web::HitCounter::SHUTDOWN_GRACE));
+        let response = client.get("/readyz").dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["status"], "unavailable");
+        assert_eq!(body["hit_counter"]["ok"], false);
+        assert_eq!(body["database"]["ok"], true);
+    }
+}
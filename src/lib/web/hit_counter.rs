@@ -1,123 +1,252 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use crate::data::DbPool;
-use crate::ShortCode;
-use crate::service::{self, ServiceError};
-use crossbeam_channel::TryRecvError;
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crate::domain::clip::field::ClipId;
+use crate::service;
+use crate::domain::stats::{View, Visitor};
+use crate::web::viewer::Viewer;
+use crate::web::Metrics;
 use parking_lot::Mutex;
-use std::sync::Arc;
 use std::time::Duration;
-use rocket::async_trait;
 use tokio::runtime::Handle;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+// views per clip and hour, referrer and client, and the visitors seen,
+// waiting to be committed. only touched by the counting task
+#[derive(Default)]
+struct HitStore {
+    views: HashMap<(ClipId, View), u32>,
+    visitors: HashSet<(ClipId, Visitor)>,
+    // the last commit failed: wait for the interval instead of retrying on every hit
+    retrying: bool,
+}
 
-// mutually exclusive lock (Mutex) shareable between threads (Arc)
-type HitStore = Arc<Mutex<HashMap<ShortCode, u32>>>;
+impl HitStore {
+    fn len(&self) -> usize {
+        self.views.len() + self.visitors.len()
+    }
 
-#[derive(Debug, thiserror::Error)]
-enum HitCountError {
-    #[error("service error: {0}")]
-    Service(#[from] ServiceError),
-    #[error("channel communication error: {0}")]
-    Channel(#[from] crossbeam_channel::SendError<HitCountMsg>),
+    fn is_empty(&self) -> bool {
+        self.views.is_empty() && self.visitors.is_empty()
+    }
 }
 
 enum HitCountMsg {
-    Commit,
-    Hit(ShortCode, u32),
+    // keyed by clip_id: a shortcode may change before the hit is committed
+    Hit(ClipId, Viewer),
+    // commit whatever is buffered, acknowledge and stop the task
+    Shutdown(oneshot::Sender<()>),
 }
 
 pub struct HitCounter {
-    tx: Sender<HitCountMsg>,
+    tx: mpsc::UnboundedSender<HitCountMsg>,
+    metrics: Metrics,
+    task: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl HitCounter {
-    fn commit_hits(hits: HitStore, handle: Handle, pool: DbPool) -> Result<(), HitCountError> {
-        let hits = Arc::clone(&hits);
-
-        // convert hashmap into vector to avoid compiler errors when passing it
-        // as arc mutex to async executor
-        let hits: Vec<(ShortCode, u32)> = {
-            let mut hits = hits.lock();
-            let hits_vec = hits
-                .iter()
-                .map(|(k, v)| (k.clone(), *v))
-                .collect();
-            hits.clear();
-            hits_vec
-        };
-
-        handle.block_on(async move {
-            let tx = service::action::begin_tx(&pool).await?;
-            for (shortcode, hits) in hits {
-                if let Err(e) = service::action::increase_hit_count(&shortcode, hits, &pool).await {
-                    eprintln!("error increasing hit count {:?}", e);
-                }
+    // upper bound for hits to stay buffered, no matter how busy the channel is
+    pub const COMMIT_INTERVAL: Duration = Duration::from_secs(5);
+    // distinct views and visitors buffered before committing ahead of the interval
+    pub const COMMIT_THRESHOLD: usize = 500;
+    // how long shutting down waits for the last commit
+    pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+    // hits stay buffered until they are committed, a failed commit is retried with the next one
+    async fn commit_hits(hits: &mut HitStore, pool: &DbPool, metrics: &Metrics) {
+        if hits.is_empty() {
+            return;
+        }
+        let _timer = metrics.hit_flush_duration.start_timer();
+        let views = hits.views
+            .iter()
+            .map(|((clip_id, view), count)| (clip_id.clone(), view.clone(), *count))
+            .collect();
+        let visitors = hits.visitors.iter().cloned().collect();
+        match service::action::record_views(views, visitors, pool).await {
+            Ok(()) => *hits = HitStore::default(),
+            Err(e) => {
+                tracing::error!(error = ?e, pending = hits.len(), "failed to commit hits, retrying later");
+                hits.retrying = true;
             }
-            Ok(service::action::end_tx(tx).await?)
-        })
+        }
     }
 
-    fn process_msg(
-        msg: HitCountMsg,
-        hits: HitStore,
-        handle: Handle, pool:
-        DbPool,
-    ) -> Result<(), HitCountError> {
-        match msg {
-            HitCountMsg::Commit => Self::commit_hits(hits.clone(), handle.clone(), pool.clone())?,
-            HitCountMsg::Hit(shortcode, count) => {
-                let mut hit_count = hits.lock();
-                let hit_count = hit_count.entry(shortcode).or_insert(0);
-                *hit_count += count;
-            }
+    fn log_lost(hits: &HitStore) {
+        if !hits.is_empty() {
+            tracing::error!(pending = hits.len(), "hit counter stopped with uncommitted hits, they are lost");
         }
-
-        Ok(())
     }
 
-    pub fn new(pool: DbPool, handle: Handle) -> Self {
-        let (tx, rx) = unbounded();
-        let tx_clone = tx.clone();
-        let rx_clone = rx.clone();
+    pub fn new(pool: DbPool, handle: Handle, metrics: Metrics) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let task_metrics = metrics.clone();
 
-        let _ = std::thread::spawn(move || {
-            println!("HitCounter thread spawned");
-            let hits: HitStore = Arc::new(Mutex::new(HashMap::new()));
+        let task = handle.spawn(async move {
+            let mut hits = HitStore::default();
+            let mut interval = tokio::time::interval(Self::COMMIT_INTERVAL);
 
             loop {
-                match rx_clone.try_recv() {
-                    Ok(msg) => if let Err(e) = Self::process_msg(
-                        msg,
-                        hits.clone(),
-                        handle.clone(),
-                        pool.clone(),
-                    ) {
-                        eprintln!("message processing error {:?}", e)
-                    },
-                    Err(e) => match e {
-                        // channel empty
-                        TryRecvError::Empty => {
-                            // wait
-                            std::thread::sleep(Duration::from_secs(5));
-                            // commit to Db if no errors
-                            if let Err(e) = tx_clone.send(HitCountMsg::Commit) {
-                                eprintln!("error sending commit msg to hits channel {:?}", e)
+                tokio::select! {
+                    _ = interval.tick() => Self::commit_hits(&mut hits, &pool, &task_metrics).await,
+                    msg = rx.recv() => match msg {
+                        Some(HitCountMsg::Hit(clip_id, viewer)) => {
+                            task_metrics.hit_queue_depth.dec();
+                            if let Some(visitor) = viewer.visitor {
+                                hits.visitors.insert((clip_id.clone(), visitor));
                             }
-                        },
-                        _ => break
+                            let hit_count = hits.views.entry((clip_id, viewer.view)).or_insert(0);
+                            *hit_count = hit_count.saturating_add(1);
+                            if hits.len() >= Self::COMMIT_THRESHOLD && !hits.retrying {
+                                Self::commit_hits(&mut hits, &pool, &task_metrics).await;
+                                interval.reset();
+                            }
+                        }
+                        Some(HitCountMsg::Shutdown(ack)) => {
+                            Self::commit_hits(&mut hits, &pool, &task_metrics).await;
+                            Self::log_lost(&hits);
+                            let _ = ack.send(());
+                            break;
+                        }
+                        // every HitCounter handle is gone
+                        None => {
+                            Self::commit_hits(&mut hits, &pool, &task_metrics).await;
+                            Self::log_lost(&hits);
+                            break;
+                        }
                     }
                 }
             }
         });
 
-        Self { tx }
+        Self { tx, metrics, task: Mutex::new(Some(task)) }
+    }
+
+    pub fn hit(&self, clip_id: ClipId, viewer: Viewer) {
+        self.metrics.clips_served.inc();
+        // counted before sending, the task may receive it right away
+        self.metrics.hit_queue_depth.inc();
+        if let Err(e) = self.tx.send(HitCountMsg::Hit(clip_id, viewer)) {
+            self.metrics.hit_queue_depth.dec();
+            tracing::error!(error = %e, "hit counter stopped, hit is lost")
+        }
+    }
+
+    // the task drops its receiver when it ends, also when it panics
+    pub fn is_alive(&self) -> bool {
+        !self.tx.is_closed()
     }
 
-    pub fn hit(&self, shortcode: ShortCode, count: u32) {
-        if let Err(e) = self.tx.send(HitCountMsg::Hit(shortcode, count)) {
-            eprintln!("hit count error {}", e)
+    // commits buffered hits and waits for the task to end, giving up after `grace`
+    pub async fn shutdown(&self, grace: Duration) {
+        let (ack, acked) = oneshot::channel();
+        if self.tx.send(HitCountMsg::Shutdown(ack)).is_err() {
+            tracing::warn!("hit counter already stopped");
+            return;
+        }
+        match tokio::time::timeout(grace, acked).await {
+            Ok(Ok(())) => {
+                let task = self.task.lock().take();
+                if let Some(task) = task {
+                    let _ = task.await;
+                }
+            }
+            _ => tracing::error!(?grace, "hit counter did not commit in time, pending hits are lost"),
         }
     }
 }
 
+#[cfg(test)]
+pub mod test {
+    use super::HitCounter;
+    use crate::data::test::{new_clip, new_db};
+    use crate::domain::stats::{Agent, View, VisitorSalt};
+    use crate::web::viewer::Viewer;
+    use crate::test::async_runtime;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    #[test]
+    fn commits_pending_hits_on_shutdown() {
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        let hits = |clip: &crate::Clip| {
+            let clip = rt.block_on(crate::data::query::get_clip(clip.shortcode.clone(), pool)).unwrap();
+            let clip = crate::Clip::try_from(clip).unwrap();
+            (clip.hits.into_inner(), clip.visitors.into_inner())
+        };
+        let (first, second) = (rt.block_on(new_clip(pool)), rt.block_on(new_clip(pool)));
+
+        let hit_counter = HitCounter::new(pool.clone(), rt.handle().clone(), Default::default());
+        let salt = VisitorSalt::default();
+        let viewer = |ip: &str, agent| Viewer {
+            view: View::new(Some("https://example.com/"), agent),
+            visitor: salt.visitor(ip.parse().ok(), Some("curl/7.85.0")),
+        };
+        hit_counter.hit(first.clip_id.clone(), viewer("192.0.2.1", Agent::Browser));
+        hit_counter.hit(first.clip_id.clone(), viewer("192.0.2.1", Agent::Browser));
+        hit_counter.hit(first.clip_id.clone(), viewer("192.0.2.2", Agent::Cli));
+        hit_counter.hit(second.clip_id.clone(), viewer("192.0.2.1", Agent::Browser));
+        rt.block_on(hit_counter.shutdown(Duration::from_secs(5)));
+        assert!(hit_counter.task.lock().is_none());
+
+        assert_eq!(hits(&first), (3, 2));
+        assert_eq!(hits(&second), (1, 1));
+
+        // the same visitor is counted once a day
+        let hit_counter = HitCounter::new(pool.clone(), rt.handle().clone(), Default::default());
+        hit_counter.hit(first.clip_id.clone(), viewer("192.0.2.2", Agent::Cli));
+        rt.block_on(hit_counter.shutdown(Duration::from_secs(5)));
+        assert_eq!(hits(&first), (4, 2));
+    }
 
+    #[test]
+    fn keeps_hits_when_commit_fails() {
+        use super::HitStore;
+        use sqlx::Executor;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        let clip = rt.block_on(new_clip(pool));
+        let salt = VisitorSalt::default();
+        let mut hits = HitStore::default();
+        hits.views.insert((clip.clip_id.clone(), View::new(None, Agent::Cli)), 2);
+        hits.visitors.insert((clip.clip_id.clone(), salt.visitor("192.0.2.1".parse().ok(), None).unwrap()));
+        let metrics = Default::default();
+
+        // view stats can't be saved while their table is away
+        rt.block_on(pool.execute("ALTER TABLE clip_views RENAME TO clip_views_away")).unwrap();
+        rt.block_on(HitCounter::commit_hits(&mut hits, pool, &metrics));
+        assert_eq!(hits.len(), 2);
+        assert!(hits.retrying);
+
+        rt.block_on(pool.execute("ALTER TABLE clip_views_away RENAME TO clip_views")).unwrap();
+        rt.block_on(HitCounter::commit_hits(&mut hits, pool, &metrics));
+        assert!(hits.is_empty() && !hits.retrying);
+        let clip = rt.block_on(crate::data::query::get_clip(clip.shortcode.clone(), pool)).unwrap();
+        let clip = crate::Clip::try_from(clip).unwrap();
+        assert_eq!((clip.hits.into_inner(), clip.visitors.into_inner()), (2, 1));
+    }
+
+    #[test]
+    fn counts_hits_of_renamed_clips() {
+        use crate::data::repository::ClipRepository;
+
+        let rt = async_runtime();
+        let db = new_db(rt.handle());
+        let pool = db.get_pool();
+        let clip = rt.block_on(new_clip(pool));
+
+        let hit_counter = HitCounter::new(pool.clone(), rt.handle().clone(), Default::default());
+        hit_counter.hit(clip.clip_id.clone(), Viewer { view: View::new(None, Agent::Cli), visitor: None });
+        // renamed while the hit is still buffered
+        let renamed = crate::ShortCode::from("renamed");
+        rt.block_on(pool.rename_clip(&clip.clip_id, &clip.shortcode, &renamed)).unwrap();
+        rt.block_on(hit_counter.shutdown(Duration::from_secs(5)));
+
+        let clip = rt.block_on(crate::data::query::get_clip(renamed, pool)).unwrap();
+        assert_eq!(crate::Clip::try_from(clip).unwrap().hits.into_inner(), 1);
+    }
+}
@@ -1,17 +1,24 @@
+// route arguments are request guards resolved by rocket, pages simply need many of them
+#![allow(clippy::too_many_arguments)]
+
 use rocket::form::{Contextual, Form};
-use rocket::http::{Cookie, CookieJar, Status};
+use rocket::http::{CookieJar, Status};
 use rocket::response::content::RawHtml;
 use rocket::response::{status, Redirect};
-use rocket::{uri, State};
-use rocket::http::uri::fmt::UriQueryArgument::Raw;
+use rocket::{uri, Either, State};
 
-use crate::data::Database;
-use crate::{Db, HitCounter, service};
+use crate::{Db, HitCounter};
 use crate::service::{action, ask};
-use crate::web::{form, renderer::Renderer, PageError, ctx, PASSWORD_COOKIE};
+use crate::web::{form, renderer::Renderer, PageError, SecureCookies, ctx, unlock_cookie, unlock_token};
 use crate::{ServiceError, ShortCode};
-use crate::domain::clip::field::Content;
 use crate::web::ctx::*;
+use crate::web::session::Session;
+use crate::web::oidc::{self, OidcConfig};
+use crate::web::attempts::Attempts;
+use crate::web::viewer::Viewer;
+use crate::web::Metrics;
+use crate::domain::clip::field::{PasswordPolicy, ShortCodeGenerator};
+use crate::domain::quota::Quota;
 
 #[rocket::get("/")]
 fn home(renderer: &State<Renderer<'_>>) -> RawHtml<String> {
@@ -19,6 +26,23 @@ fn home(renderer: &State<Renderer<'_>>) -> RawHtml<String> {
     RawHtml(renderer.render(ctx, &[]))
 }
 
+// validation messages of a form that could not be parsed
+fn form_errors<'a>(context: &'a rocket::form::Context<'_>) -> Vec<&'a str> {
+    context
+        .errors()
+        .map(|err| {
+            use rocket::form::error::ErrorKind;
+            if let ErrorKind::Validation(msg) = &err.kind {
+                msg.as_ref()
+            } else {
+                // only the field name, the value may be a password
+                tracing::warn!(field = ?err.name, kind = %err.kind, "unhandled form error");
+                "An error occurred, please try again"
+            }
+        })
+        .collect()
+}
+
 
 #[rocket::post("/", data = "<form>")]
 pub async fn new_clip(
@@ -26,9 +50,14 @@ pub async fn new_clip(
     // form data may not exist or be incorrect
     // using Contextual allows to accept invalid form data
     form: Form<Contextual<'_, form::NewClip>>,
+    session: Option<Session>,
     db: &State<Db>,
+    quota: &State<Quota>,
+    policy: &State<PasswordPolicy>,
+    shortcodes: &State<ShortCodeGenerator>,
+    metrics: &State<Metrics>,
     renderer: &State<Renderer<'_>>,
-) -> Result<Redirect, (Status, RawHtml<String>)> {
+) -> Result<Either<Redirect, RawHtml<String>>, (Status, RawHtml<String>)> {
     let form = form.into_inner(); // to get Contextual
     if let Some(value) = form.value {
         let req = ask::NewClip {
@@ -37,12 +66,41 @@ pub async fn new_clip(
             title: value.title,
             expires: value.expires,
             password: value.password,
+            visibility: value.visibility,
+            shared_with: value.shared_with,
+            burn_after: value.burn_after,
+            generate_password: value.generate_password,
+            shortcode: value.shortcode.filter(|shortcode| !shortcode.trim().is_empty()).map(ShortCode::from),
+            tags: value.tags,
+        };
+        let generated = req.generate_password;
+
+        let owner = match session {
+            Some(session) => ask::Identity::User(session.user_id),
+            None => ask::Identity::Anonymous
         };
 
-        match action::new_clip(req, db.get_pool()).await {
-            Ok(clip) => Ok(Redirect::to(uri!(get_clip(shortcode = clip.shortcode)))),
+        let created = action::new_clip(req, owner, quota, policy, shortcodes, db.get_pool()).await;
+        if created.is_ok() {
+            metrics.clips_created.inc();
+        }
+        match created {
+            // a generated passphrase is shown this once, before the creator is sent anywhere else
+            Ok(clip) if generated => {
+                let password = clip.password.clone().into_inner();
+                Ok(Either::Right(RawHtml(renderer.render(ctx::ViewClip::new(clip, password, None), &[]))))
+            }
+            Ok(clip) => Ok(Either::Left(Redirect::to(uri!(get_clip(shortcode = clip.shortcode, share = _))))),
+            Err(ServiceError::Clip(e)) => Err((
+                Status::BadRequest,
+                RawHtml(renderer.render_with_data(ctx::Home::default(), ("clip", &form.context), &[e.to_string().as_str()]))
+            )),
+            Err(ServiceError::Conflict(msg)) => Err((
+                Status::Conflict,
+                RawHtml(renderer.render_with_data(ctx::Home::default(), ("clip", &form.context), &[msg.as_str()]))
+            )),
             Err(e) => {
-                eprintln!("internal error: {:?}", e);
+                tracing::error!(error = ?e, "internal error");
                 Err((
                     Status::InternalServerError,
                     RawHtml(
@@ -54,19 +112,7 @@ pub async fn new_clip(
             }
         }
     } else {
-        let errors = form
-            .context
-            .errors()
-            .map(|err| {
-                use rocket::form::error::ErrorKind;
-                if let ErrorKind::Validation(msg) = &err.kind {
-                    msg.as_ref()
-                } else {
-                    eprintln!("unhandled error: {:?}", err);
-                    "An error occurred, please try again"
-                }
-            })
-            .collect::<Vec<_>>();
+        let errors = form_errors(&form.context);
 
         Err((
             Status::BadRequest,
@@ -81,25 +127,58 @@ pub async fn new_clip(
     }
 }
 
-#[rocket::get("/clip/<shortcode>")]
+// requests from logged in users may view their private and shared clips
+fn requester(session: Option<Session>) -> ask::Identity {
+    match session {
+        Some(session) => ask::Identity::User(session.user_id),
+        None => ask::Identity::Anonymous
+    }
+}
+
+#[rocket::get("/clip/<shortcode>?<share..>")]
 pub async fn get_clip(
+    cookies: &CookieJar<'_>,
     shortcode: ShortCode,
+    share: Option<form::ShareLink>,
+    session: Option<Session>,
+    attempts: Attempts<'_>,
+    viewer: Viewer,
     db: &State<Db>,
     hit_counter: &State<HitCounter>,
     renderer: &State<Renderer<'_>>,
-) -> Result<status::Custom<RawHtml<String>>, PageError> {
+) -> Result<Either<Redirect, status::Custom<RawHtml<String>>>, PageError> {
     fn render_with_status<T: PageCtx + serde::Serialize + std::fmt::Debug>(
         status: Status,
         context: T,
         renderer: &Renderer,
-    ) -> Result<status::Custom<RawHtml<String>>, PageError> {
-        Ok(status::Custom(status, RawHtml(renderer.render(context, &[]))))
+    ) -> Result<Either<Redirect, status::Custom<RawHtml<String>>>, PageError> {
+        Ok(Either::Right(status::Custom(status, RawHtml(renderer.render(context, &[])))))
     }
 
-    match action::get_clip(shortcode.clone().into(), db.get_pool()).await {
+    // renamed clips and aliases permanently redirect to the current shortcode, keeping share links intact
+    match action::resolve_alias(&shortcode, db.get_pool()).await {
+        Ok(Some(primary)) => {
+            let link = share;
+            return Ok(Either::Left(Redirect::moved(uri!(get_clip(shortcode = primary, share = link)))));
+        }
+        Ok(None) => (),
+        Err(ServiceError::NotFound) => return Err(PageError::NotFound("Clip not found".to_owned())),
+        Err(_) => return Err(PageError::Internal("server error".to_owned())),
+    }
+
+    let req = ask::GetClip {
+        share_link: share.map(Into::into),
+        unlock_token: unlock_token(cookies, &shortcode),
+        ..shortcode.clone().into()
+    };
+
+    let requester = requester(session);
+    match action::get_clip(req, requester.clone(), attempts.client, attempts.lockout, db.get_pool()).await {
         Ok(clip) => {
-            hit_counter.hit(shortcode.clone(), 1);
-            let context = ctx::ViewClip::new(clip);
+            hit_counter.hit(clip.clip_id.clone(), viewer);
+            // only the creator gets to see the stats
+            let stats = action::get_clip_stats(clip.shortcode.clone(), requester, db.get_pool()).await.ok();
+            let context = ctx::ViewClip::new(clip, None, stats);
             render_with_status(Status::Ok, context, renderer)
         }
         Err(e) => match e {
@@ -107,6 +186,9 @@ pub async fn get_clip(
                 let context = ctx::PasswordRequired::new(shortcode);
                 render_with_status(Status::Unauthorized, context, renderer)
             }
+            ServiceError::AccessDenied(msg) => Err(PageError::Forbidden(msg)),
+            ServiceError::Share(e) => Err(PageError::Forbidden(e.to_string())),
+            ServiceError::Locked(e) => Err(PageError::TooManyRequests(e.to_string())),
             ServiceError::NotFound => Err(PageError::NotFound("Clip not found".to_owned())),
             _ => Err(PageError::Internal("server error".to_owned()))
         }
@@ -118,7 +200,11 @@ pub async fn submit_clip_password(
     cookies: &CookieJar<'_>,
     form: Form<Contextual<'_, form::GetPasswordProtectedClip>>,
     shortcode: ShortCode,
+    session: Option<Session>,
+    attempts: Attempts<'_>,
+    viewer: Viewer,
     hit_counter: &State<HitCounter>,
+    secure_cookies: &State<SecureCookies>,
     db: &State<Db>,
     renderer: &State<Renderer<'_>>,
 ) -> Result<RawHtml<String>, PageError> {
@@ -126,18 +212,20 @@ pub async fn submit_clip_password(
         let req = ask::GetClip {
             shortcode: shortcode.clone(),
             password: form.password.clone(),
+            share_link: None,
+            unlock_token: None,
         };
 
-        match action::get_clip(req, db.get_pool()).await {
-            Ok(clip) => {
-                hit_counter.hit(shortcode.clone(), 1);
-                let context = ctx::ViewClip::new(clip);
-
-                // adding cookie
-                cookies.add(Cookie::new(
-                    PASSWORD_COOKIE,
-                    form.password.clone().into_inner().unwrap_or_default(),
-                ));
+        let requester = requester(session);
+        match action::unlock_clip(req, requester.clone(), attempts.client, attempts.lockout, db.get_pool()).await {
+            Ok((clip, token)) => {
+                hit_counter.hit(clip.clip_id.clone(), viewer);
+                // the raw view and later visits are unlocked by the token, the password is never stored
+                if let Some(token) = token {
+                    cookies.add(unlock_cookie(&clip.shortcode, &token, *secure_cookies.inner()));
+                }
+                let stats = action::get_clip_stats(clip.shortcode.clone(), requester, db.get_pool()).await.ok();
+                let context = ctx::ViewClip::new(clip, None, stats);
                 Ok(RawHtml(renderer.render(context, &[])))
             }
             Err(e) => match e {
@@ -145,6 +233,8 @@ pub async fn submit_clip_password(
                     let context = ctx::PasswordRequired::new(shortcode);
                     Ok(RawHtml(renderer.render(context, &[e.as_str()])))
                 }
+                ServiceError::AccessDenied(msg) => Err(PageError::Forbidden(msg)),
+                ServiceError::Locked(e) => Err(PageError::TooManyRequests(e.to_string())),
                 ServiceError::NotFound => Err(PageError::NotFound("clip not found".to_owned())),
                 _ => Err(PageError::Internal("server error".to_owned()))
             }
@@ -155,60 +245,352 @@ pub async fn submit_clip_password(
     }
 }
 
-#[rocket::get("/clip/raw/<shortcode>")]
+#[rocket::get("/clip/raw/<shortcode>?<share..>")]
 pub async fn get_raw_clip(
     cookies: &CookieJar<'_>,
     shortcode: ShortCode,
+    share: Option<form::ShareLink>,
+    session: Option<Session>,
+    attempts: Attempts<'_>,
+    viewer: Viewer,
     hit_counter: &State<HitCounter>,
     db: &State<Db>,
 ) -> Result<status::Custom<String>, Status> {
-    use crate::domain::clip::field::Password;
+    // unlock cookies are set for the primary shortcode, aliases are looked up under it
+    let primary = match action::resolve_alias(&shortcode, db.get_pool()).await {
+        Ok(primary) => primary.unwrap_or_else(|| shortcode.clone()),
+        Err(ServiceError::NotFound) => return Err(Status::NotFound),
+        Err(_) => return Err(Status::InternalServerError),
+    };
     let req = ask::GetClip {
-        shortcode: shortcode.clone(),
-        password: cookies
-            .get(PASSWORD_COOKIE)
-            .map(|cookie| cookie.value())
-            .map(|raw_pwd| Password::new(raw_pwd.to_string()).ok())
-            .flatten() // Option(Option()) -> Option()
-            .unwrap_or_else(Password::default),
+        share_link: share.map(Into::into),
+        unlock_token: unlock_token(cookies, &primary),
+        ..shortcode.clone().into()
     };
 
-    match action::get_clip(req, db.get_pool()).await {
+    match action::get_clip(req, requester(session), attempts.client, attempts.lockout, db.get_pool()).await {
         Ok(clip) => {
-            hit_counter.hit(shortcode.clone(), 1);
+            hit_counter.hit(clip.clip_id.clone(), viewer);
             Ok(status::Custom(Status::Ok, clip.content.into_inner()))
         }
         Err(e) => match e {
             ServiceError::PermissionError(msg) => Ok(status::Custom(Status::Unauthorized, msg)),
+            ServiceError::AccessDenied(msg) => Ok(status::Custom(Status::Forbidden, msg)),
+            ServiceError::Share(e) => Ok(status::Custom(Status::Forbidden, e.to_string())),
+            ServiceError::Locked(e) => Ok(status::Custom(Status::TooManyRequests, e.to_string())),
             ServiceError::NotFound => Err(Status::NotFound),
             _ => Err(Status::InternalServerError)
         }
     }
 }
 
+async fn view_collection(
+    req: ask::GetCollection,
+    attempts: Attempts<'_>,
+    db: &Db,
+    renderer: &Renderer<'_>,
+) -> Result<status::Custom<RawHtml<String>>, PageError> {
+    let shortcode = req.shortcode.clone();
+    let tried = req.password.has_password();
+    let collection = match action::get_collection(req, attempts.client, attempts.lockout, db.get_pool()).await {
+        Ok(collection) => collection,
+        Err(ServiceError::PermissionError(msg)) => {
+            let context = ctx::ViewCollection::new(shortcode, None, vec![]);
+            let errors: &[&str] = if tried { &[msg.as_str()] } else { &[] };
+            return Ok(status::Custom(Status::Unauthorized, RawHtml(renderer.render(context, errors))));
+        }
+        Err(ServiceError::Locked(e)) => return Err(PageError::TooManyRequests(e.to_string())),
+        Err(ServiceError::NotFound) => return Err(PageError::NotFound("Collection not found".to_owned())),
+        Err(_) => return Err(PageError::Internal("server error".to_owned())),
+    };
+
+    match action::get_collection_members(&collection, db.get_pool()).await {
+        Ok(clips) => {
+            let context = ctx::ViewCollection::new(shortcode, Some(collection), clips);
+            Ok(status::Custom(Status::Ok, RawHtml(renderer.render(context, &[]))))
+        }
+        Err(_) => Err(PageError::Internal("server error".to_owned())),
+    }
+}
+
+#[rocket::get("/collection/<shortcode>")]
+pub async fn get_collection(
+    shortcode: ShortCode,
+    attempts: Attempts<'_>,
+    db: &State<Db>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<status::Custom<RawHtml<String>>, PageError> {
+    let req = ask::GetCollection { shortcode, password: Default::default() };
+    view_collection(req, attempts, db, renderer).await
+}
+
+#[rocket::post("/collection/<shortcode>", data = "<form>")]
+pub async fn submit_collection_password(
+    shortcode: ShortCode,
+    form: Form<Contextual<'_, form::GetPasswordProtectedClip>>,
+    attempts: Attempts<'_>,
+    db: &State<Db>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<status::Custom<RawHtml<String>>, PageError> {
+    let password = form.into_inner().value.map(|form| form.password).unwrap_or_default();
+    let req = ask::GetCollection { shortcode, password };
+    view_collection(req, attempts, db, renderer).await
+}
+
+fn password_login_disabled(renderer: &Renderer<'_>) -> (Status, RawHtml<String>) {
+    let msg = "Password logins are disabled, please log in with single sign-on.";
+    (Status::Forbidden, RawHtml(renderer.render(ctx::Login::new(true, false), &[msg])))
+}
+
+// accounts come from the identity provider only when password logins are disabled
+#[rocket::get("/register")]
+fn register_page(oidc: &State<Option<OidcConfig>>, renderer: &State<Renderer<'_>>) -> Result<RawHtml<String>, Status> {
+    if !oidc::password_login(oidc) {
+        return Err(Status::NotFound);
+    }
+    Ok(RawHtml(renderer.render(ctx::Register::default(), &[])))
+}
+
+#[rocket::post("/register", data = "<form>")]
+pub async fn register(
+    form: Form<Contextual<'_, form::Account>>,
+    cookies: &CookieJar<'_>,
+    oidc: &State<Option<OidcConfig>>,
+    db: &State<Db>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<Redirect, (Status, RawHtml<String>)> {
+    if !oidc::password_login(oidc) {
+        return Err(password_login_disabled(renderer));
+    }
+    let form = form.into_inner();
+    if let Some(value) = form.value {
+        let req = ask::NewUser {
+            username: value.username,
+            password: value.password,
+        };
+
+        let started = match action::new_user(req, db.get_pool()).await {
+            Ok(user) => Session::start(&user, cookies, db).await,
+            Err(e) => Err(e),
+        };
+        match started {
+            Ok(()) => Ok(Redirect::to(uri!(my_clips(tag = _)))),
+            Err(ServiceError::Conflict(msg)) => Err((
+                Status::Conflict,
+                RawHtml(renderer.render(ctx::Register::default(), &[msg.as_str()]))
+            )),
+            Err(e) => {
+                tracing::error!(error = ?e, "internal error");
+                Err((
+                    Status::InternalServerError,
+                    RawHtml(renderer.render(ctx::Register::default(), &["A server error occurred. Please try again."]))
+                ))
+            }
+        }
+    } else {
+        let errors = form_errors(&form.context);
+        Err((
+            Status::BadRequest,
+            RawHtml(renderer.render_with_data(ctx::Register::default(), ("account", &form.context), &errors))
+        ))
+    }
+}
+
+#[rocket::get("/login")]
+fn login_page(oidc: &State<Option<OidcConfig>>, renderer: &State<Renderer<'_>>) -> RawHtml<String> {
+    RawHtml(renderer.render(ctx::Login::new(oidc.is_some(), oidc::password_login(oidc)), &[]))
+}
+
+#[rocket::post("/login", data = "<form>")]
+pub async fn login(
+    form: Form<Contextual<'_, form::Login>>,
+    cookies: &CookieJar<'_>,
+    oidc: &State<Option<OidcConfig>>,
+    db: &State<Db>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<Redirect, (Status, RawHtml<String>)> {
+    let (sso, password_login) = (oidc.is_some(), oidc::password_login(oidc));
+    if !password_login {
+        return Err(password_login_disabled(renderer));
+    }
+    let form = form.into_inner();
+    if let Some(value) = form.value {
+        let req = ask::Login {
+            username: value.username,
+            password: value.password,
+        };
+
+        let started = match action::login(req, db.get_pool()).await {
+            Ok(user) => Session::start(&user, cookies, db).await,
+            Err(e) => Err(e),
+        };
+        match started {
+            Ok(()) => Ok(Redirect::to(uri!(my_clips(tag = _)))),
+            Err(ServiceError::PermissionError(msg)) => Err((
+                Status::Unauthorized,
+                RawHtml(renderer.render(ctx::Login::new(sso, password_login), &[msg.as_str()]))
+            )),
+            Err(e) => {
+                tracing::error!(error = ?e, "internal error");
+                Err((
+                    Status::InternalServerError,
+                    RawHtml(renderer.render(ctx::Login::new(sso, password_login), &["A server error occurred. Please try again."]))
+                ))
+            }
+        }
+    } else {
+        let errors = form_errors(&form.context);
+        Err((
+            Status::BadRequest,
+            RawHtml(renderer.render_with_data(ctx::Login::new(sso, password_login), ("account", &form.context), &errors))
+        ))
+    }
+}
+
+#[rocket::post("/logout")]
+async fn logout(cookies: &CookieJar<'_>, db: &State<Db>) -> Redirect {
+    Session::end(cookies, db).await;
+    Redirect::to(uri!(home))
+}
+
+async fn render_my_clips(
+    session: Session,
+    api_key: Option<String>,
+    share_link: Option<String>,
+    tag: Option<&str>,
+    db: &Db,
+    renderer: &Renderer<'_>,
+) -> Result<RawHtml<String>, PageError> {
+    let to_page_error = |e| match e {
+        ServiceError::NotFound => PageError::NotFound("user not found".to_owned()),
+        ServiceError::Clip(e) => PageError::BadRequest(e.to_string()),
+        _ => PageError::Internal("server error".to_owned())
+    };
+
+    let user = action::get_user(session.user_id.clone(), db.get_pool()).await.map_err(to_page_error)?;
+    let clips = action::get_user_clips(session.user_id, tag, db.get_pool()).await.map_err(to_page_error)?;
+    let tag = tag.map(str::to_owned);
+    Ok(RawHtml(renderer.render(ctx::MyClips::new(user, clips, api_key, share_link, tag), &[])))
+}
+
+#[rocket::get("/my/clips?<tag>")]
+pub async fn my_clips(
+    tag: Option<&str>,
+    session: Session,
+    db: &State<Db>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<RawHtml<String>, PageError> {
+    render_my_clips(session, None, None, tag, db, renderer).await
+}
+
+#[rocket::post("/my/keys")]
+pub async fn new_api_key(
+    session: Session,
+    db: &State<Db>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<RawHtml<String>, PageError> {
+    match action::new_api_key(Some(session.user_id.clone()), db.get_pool()).await {
+        Ok(api_key) => render_my_clips(session, Some(api_key.to_base64()), None, None, db, renderer).await,
+        Err(_) => Err(PageError::Internal("server error".to_owned()))
+    }
+}
+
+#[rocket::post("/my/clips/<shortcode>/share", data = "<form>")]
+pub async fn share_clip(
+    shortcode: ShortCode,
+    form: Form<form::ShareClip>,
+    session: Session,
+    db: &State<Db>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<RawHtml<String>, PageError> {
+    let req = ask::ShareClip {
+        shortcode: shortcode.clone(),
+        hours: form.hours,
+    };
+
+    match action::share_clip(req, ask::Identity::User(session.user_id.clone()), db.get_pool()).await {
+        Ok(link) => render_my_clips(session, None, Some(link.url(&shortcode)), None, db, renderer).await,
+        Err(ServiceError::AccessDenied(msg)) => Err(PageError::Forbidden(msg)),
+        Err(ServiceError::NotFound) => Err(PageError::NotFound("Clip not found".to_owned())),
+        Err(_) => Err(PageError::Internal("server error".to_owned()))
+    }
+}
+
+#[rocket::post("/my/clips/<shortcode>/share/revoke")]
+pub async fn revoke_share_links(
+    shortcode: ShortCode,
+    session: Session,
+    db: &State<Db>,
+) -> Result<Redirect, PageError> {
+    match action::revoke_share_links(shortcode, ask::Identity::User(session.user_id), db.get_pool()).await {
+        Ok(()) => Ok(Redirect::to(uri!(my_clips(tag = _)))),
+        Err(ServiceError::AccessDenied(msg)) => Err(PageError::Forbidden(msg)),
+        Err(ServiceError::NotFound) => Err(PageError::NotFound("Clip not found".to_owned())),
+        Err(_) => Err(PageError::Internal("server error".to_owned()))
+    }
+}
+
+// reached when the Session guard forwards, i.e. nobody is logged in
+#[rocket::get("/my/clips", rank = 2)]
+fn my_clips_login() -> Redirect {
+    Redirect::to(uri!(login_page))
+}
+
+#[rocket::post("/my/keys", rank = 2)]
+fn new_api_key_login() -> Redirect {
+    Redirect::to(uri!(login_page))
+}
+
+#[rocket::post("/my/clips/<_>/share", rank = 2)]
+fn share_clip_login() -> Redirect {
+    Redirect::to(uri!(login_page))
+}
+
+#[rocket::post("/my/clips/<_>/share/revoke", rank = 2)]
+fn revoke_share_links_login() -> Redirect {
+    Redirect::to(uri!(login_page))
+}
+
 pub fn routes() -> Vec<rocket::Route> {
     rocket::routes![
         home,
         get_clip,
         new_clip,
         submit_clip_password,
-        get_raw_clip
+        get_raw_clip,
+        get_collection,
+        submit_collection_password,
+        register_page,
+        register,
+        login_page,
+        login,
+        logout,
+        my_clips,
+        my_clips_login,
+        new_api_key,
+        new_api_key_login,
+        share_clip,
+        share_clip_login,
+        revoke_share_links,
+        revoke_share_links_login
     ]
 }
 
 pub mod catcher {
+    use crate::web::logging::RequestId;
+    use rocket::http::Status;
     use rocket::Request;
     use rocket::{catch, catchers, Catcher};
 
     #[catch(default)]
-    fn default(req: &Request) -> &'static str {
-        eprintln!("General error: {:?}", req);
+    fn default(status: Status, req: &Request) -> &'static str {
+        tracing::warn!(request_id = %RequestId::of(req), method = %req.method(), path = %req.uri().path(), status = status.code, "unhandled error");
         "Something went wrong"
     }
 
     #[catch(500)]
     fn internal_error(req: &Request) -> &'static str {
-        eprintln!("Internal error: {:?}", req);
+        tracing::error!(request_id = %RequestId::of(req), method = %req.method(), path = %req.uri().path(), "internal error");
         "internal server error"
     }
 
@@ -226,9 +608,8 @@ pub mod catcher {
 pub mod test {
     use crate::data::Db;
     use crate::test::async_runtime;
-    use crate::web::test::client;
+    use crate::web::test::{client, client_with};
     use rocket::http::Status;
-    use serde_json::Value::String;
 
     #[test]
     fn gets_home() {
@@ -238,16 +619,21 @@ pub mod test {
     }
 
     #[test]
+    #[allow(non_snake_case)]
     fn not_found__error_on_unknown_clip_shortcode() {
         let client = client();
         let res = client.get("/clip/foo").dispatch();
         assert_eq!(res.status(), Status::NotFound);
+
+        // malformed shortcodes never reach the database
+        let res = client.get("/clip/raw/not%20a%20shortcode").dispatch();
+        assert_eq!(res.status(), Status::NotFound);
     }
 
     #[test]
     fn requires_pwd_if_defined() {
-        use crate::domain::clip::field::{Content, Expires, Password, Title};
-        use crate::service;
+        use crate::data::test::new_clip_with;
+        use crate::domain::clip::field::Password;
         use rocket::http::{ContentType, Cookie};
 
         let rt = async_runtime();
@@ -255,15 +641,8 @@ pub mod test {
         let client = client();
         let db = client.rocket().state::<Db>().unwrap();
 
-        let req = service::ask::NewClip {
-            content: Content::new("content").unwrap(),
-            expires: Expires::default(),
-            password: Password::new("123".to_owned()).unwrap(),
-            title: Title::default(),
-        };
-        let clip = rt
-            .block_on(async move { service::action::new_clip(req, db.get_pool()).await })
-            .unwrap();
+        let new_clip = || rt.block_on(new_clip_with(db.get_pool(), |req| req.password = Password::new("tulip-orbit-9".to_owned()).unwrap()));
+        let clip = new_clip();
         let response = client
             .get(format!("/clip/{}", clip.shortcode.as_str()))
             .dispatch();
@@ -280,22 +659,180 @@ pub mod test {
         let response = client
             .post(format!("/clip/{}", clip.shortcode.as_str()))
             .header(ContentType::Form)
-            .body("password=123")
+            .body("password=tulip-orbit-9")
             .dispatch();
         assert_eq!(response.status(), Status::Ok);
 
+        // the unlock cookie set above opens the raw view, also under an alias
         let response = client
             .get(format!("/clip/raw/{}", clip.shortcode.as_str()))
-            .cookie(Cookie::new("password", "123"))
             .dispatch();
         assert_eq!(response.status(), Status::Ok);
 
-        // Get clip when the password is provided, but incorrect
+        {
+            use crate::data::repository::ClipRepository;
+            let alias = crate::ShortCode::from("tulip-alias");
+            rt.block_on(db.get_pool().add_alias(&clip.clip_id, &alias)).unwrap();
+        }
+        let response = client.get("/clip/raw/tulip-alias").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // but not another clip with the same password, nor does a forged token or the bare password
+        let other = new_clip();
         let response = client
-            .get(format!("/clip/raw/{}", clip.shortcode.as_str()))
-            .cookie(Cookie::new("password", "abc"))
+            .get(format!("/clip/raw/{}", other.shortcode.as_str()))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .get(format!("/clip/raw/{}", other.shortcode.as_str()))
+            .cookie(Cookie::new(format!("unlock_{}", other.shortcode.as_str()), "4102444800.forged"))
+            .cookie(Cookie::new("password", "tulip-orbit-9"))
             .dispatch();
         assert_eq!(response.status(), Status::Unauthorized);
     }
-}
 
+    #[test]
+    fn marks_unlock_cookie_secure_over_https() {
+        use crate::data::test::new_clip_with;
+        use crate::domain::clip::field::Password;
+        use crate::web::SecureCookies;
+        use rocket::http::ContentType;
+
+        for https in [false, true] {
+            let client = client_with(|config| config.secure_cookies = SecureCookies(https));
+            let db = client.rocket().state::<Db>().unwrap();
+            let clip = async_runtime()
+                .block_on(new_clip_with(db.get_pool(), |req| req.password = Password::new("tulip-orbit-9".to_owned()).unwrap()));
+
+            let response = client
+                .post(format!("/clip/{}", clip.shortcode.as_str()))
+                .header(ContentType::Form)
+                .body("password=tulip-orbit-9")
+                .dispatch();
+            let cookie = response.cookies().get(&format!("unlock_{}", clip.shortcode.as_str())).unwrap();
+            assert_eq!(cookie.secure().unwrap_or(false), https);
+        }
+    }
+
+    #[test]
+    fn registers_and_lists_own_clips() {
+        use rocket::http::ContentType;
+
+        let client = client();
+
+        // anonymous users are sent to the login page
+        let response = client.get("/my/clips").dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let response = client
+            .post("/register")
+            .header(ContentType::Form)
+            .body("username=alice&password=correct-horse")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let response = client
+            .post("/")
+            .header(ContentType::Form)
+            .body("content=mine&title=&expires=&password=")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let response = client.get("/my/clips").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().contains("alice"));
+
+        // the username is taken
+        let response = client
+            .post("/register")
+            .header(ContentType::Form)
+            .body("username=alice&password=another-password")
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        // logging out ends the session on the server, a copy of the cookie is no good afterwards
+        let session = client.cookies().get_private("session").unwrap();
+        client.post("/logout").dispatch();
+        let response = client.get("/my/clips").dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let response = client.get("/my/clips").private_cookie(session).dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        // too short to register with is just as wrong
+        for password in ["wrong-password", "short"] {
+            let response = client
+                .post("/login")
+                .header(ContentType::Form)
+                .body(format!("username=alice&password={}", password))
+                .dispatch();
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+
+        let response = client
+            .post("/login")
+            .header(ContentType::Form)
+            .body("username=alice&password=correct-horse")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let session = response.cookies().get("session").unwrap();
+        assert_eq!(session.max_age(), Some(rocket::time::Duration::days(7)));
+        assert_eq!(client.get("/my/clips").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn throttles_and_burns_on_wrong_passwords() {
+        use crate::data::test::new_clip_with;
+        use crate::domain::clip::field::{BurnAfter, Password};
+        use rocket::http::ContentType;
+
+        let rt = async_runtime();
+        let client = client();
+        let db = client.rocket().state::<Db>().unwrap();
+
+        let new_clip = |burn_after: Option<u32>| rt.block_on(new_clip_with(db.get_pool(), |req| {
+            req.password = Password::new("tulip-orbit-9".to_owned()).unwrap();
+            req.burn_after = BurnAfter::new(burn_after).unwrap();
+        }));
+        let guess = |shortcode: &str, password: &str| client
+            .post(format!("/clip/{}", shortcode))
+            .header(ContentType::Form)
+            .body(format!("password={}", password))
+            .dispatch()
+            .status();
+
+        let clip = new_clip(Some(2));
+        assert_eq!(guess(clip.shortcode.as_str(), "wrong"), Status::Ok);
+        assert_eq!(guess(clip.shortcode.as_str(), "wrong"), Status::NotFound);
+        assert_eq!(guess(clip.shortcode.as_str(), "tulip-orbit-9"), Status::NotFound);
+
+        // the default policy allows three free guesses
+        let clip = new_clip(None);
+        for _ in 0..4 {
+            assert_eq!(guess(clip.shortcode.as_str(), "wrong"), Status::Ok);
+        }
+        assert_eq!(guess(clip.shortcode.as_str(), "tulip-orbit-9"), Status::TooManyRequests);
+    }
+
+    #[test]
+    fn rejects_weak_and_generates_passwords() {
+        use rocket::http::ContentType;
+
+        let client = client();
+        let post = |body: &str| client
+            .post("/")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+
+        let response = post("content=hello&title=&expires=&password=password");
+        assert_eq!(response.status(), Status::BadRequest);
+        // the submitted content is kept so it can be fixed up
+        assert!(response.into_string().unwrap().contains("hello"));
+
+        // the generated passphrase is shown once instead of redirecting
+        let response = post("content=hello&title=&expires=&password=&generate_password=on");
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().contains("Generated Passphrase"));
+    }
+}
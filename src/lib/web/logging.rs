@@ -0,0 +1,135 @@
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request, Response};
+use std::convert::Infallible;
+use std::fmt;
+use std::time::Instant;
+use strum::EnumString;
+use tracing_subscriber::EnvFilter;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+#[derive(Clone, Copy, Debug, EnumString)]
+#[strum(serialize_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// `filter` takes the RUST_LOG syntax, e.g. `info` or `clipstash=debug,sqlx=warn`
+pub fn init(filter: &str, format: LogFormat) {
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(filter));
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
+
+// identifies a request in the logs and, through the X-Request-Id header, to the client.
+// an id sent by a proxy in front of clipstash is kept if it looks sane
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    const MAX_LENGTH: usize = 64;
+
+    fn new() -> Self {
+        Self(uuid::Uuid::new_v4().to_simple().to_string())
+    }
+
+    fn from_header(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= Self::MAX_LENGTH
+            && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        Some(Self(value.to_owned())).filter(|_| valid)
+    }
+
+    // the id of a request, also outside of handlers such as in catchers
+    pub fn of(request: &Request<'_>) -> Self {
+        request.local_cache(|| RequestStart::new(request)).id.clone()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::of(request))
+    }
+}
+
+struct RequestStart {
+    id: RequestId,
+    started: Instant,
+}
+
+impl RequestStart {
+    fn new(request: &Request<'_>) -> Self {
+        let id = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(RequestId::new);
+        Self { id, started: Instant::now() }
+    }
+}
+
+// logs every request once it is answered. only the path is logged:
+// query strings carry share links and headers carry passwords, API keys and sessions
+pub struct RequestLogger;
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info { name: "Request logger", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let start = RequestStart::new(request);
+        request.local_cache(|| start);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let start = request.local_cache(|| RequestStart::new(request));
+        response.set_header(Header::new(REQUEST_ID_HEADER, start.id.to_string()));
+
+        let status = response.status().code;
+        let elapsed_ms = start.started.elapsed().as_secs_f64() * 1000.0;
+        let path = request.uri().path();
+        if status >= 500 {
+            tracing::error!(request_id = %start.id, method = %request.method(), %path, status, elapsed_ms, "request failed");
+        } else {
+            tracing::info!(request_id = %start.id, method = %request.method(), %path, status, elapsed_ms, "request");
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::{RequestId, REQUEST_ID_HEADER};
+    use crate::web::test::client;
+    use rocket::http::Header;
+
+    #[test]
+    fn tags_responses_with_request_ids() {
+        let client = client();
+        let response = client.get("/").dispatch();
+        let generated = response.headers().get_one(REQUEST_ID_HEADER).unwrap();
+        assert_eq!(generated.len(), 32);
+
+        let response = client.get("/").header(Header::new(REQUEST_ID_HEADER, "edge-42")).dispatch();
+        assert_eq!(response.headers().get_one(REQUEST_ID_HEADER), Some("edge-42"));
+        let response = client.get("/").header(Header::new(REQUEST_ID_HEADER, "not\tsane")).dispatch();
+        assert_ne!(response.headers().get_one(REQUEST_ID_HEADER), Some("not\tsane"));
+
+        assert!(RequestId::from_header(&"x".repeat(RequestId::MAX_LENGTH + 1)).is_none());
+    }
+}
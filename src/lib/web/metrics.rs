@@ -0,0 +1,145 @@
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::{Data, Request, Response, State};
+use std::time::Instant;
+
+use crate::data::Db;
+
+// everything exported at /metrics. clones share the same series
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub requests: IntCounterVec,
+    pub request_duration: HistogramVec,
+    pub clips_created: IntCounter,
+    pub clips_served: IntCounter,
+    pub clips_expired: IntCounter,
+    pub maintenance_runs: IntCounter,
+    pub hit_queue_depth: IntGauge,
+    pub hit_flush_duration: Histogram,
+    pub pool_connections: IntGauge,
+    pub pool_idle_connections: IntGauge,
+    pub api_key_failures: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new_custom(Some("clipstash".to_owned()), None).expect("valid metrics prefix");
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route and status"),
+                &["method", "route", "status"],
+            ).unwrap(),
+            request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "time spent handling HTTP requests"),
+                &["method", "route"],
+            ).unwrap(),
+            clips_created: IntCounter::new("clips_created_total", "clips created").unwrap(),
+            clips_served: IntCounter::new("clips_served_total", "clips viewed, through pages, raw views or the API").unwrap(),
+            clips_expired: IntCounter::new("clips_expired_total", "expired clips deleted by maintenance").unwrap(),
+            maintenance_runs: IntCounter::new("maintenance_runs_total", "maintenance passes over the database").unwrap(),
+            hit_queue_depth: IntGauge::new("hit_counter_queue_depth", "hits sent to the hit counter and not yet buffered").unwrap(),
+            hit_flush_duration: Histogram::with_opts(HistogramOpts::new(
+                "hit_counter_flush_duration_seconds",
+                "time spent committing buffered hits",
+            )).unwrap(),
+            pool_connections: IntGauge::new("db_pool_connections", "open database connections").unwrap(),
+            pool_idle_connections: IntGauge::new("db_pool_idle_connections", "open database connections not in use").unwrap(),
+            api_key_failures: IntCounter::new("api_key_failures_total", "API requests with a missing, malformed or unknown API key").unwrap(),
+            registry,
+        };
+        metrics.register();
+        metrics
+    }
+}
+
+impl Metrics {
+    fn register(&self) {
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(self.requests.clone()),
+            Box::new(self.request_duration.clone()),
+            Box::new(self.clips_created.clone()),
+            Box::new(self.clips_served.clone()),
+            Box::new(self.clips_expired.clone()),
+            Box::new(self.maintenance_runs.clone()),
+            Box::new(self.hit_queue_depth.clone()),
+            Box::new(self.hit_flush_duration.clone()),
+            Box::new(self.pool_connections.clone()),
+            Box::new(self.pool_idle_connections.clone()),
+            Box::new(self.api_key_failures.clone()),
+        ];
+        for collector in collectors {
+            self.registry.register(collector).expect("metrics are registered once");
+        }
+    }
+
+    // Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics encode to text");
+        String::from_utf8(buffer).expect("metrics are UTF-8")
+    }
+}
+
+// when the request started, cached on the request
+struct RequestStart(Instant);
+
+#[rocket::async_trait]
+impl Fairing for Metrics {
+    fn info(&self) -> Info {
+        Info { name: "Request metrics", kind: Kind::Request | Kind::Response }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let started = request.local_cache(|| RequestStart(Instant::now()));
+        // label by route pattern rather than path, shortcodes would make series unbounded
+        let route = request.route().map(|route| route.uri.to_string()).unwrap_or_else(|| "unmatched".to_owned());
+        let method = request.method().as_str();
+        self.requests
+            .with_label_values(&[method, route.as_str(), response.status().code.to_string().as_str()])
+            .inc();
+        self.request_duration
+            .with_label_values(&[method, route.as_str()])
+            .observe(started.0.elapsed().as_secs_f64());
+    }
+}
+
+#[rocket::get("/metrics")]
+pub fn metrics(metrics: &State<Metrics>, db: &State<Db>) -> (ContentType, String) {
+    let pool = db.get_pool();
+    metrics.pool_connections.set(i64::from(pool.size()));
+    metrics.pool_idle_connections.set(pool.num_idle() as i64);
+    (ContentType::new("text", "plain").with_params(("version", "0.0.4")), metrics.render())
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes!(metrics)
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::web::test::client;
+    use rocket::http::{Header, Status};
+
+    #[test]
+    fn exports_prometheus_metrics() {
+        let client = client();
+        assert_eq!(client.get("/").dispatch().status(), Status::Ok);
+        assert_eq!(client.get("/api/clip").header(Header::new("X-Api-Key", "not base64!")).dispatch().status(), Status::BadRequest);
+
+        let response = client.get("/metrics").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let metrics = response.into_string().unwrap();
+        assert!(metrics.contains(r#"clipstash_http_requests_total{method="GET",route="/",status="200"} 1"#));
+        assert!(metrics.contains("clipstash_api_key_failures_total 1"));
+        assert!(metrics.contains("clipstash_db_pool_connections"));
+        assert!(metrics.contains("clipstash_hit_counter_queue_depth 0"));
+    }
+}
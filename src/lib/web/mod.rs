@@ -1,16 +1,59 @@
 use handlebars::RenderError;
+use rocket::http::{Cookie, CookieJar, SameSite};
+use rocket::time::OffsetDateTime;
+use std::str::FromStr;
+
+use crate::domain::share::UnlockToken;
+use crate::ShortCode;
 
 pub mod ctx;
 pub mod renderer;
+// the FromForm derive still emits the removed `private_in_public` lint,
+// UriDisplayQuery borrows fields it could pass by value
+#[allow(renamed_and_removed_lints, clippy::needless_borrows_for_generic_args)]
 pub mod form;
 pub mod http;
 pub mod hit_counter;
+pub mod session;
+pub mod attempts;
+pub mod viewer;
+pub mod metrics;
+pub mod logging;
+pub mod health;
+pub mod oidc;
 
 pub use hit_counter::HitCounter;
+pub use metrics::Metrics;
+pub use logging::RequestLogger;
 
 pub mod api;
 
-pub const PASSWORD_COOKIE: &str = "password";
+pub const SESSION_COOKIE: &str = "session";
+pub const OIDC_STATE_COOKIE: &str = "oidc_state";
+// followed by the shortcode: every clip gets its own unlock cookie
+pub const UNLOCK_COOKIE_PREFIX: &str = "unlock_";
+
+// set when clients reach the server over https, e.g. through a proxy terminating TLS:
+// browsers then never send the cookies over plain http
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SecureCookies(pub bool);
+
+pub fn unlock_cookie(shortcode: &ShortCode, token: &UnlockToken, secure: SecureCookies) -> Cookie<'static> {
+    Cookie::build(format!("{}{}", UNLOCK_COOKIE_PREFIX, shortcode.as_str()), token.to_string())
+        .path("/")
+        .http_only(true)
+        .secure(secure.0)
+        .same_site(SameSite::Lax)
+        .expires(OffsetDateTime::from_unix_timestamp(token.expires().timestamp()).ok())
+        .finish()
+}
+
+// token a browser got when entering the password of this clip
+pub fn unlock_token(cookies: &CookieJar<'_>, shortcode: &ShortCode) -> Option<UnlockToken> {
+    cookies
+        .get(&format!("{}{}", UNLOCK_COOKIE_PREFIX, shortcode.as_str()))
+        .and_then(|cookie| UnlockToken::from_str(cookie.value()).ok())
+}
 
 #[derive(rocket::Responder)]
 pub enum PageError {
@@ -18,8 +61,14 @@ pub enum PageError {
     Serialization(String),
     #[response(status = 500)]
     Render(String),
+    #[response(status = 400)]
+    BadRequest(String),
+    #[response(status = 403)]
+    Forbidden(String),
     #[response(status = 404)]
     NotFound(String),
+    #[response(status = 429)]
+    TooManyRequests(String),
     #[response(status = 500)]
     Internal(String),
 }
@@ -40,30 +89,67 @@ impl From<serde_json::Error> for PageError {
 #[cfg(test)]
 pub mod test {
     use crate::test::async_runtime;
-    use crate::{Renderer, RocketConfig};
+    use crate::RocketConfig;
     use rocket::local::blocking::Client;
+    use std::ops::Deref;
+    use tokio::runtime::Runtime;
 
-    pub fn config() -> RocketConfig {
+    // the in memory DB and the background tasks of the config live on `rt`,
+    // which has to outlive the client
+    pub fn config(rt: &Runtime) -> RocketConfig {
         use crate::web::{hit_counter::HitCounter, renderer::Renderer};
 
-        let rt = async_runtime();
         let renderer = Renderer::new("templates/".into());
         let db = crate::data::test::new_db(rt.handle());
+        let metrics = crate::web::Metrics::default();
+        let lockout = crate::domain::lockout::Lockout::default();
         let maintenance = crate::domain::maintenance::Maintenance::spawn(
             db.get_pool().clone(),
-            rt.handle().clone());
-        let hit_counter = HitCounter::new(db.get_pool().clone(), rt.handle().clone());
+            rt.handle().clone(),
+            metrics.clone(),
+            lockout.clone());
+        let hit_counter = HitCounter::new(db.get_pool().clone(), rt.handle().clone(), metrics.clone());
 
         RocketConfig {
             renderer,
             db,
             hit_counter,
-            maintenance
+            lockout,
+            maintenance,
+            quota: Default::default(),
+            password_policy: Default::default(),
+            shortcodes: Default::default(),
+            oidc: None,
+            secure_cookies: Default::default(),
+            metrics,
         }
     }
 
-    pub fn client() -> Client {
-        let config = config();
-        Client::tracked(crate::rocket(config)).expect("failed to build rocket instance")
+    // a client along with the runtime of its config. fields drop in order:
+    // the client shuts down before the runtime does
+    pub struct TestClient {
+        client: Client,
+        _rt: Runtime,
     }
-}
\ No newline at end of file
+
+    impl Deref for TestClient {
+        type Target = Client;
+
+        fn deref(&self) -> &Client {
+            &self.client
+        }
+    }
+
+    pub fn client() -> TestClient {
+        client_with(|_| ())
+    }
+
+    // a client for the test config as changed by `configure`
+    pub fn client_with<F: FnOnce(&mut RocketConfig)>(configure: F) -> TestClient {
+        let rt = async_runtime();
+        let mut config = config(&rt);
+        configure(&mut config);
+        let client = Client::tracked(crate::rocket(config)).expect("failed to build rocket instance");
+        TestClient { client, _rt: rt }
+    }
+}
@@ -0,0 +1,433 @@
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::response::content::RawHtml;
+use rocket::response::Redirect;
+use rocket::State;
+use serde::Deserialize;
+
+use crate::data::Db;
+use crate::service::{action, ask};
+use crate::web::{ctx, renderer::Renderer, session::Session, OIDC_STATE_COOKIE};
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcError {
+    #[error("single sign-on is not configured")]
+    NotConfigured,
+    #[error("identity provider request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("login state does not match, please try again")]
+    State,
+    #[error("invalid id token: {0}")]
+    Token(String),
+    #[error("email domain is not allowed")]
+    Domain,
+    #[error("identity provider URL must use https: {0}")]
+    Insecure(String),
+}
+
+// OpenID Connect provider clipstash delegates logins to
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    // must point to /login/oidc/callback and be registered with the provider
+    pub redirect_url: String,
+    // only accept users whose email belongs to this domain
+    pub email_domain: Option<String>,
+    // whether local accounts may still register and log in with a password
+    pub password_login: bool,
+}
+
+// subset of the discovery document we rely on
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::One(aud) => aud == client_id,
+            Audience::Many(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    preferred_username: Option<String>,
+}
+
+// id token signatures are not checked, which is only sound over TLS.
+// plain http is accepted for a provider on the loopback interface, as used in development
+pub fn check_secure(url: &str) -> Result<(), OidcError> {
+    use std::net::IpAddr;
+
+    let url = reqwest::Url::parse(url).map_err(|e| OidcError::Insecure(e.to_string()))?;
+    let loopback = match url.host_str() {
+        Some("localhost") => true,
+        // IPv6 hosts come in brackets
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback()),
+        None => false,
+    };
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if loopback => Ok(()),
+        _ => Err(OidcError::Insecure(url.to_string())),
+    }
+}
+
+// without password logins, every account comes from the identity provider
+pub fn password_login(oidc: &Option<OidcConfig>) -> bool {
+    oidc.as_ref().is_none_or(|config| config.password_login)
+}
+
+impl OidcConfig {
+    async fn metadata(&self) -> Result<ProviderMetadata, OidcError> {
+        check_secure(&self.issuer)?;
+        let url = format!("{}/.well-known/openid-configuration", self.issuer.trim_end_matches('/'));
+        let metadata: ProviderMetadata = reqwest::get(url).await?.error_for_status()?.json().await?;
+        check_secure(&metadata.token_endpoint)?;
+        Ok(metadata)
+    }
+
+    async fn exchange_code(&self, metadata: &ProviderMetadata, code: &str) -> Result<TokenResponse, OidcError> {
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.redirect_url.as_str()),
+            ("client_id", self.client_id.as_str()),
+        ];
+        if let Some(secret) = &self.client_secret {
+            params.push(("client_secret", secret.as_str()));
+        }
+
+        Ok(reqwest::Client::new()
+            .post(metadata.token_endpoint.as_str())
+            .form(&params)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    // the id token comes straight from the token endpoint, so per OpenID Connect Core 3.1.3.7
+    // the TLS connection authenticates the issuer and the signature does not need to be checked
+    fn validate(&self, metadata: &ProviderMetadata, id_token: &str, nonce: &str) -> Result<ask::OidcLogin, OidcError> {
+        let payload = id_token
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| OidcError::Token("malformed token".to_owned()))?;
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD)
+            .map_err(|e| OidcError::Token(e.to_string()))?;
+        let claims: Claims = serde_json::from_slice(&payload)
+            .map_err(|e| OidcError::Token(e.to_string()))?;
+
+        if claims.iss != metadata.issuer {
+            return Err(OidcError::Token("unexpected issuer".to_owned()));
+        }
+        if !claims.aud.contains(&self.client_id) {
+            return Err(OidcError::Token("unexpected audience".to_owned()));
+        }
+        if claims.exp < chrono::Utc::now().timestamp() {
+            return Err(OidcError::Token("token expired".to_owned()));
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OidcError::Token("nonce does not match".to_owned()));
+        }
+
+        if let Some(domain) = &self.email_domain {
+            // an email the provider does not vouch for could be anyone's
+            let verified = claims.email_verified.unwrap_or(false);
+            match &claims.email {
+                Some(email) if verified && email.to_lowercase().ends_with(&format!("@{}", domain.to_lowercase())) => (),
+                _ => return Err(OidcError::Domain),
+            }
+        }
+
+        let username_hint = claims
+            .preferred_username
+            .or_else(|| claims.email.and_then(|email| email.split('@').next().map(str::to_owned)))
+            .unwrap_or_else(|| claims.sub.clone());
+
+        Ok(ask::OidcLogin {
+            issuer: claims.iss,
+            subject: claims.sub,
+            username_hint,
+        })
+    }
+}
+
+fn random_token() -> String {
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+
+    rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect()
+}
+
+fn login_error(config: &OidcConfig, status: Status, renderer: &Renderer<'_>, msg: &str) -> (Status, RawHtml<String>) {
+    (status, RawHtml(renderer.render(ctx::Login::new(true, config.password_login), &[msg])))
+}
+
+#[rocket::get("/login/oidc")]
+pub async fn login(
+    oidc: &State<Option<OidcConfig>>,
+    cookies: &CookieJar<'_>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<Redirect, (Status, RawHtml<String>)> {
+    let config = oidc.as_ref().ok_or((Status::NotFound, RawHtml(OidcError::NotConfigured.to_string())))?;
+    let metadata = config.metadata().await.map_err(|e| {
+        tracing::error!(error = ?e, "oidc discovery failed");
+        login_error(config, Status::BadGateway, renderer, "The identity provider is unavailable.")
+    })?;
+
+    let (state, nonce) = (random_token(), random_token());
+    let url = reqwest::Url::parse_with_params(
+        metadata.authorization_endpoint.as_str(),
+        &[
+            ("response_type", "code"),
+            ("scope", "openid email profile"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", config.redirect_url.as_str()),
+            ("state", state.as_str()),
+            ("nonce", nonce.as_str()),
+        ],
+    ).map_err(|e| {
+        tracing::error!(error = ?e, "invalid oidc authorization endpoint");
+        login_error(config, Status::BadGateway, renderer, "The identity provider is misconfigured.")
+    })?;
+
+    // the provider redirects back cross-site: a strict cookie would not be sent along
+    let mut cookie = Cookie::new(OIDC_STATE_COOKIE, format!("{} {}", state, nonce));
+    cookie.set_same_site(rocket::http::SameSite::Lax);
+    cookies.add_private(cookie);
+    Ok(Redirect::to(url.to_string()))
+}
+
+#[rocket::get("/login/oidc/callback?<code>&<state>")]
+pub async fn callback(
+    code: &str,
+    state: &str,
+    oidc: &State<Option<OidcConfig>>,
+    cookies: &CookieJar<'_>,
+    db: &State<Db>,
+    renderer: &State<Renderer<'_>>,
+) -> Result<Redirect, (Status, RawHtml<String>)> {
+    let config = oidc.as_ref().ok_or((Status::NotFound, RawHtml(OidcError::NotConfigured.to_string())))?;
+
+    let expected = cookies.get_private(OIDC_STATE_COOKIE).map(|cookie| cookie.value().to_owned());
+    cookies.remove_private(Cookie::named(OIDC_STATE_COOKIE));
+    let nonce = match expected.as_deref().and_then(|value| value.split_once(' ')) {
+        Some((expected_state, nonce)) if expected_state == state => nonce.to_owned(),
+        _ => return Err(login_error(config, Status::BadRequest, renderer, &OidcError::State.to_string())),
+    };
+
+    let identity = async {
+        let metadata = config.metadata().await?;
+        let token = config.exchange_code(&metadata, code).await?;
+        config.validate(&metadata, &token.id_token, &nonce)
+    }.await;
+
+    let identity = match identity {
+        Ok(identity) => identity,
+        Err(e @ OidcError::Domain) => return Err(login_error(config, Status::Forbidden, renderer, &e.to_string())),
+        Err(e) => {
+            tracing::warn!(error = ?e, "oidc login failed");
+            return Err(login_error(config, Status::Unauthorized, renderer, "Single sign-on failed, please try again."));
+        }
+    };
+
+    let started = match action::oidc_login(identity, db.get_pool()).await {
+        Ok(user) => Session::start(&user, cookies, db).await,
+        Err(e) => Err(e),
+    };
+    match started {
+        Ok(()) => Ok(Redirect::to(rocket::uri!(crate::web::http::my_clips(tag = _)))),
+        Err(e) => {
+            tracing::error!(error = ?e, "internal error");
+            Err(login_error(config, Status::InternalServerError, renderer, "A server error occurred. Please try again."))
+        }
+    }
+}
+
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![login, callback]
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::web::oidc::{check_secure, OidcConfig, OidcError, ProviderMetadata};
+    use crate::web::test::client_with;
+    use httpmock::prelude::*;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+
+    fn claims(issuer: &str, nonce: &str, email: &str) -> serde_json::Value {
+        serde_json::json!({
+            "iss": issuer,
+            "aud": "clipstash",
+            "exp": chrono::Utc::now().timestamp() + 60,
+            "sub": "subject-1",
+            "nonce": nonce,
+            "email": email,
+            "email_verified": true,
+        })
+    }
+
+    // unsigned id token as issued by the mock provider
+    fn id_token(claims: serde_json::Value) -> String {
+        format!(
+            "{}.{}.",
+            base64::encode_config(r#"{"alg":"none"}"#, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(claims.to_string(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    // follows the redirect to the provider and back, returning the callback status
+    fn log_in(client: &Client, provider: &MockServer, email: &str) -> Status {
+        let response = client.get("/login/oidc").dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+        let location = reqwest::Url::parse(response.headers().get_one("Location").unwrap()).unwrap();
+        let param = |name: &str| location
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .unwrap();
+        let (state, nonce) = (param("state"), param("nonce"));
+
+        let mut token = provider.mock(|when, then| {
+            when.method(POST).path("/token").body_contains("code=the-code");
+            then.status(200).json_body(serde_json::json!({
+                "access_token": "access",
+                "token_type": "Bearer",
+                "id_token": id_token(claims(&provider.base_url(), &nonce, email)),
+            }));
+        });
+        let status = client
+            .get(format!("/login/oidc/callback?code=the-code&state={}", state))
+            .dispatch()
+            .status();
+        token.delete();
+        status
+    }
+
+    #[test]
+    fn logs_in_through_mock_provider() {
+        let provider = MockServer::start();
+        provider.mock(|when, then| {
+            when.method(GET).path("/.well-known/openid-configuration");
+            then.status(200).json_body(serde_json::json!({
+                "issuer": provider.base_url(),
+                "authorization_endpoint": provider.url("/authorize"),
+                "token_endpoint": provider.url("/token"),
+            }));
+        });
+
+        let client = client_with(|config| {
+            config.oidc = Some(OidcConfig {
+                issuer: provider.base_url(),
+                client_id: "clipstash".to_owned(),
+                client_secret: Some("secret".to_owned()),
+                redirect_url: "http://localhost:8000/login/oidc/callback".to_owned(),
+                email_domain: Some("example.com".to_owned()),
+                password_login: true,
+            });
+        });
+
+        assert_eq!(log_in(&client, &provider, "mallory@elsewhere.org"), Status::Forbidden);
+        assert_eq!(client.get("/my/clips").dispatch().status(), Status::SeeOther);
+
+        assert_eq!(log_in(&client, &provider, "alice@example.com"), Status::SeeOther);
+        let response = client.get("/my/clips").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().unwrap().contains("alice"));
+
+        // a forged state is rejected
+        let response = client.get("/login/oidc/callback?code=the-code&state=forged").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    fn config(issuer: &str, password_login: bool) -> OidcConfig {
+        OidcConfig {
+            issuer: issuer.to_owned(),
+            client_id: "clipstash".to_owned(),
+            client_secret: None,
+            redirect_url: "http://localhost:8000/login/oidc/callback".to_owned(),
+            email_domain: Some("example.com".to_owned()),
+            password_login,
+        }
+    }
+
+    #[test]
+    fn email_without_verified_claim_is_not_trusted() {
+        let issuer = "https://idp.example.com";
+        let (config, metadata) = (config(issuer, true), ProviderMetadata {
+            issuer: issuer.to_owned(),
+            authorization_endpoint: format!("{}/authorize", issuer),
+            token_endpoint: format!("{}/token", issuer),
+        });
+
+        let verified = id_token(claims(issuer, "nonce", "alice@example.com"));
+        assert!(config.validate(&metadata, &verified, "nonce").is_ok());
+
+        let mut unverified = claims(issuer, "nonce", "alice@example.com");
+        unverified.as_object_mut().unwrap().remove("email_verified");
+        let unverified = id_token(unverified);
+        assert!(matches!(config.validate(&metadata, &unverified, "nonce"), Err(OidcError::Domain)));
+    }
+
+    #[test]
+    fn issuer_needs_tls_unless_on_loopback() {
+        assert!(check_secure("https://idp.example.com").is_ok());
+        assert!(matches!(check_secure("http://idp.example.com"), Err(OidcError::Insecure(_))));
+        assert!(check_secure("http://127.0.0.1:8080").is_ok());
+        assert!(check_secure("http://[::1]:8080").is_ok());
+        assert!(check_secure("http://localhost").is_ok());
+    }
+
+    #[test]
+    fn password_login_can_be_disabled() {
+        use rocket::http::ContentType;
+
+        let client = client_with(|config| config.oidc = Some(self::config("https://idp.example.com", false)));
+
+        let page = client.get("/login").dispatch().into_string().unwrap();
+        assert!(page.contains("/login/oidc"));
+        assert!(!page.contains("name=\"password\""));
+        assert_eq!(client.get("/register").dispatch().status(), Status::NotFound);
+        for path in ["/register", "/login"] {
+            let response = client
+                .post(path)
+                .header(ContentType::Form)
+                .body("username=alice&password=correct-horse-battery")
+                .dispatch();
+            assert_eq!(response.status(), Status::Forbidden);
+        }
+    }
+}
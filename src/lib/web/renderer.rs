@@ -20,7 +20,7 @@ impl<'a> Renderer<'a> {
     fn serialize<S>(serializable: &S) -> serde_json::Value
         where S: serde::Serialize + std::fmt::Debug
     {
-        serde_json::to_value(&serializable).expect("failed to serialized struct into value") // should not fail as Serialize is derived almost everywhere
+        serde_json::to_value(serializable).expect("failed to serialized struct into value") // should not fail as Serialize is derived almost everywhere
     }
 
     pub fn render<P>(&self, ctx: P, errors: &[&str]) -> String
@@ -0,0 +1,67 @@
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use std::time::Duration;
+
+use crate::data::Db;
+use crate::domain::user::field::UserId;
+use crate::domain::User;
+use crate::service::action;
+use crate::web::SESSION_COOKIE;
+use crate::{ServiceError, Time};
+
+// logged in user, identified by a session id in an encrypted (private) cookie.
+// the session is kept in the database, so logging out ends it on the server as well
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub user_id: UserId,
+}
+
+impl Session {
+    // users log in again after this long, however active they are
+    pub const LIFETIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    pub async fn start(user: &User, cookies: &CookieJar<'_>, db: &Db) -> Result<(), ServiceError> {
+        let lifetime = Self::LIFETIME.as_secs() as i64;
+        let expires = Time::from_timestamp(chrono::Utc::now().timestamp() + lifetime);
+        let session_id = action::start_session(user.user_id.clone(), expires, db.get_pool()).await?;
+        let mut cookie = Cookie::new(SESSION_COOKIE, session_id);
+        cookie.set_max_age(rocket::time::Duration::seconds(lifetime));
+        cookies.add_private(cookie);
+        Ok(())
+    }
+
+    pub async fn end(cookies: &CookieJar<'_>, db: &Db) {
+        if let Some(cookie) = cookies.get_private(SESSION_COOKIE) {
+            if let Err(e) = action::end_session(cookie.value(), db.get_pool()).await {
+                tracing::error!(error = ?e, "failed to end session");
+            }
+        }
+        cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    }
+}
+
+// forwards when nobody is logged in so that routes can fall back (e.g. redirect to login)
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let session_id = match request.cookies().get_private(SESSION_COOKIE) {
+            Some(cookie) => cookie.value().to_owned(),
+            None => return Outcome::Forward(()),
+        };
+        let db = match request.rocket().state::<Db>() {
+            Some(db) => db,
+            None => return Outcome::Failure((Status::InternalServerError, ())),
+        };
+        // expired or logged out
+        match action::session_user(&session_id, db.get_pool()).await {
+            Ok(user_id) => Outcome::Success(Session { user_id }),
+            Err(ServiceError::NotFound) => Outcome::Forward(()),
+            Err(e) => {
+                tracing::error!(error = ?e, "failed to look up session");
+                Outcome::Failure((Status::InternalServerError, ()))
+            }
+        }
+    }
+}
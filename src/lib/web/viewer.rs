@@ -0,0 +1,48 @@
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::data::Db;
+use crate::domain::stats::{Agent, SaltCache, View, Visitor, VisitorSalt};
+use crate::service::action;
+
+// a view of a clip as it is counted in its stats, taken from the Referer and User-Agent headers,
+// and the visitor it is attributed to
+pub struct Viewer {
+    pub view: View,
+    pub visitor: Option<Visitor>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Viewer {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        let user_agent = headers.get_one("User-Agent");
+        let api = request.uri().path().starts_with("/api/");
+        let agent = Agent::classify(user_agent, api);
+        let visitor = salt(request).await.and_then(|salt| salt.visitor(request.client_ip(), user_agent));
+        Outcome::Success(Viewer {
+            view: View::new(headers.get_one("Referer"), agent),
+            visitor,
+        })
+    }
+}
+
+// the view is still counted when the salt can't be read, only its visitor isn't
+async fn salt(request: &Request<'_>) -> Option<VisitorSalt> {
+    let cache = request.rocket().state::<SaltCache>()?;
+    if let Some(salt) = cache.get(VisitorSalt::today()) {
+        return Some(salt);
+    }
+    let db = request.rocket().state::<Db>()?;
+    match action::visitor_salt(db.get_pool()).await {
+        Ok(salt) => {
+            cache.set(salt.clone());
+            Some(salt)
+        }
+        Err(e) => {
+            tracing::error!(error = ?e, "failed to read the visitor salt");
+            None
+        }
+    }
+}